@@ -0,0 +1,97 @@
+//! Flashcard export, as tab-separated text that Anki can
+//! import. There is one note per node with an "ff" or "fb",
+//! with the front and back as HTML. The header lines tell
+//! Anki which columns hold the GUID and the tags.
+//!
+//! The GUID is a hash of the node name, so importing a new
+//! export updates the notes from the last one instead of
+//! adding them again. Renaming a node makes it a new note.
+
+use crate::dagzet::{namespace, DagZet};
+use crate::text::{escape_html, fnv1a};
+use std::io;
+
+/// The GUID of the note for a node
+pub fn guid(name: &str) -> String {
+    format!("dz{:016x}", fnv1a(name))
+}
+
+/// Lines as a single field. Tabs would start a new column,
+/// so they get escaped along with everything else.
+fn field(lines: &[String]) -> String {
+    let lines: Vec<String> = lines
+        .iter()
+        .map(|line| escape_html(line).replace('\t', "&#9;"))
+        .collect();
+    lines.join("<br>")
+}
+
+/// The node's tags, and its namespace as a hierarchical
+/// tag, so a/b becomes a::b
+fn tags(dz: &DagZet, id: u32, name: &str) -> String {
+    let mut tags: Vec<String> = dz.tags.get(&id).into_iter().flatten().cloned().collect();
+    let ns = namespace(name);
+    if !ns.is_empty() {
+        tags.push(ns.replace(' ', "_").replace('/', "::"));
+    }
+    tags.join(" ")
+}
+
+/// Write every flashcard in the graph, in the order their
+/// nodes were made
+pub fn generate(dz: &DagZet, f: &mut impl io::Write) {
+    let _ = f.write_all(b"#separator:tab\n#html:true\n#guid column:1\n#tags column:4\n");
+
+    for (id, card) in &dz.flashcards {
+        let name = &dz.nodelist[*id as usize - 1];
+        let _ = writeln!(
+            f,
+            "{}\t{}\t{}\t{}",
+            guid(name),
+            field(&card.front),
+            field(&card.back),
+            tags(dz, *id, name)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_generate() {
+        let mut dz = DagZet::new();
+        let code = [
+            "ns a/b",
+            "nn c",
+            "ff what's <this>?",
+            "ff \tsecond line",
+            "fb an answer",
+            "tg z y",
+            "nn d",
+            "ln no card",
+            "nn e",
+            "fb only a back",
+        ];
+        for line in code {
+            dz.parse_line_with_result(line).unwrap();
+        }
+
+        let mut out: Vec<u8> = vec![];
+        generate(&dz, &mut out);
+        let out = String::from_utf8(out).unwrap();
+
+        let expected = format!(
+            concat!(
+                "#separator:tab\n#html:true\n#guid column:1\n#tags column:4\n",
+                "{}\twhat&#39;s &lt;this&gt;?<br>&#9;second line\tan answer\ty z a::b\n",
+                "{}\t\tonly a back\ta::b\n",
+            ),
+            guid("a/b/c"),
+            guid("a/b/e"),
+        );
+        assert_eq!(out, expected);
+        assert_eq!(guid("a/b/c"), "dz9ef3e9bc4337feeb");
+    }
+}
@@ -0,0 +1,76 @@
+//! The dagzet language server, speaking the Language Server
+//! Protocol over stdin and stdout.
+
+use dagzet::jsonrpc::{self, Value};
+use dagzet::lsp::Server;
+use std::io::{self, BufRead, Write};
+use std::process;
+
+/// Read one message, which is a set of headers followed by
+/// a JSON body. None at the end of input.
+fn read_message(reader: &mut impl BufRead) -> Option<String> {
+    let mut length: Option<usize> = None;
+
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().ok();
+            }
+        }
+    }
+
+    let mut body = vec![0; length?];
+    reader.read_exact(&mut body).ok()?;
+    String::from_utf8(body).ok()
+}
+
+fn write_message(out: &mut impl Write, msg: &Value) {
+    let body = msg.encode();
+    let _ = write!(out, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+    let _ = out.flush();
+}
+
+fn main() {
+    let stdin = io::stdin();
+    let mut reader = stdin.lock();
+    let mut out = io::stdout();
+    let mut server = Server::new();
+
+    while let Some(body) = read_message(&mut reader) {
+        let msg = match jsonrpc::parse(&body) {
+            Some(msg) => msg,
+            None => {
+                let error = Value::object(vec![
+                    ("code", Value::Number(-32700.0)),
+                    ("message", Value::string("parse error")),
+                ]);
+                let response = Value::object(vec![
+                    ("jsonrpc", Value::string("2.0")),
+                    ("id", Value::Null),
+                    ("error", error),
+                ]);
+                write_message(&mut out, &response);
+                continue;
+            }
+        };
+
+        for reply in server.handle(&msg) {
+            write_message(&mut out, &reply);
+        }
+
+        if let Some(code) = server.exit {
+            process::exit(code);
+        }
+    }
+
+    // the client went away without asking
+    process::exit(1);
+}
@@ -4,12 +4,14 @@ use dagzet::logzet::session_tree::entities_to_map;
 use dagzet::logzet::session_tree::SessionNode;
 use dagzet::logzet::statement::Statement;
 use dagzet::logzet::statement::StatementBuilder;
-use dagzet::logzet::tables::Schemas;
+use dagzet::logzet::tables::{entity_ids, Schemas};
+use dagzet::sqlite::Output;
 use std::env;
 use std::fs::File;
 use std::io;
 use std::io::prelude::*;
 use std::io::BufReader;
+use std::process;
 
 fn generate_statements<T>(reader: BufReader<T>) -> Vec<Statement>
 where
@@ -22,44 +24,146 @@ where
     builder.build()
 }
 
-fn generate(stmts: Vec<Statement>) -> Vec<SessionRows> {
-    let entities = statements_to_entities(stmts);
+/// Session rows for a single log. Commands that can't be
+/// used are reported, along with the file they were in.
+fn generate(filename: &str, stmts: Vec<Statement>) -> Vec<SessionRows> {
+    let entities = match statements_to_entities(stmts) {
+        Ok(entities) => entities,
+        Err(e) => {
+            eprintln!("{}:{}", filename, e);
+            eprintln!("1 error(s) found, no output generated.");
+            process::exit(1);
+        }
+    };
     let session_map = entities_to_map(&entities.entities);
     let sessions: Vec<SessionNode> = session_map.into_iter().map(|s| s.into()).collect();
     sessions.iter().map(|s| (&entities, s).into()).collect()
 }
 
-fn rows() -> Vec<SessionRows> {
-    let mut stdin = false;
-
-    if env::args().len() < 2 {
-        stdin = true;
-    }
-
-    if stdin {
+fn rows(filenames: &[String]) -> Vec<SessionRows> {
+    if filenames.is_empty() {
         let reader = BufReader::new(io::stdin());
-        return generate(generate_statements(reader));
+        return generate("<stdin>", generate_statements(reader));
     }
 
-    let filenames = env::args().skip(1);
-
     let mut rows: Vec<SessionRows> = vec![];
     for filename in filenames {
         let f = File::open(filename).unwrap();
         let reader = BufReader::new(f);
-        rows.append(&mut generate(generate_statements(reader)));
+        rows.append(&mut generate(filename, generate_statements(reader)));
     }
     rows
 }
 
-fn main() {
-    let mut f = io::stdout();
+fn write(rows: &[SessionRows], literal_ids: bool, search: bool, f: &mut impl Output) {
     let schemas = Schemas::default();
-    schemas.generate(&mut f);
-    let rows = rows();
-    for row in &rows {
-        row.generate(&schemas, &mut f)
+    schemas.generate(f);
+
+    if literal_ids {
+        let ids = entity_ids(rows);
+        for row in rows {
+            row.generate_with_ids(&schemas, &ids, f)
+        }
+    } else {
+        for row in rows {
+            row.generate(&schemas, f)
+        }
+        // HACK: Only generate connections once
+        // rows[0].generate_connections(&schemas, &mut f);
+    }
+
+    if search {
+        schemas.generate_search(f);
+    }
+}
+
+fn usage() -> ! {
+    eprintln!("usage: logzet [--db FILE [--append]] [--literal-ids] [--search] [files...]");
+    process::exit(1);
+}
+
+#[cfg(feature = "database")]
+fn write_database(rows: &[SessionRows], path: &str, append: bool, literal_ids: bool, search: bool) {
+    use dagzet::database::{Database, WriteMode};
+
+    let mode = if append {
+        WriteMode::Append
+    } else {
+        WriteMode::Replace
+    };
+
+    let result = Database::open(path, mode).and_then(|mut db| {
+        write(rows, literal_ids, search, &mut db);
+        db.finish()
+    });
+
+    if let Err(e) = result {
+        eprintln!("could not write to {}: {}", path, e);
+        process::exit(1);
+    }
+}
+
+#[cfg(not(feature = "database"))]
+fn write_database(
+    _rows: &[SessionRows],
+    _path: &str,
+    _append: bool,
+    _literal_ids: bool,
+    _search: bool,
+) {
+    eprintln!("--db needs logzet to be built with the \"database\" feature");
+    process::exit(1);
+}
+
+fn main() {
+    let mut filenames: Vec<String> = vec![];
+    let mut db: Option<String> = None;
+    let mut append = false;
+    let mut literal_ids = false;
+    let mut search = false;
+    let mut args = env::args().skip(1);
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--db" => match args.next() {
+                Some(path) => db = Some(path),
+                None => usage(),
+            },
+            "--append" => append = true,
+            "--literal-ids" => literal_ids = true,
+            "--search" => search = true,
+            _ => filenames.push(arg),
+        }
+    }
+
+    if append && db.is_none() {
+        usage();
+    }
+
+    if literal_ids && append {
+        eprintln!("--literal-ids numbers entities from 1, so it can't be");
+        eprintln!("used with --append");
+        usage();
+    }
+
+    let rows = rows(&filenames);
+
+    if literal_ids {
+        let mut nerrors = 0;
+        for row in &rows {
+            for id in row.unresolved() {
+                eprintln!("session {}: unknown entity {}", row.session.day, id);
+                nerrors += 1;
+            }
+        }
+        if nerrors > 0 {
+            eprintln!("{} error(s) found, no output generated.", nerrors);
+            process::exit(1);
+        }
+    }
+
+    match db {
+        Some(path) => write_database(&rows, &path, append, literal_ids, search),
+        None => write(&rows, literal_ids, search, &mut io::stdout()),
     }
-    // HACK: Only generate connections once
-    // rows[0].generate_connections(&schemas, &mut f);
 }
@@ -0,0 +1,881 @@
+//! Incremental compilation. A cache file remembers what each
+//! input file produced the last time it was compiled, so that
+//! only files that changed need to be parsed again. Instead of
+//! the whole database, a SQL delta is written: rows belonging
+//! to changed or removed files are deleted, and the rows for
+//! the changed files are inserted again.
+//!
+//! Attachments (lines, remarks, tags, etc) are owned by the file
+//! that created the node they belong to. If a file attaches
+//! things to a node from another file (by selecting it with
+//! "sn", for example), that ownership is lost. Files like that
+//! are marked as entangled, and any change made while there is
+//! entanglement falls back to rebuilding everything.
+
+use crate::compiler::{generate_rows, Compiler, Graph};
+use crate::dagzet::{DagZet, DagZetError, ParserState, ReturnCode, SourceRef};
+use crate::parser::Command;
+use crate::sqlite::{SQLize, Table, Value};
+use crate::tables::*;
+use crate::text::fnv1a;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::ops::Range;
+
+const CACHE_HEADER: &str = "dagzet-cache 1";
+
+/// Does a command attach something to the selected node?
+fn attaches(cmd: &Command) -> bool {
+    matches!(
+        cmd,
+        Command::Line(_)
+            | Command::Remark(_)
+            | Command::FileRange { .. }
+            | Command::Hyperlink(_)
+            | Command::Todo(_)
+            | Command::Tags(_)
+            | Command::FlashCardFront(_)
+            | Command::FlashCardBack(_)
+            | Command::Image(_)
+            | Command::Audio(_)
+            | Command::Attribute(_, _)
+            | Command::Equation(_)
+            | Command::Page(_)
+    )
+}
+
+/// A connection as it was made by a file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CachedConnection {
+    /// Connection as it was parsed, which may use ?suffixes
+    pub raw: [String; 2],
+    /// Connection with suffixes resolved
+    pub resolved: [String; 2],
+    pub linum: u32,
+    /// made with "cx"
+    pub external: bool,
+}
+
+/// Everything remembered about one input file
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileRecord {
+    pub filename: String,
+    pub hash: u64,
+    /// parser state before the file was parsed
+    pub entry: ParserState,
+    /// parser state after the file was parsed
+    pub exit: ParserState,
+    /// number of nodes created before this file
+    pub start: usize,
+    pub nodes: Vec<String>,
+    pub connections: Vec<CachedConnection>,
+    /// namespaces given graph remarks
+    pub namespaces: Vec<String>,
+    /// files pulled in with "in", and their hashes
+    pub includes: Vec<(String, u64)>,
+    pub entangled: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cache {
+    pub files: Vec<FileRecord>,
+}
+
+/// The hash of a file on disk, if it can be read
+fn hash_file(filename: &str) -> Option<u64> {
+    fs::read_to_string(filename).ok().map(|data| fnv1a(&data))
+}
+
+fn escape_field(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+fn unescape_field(s: &str) -> String {
+    let mut out = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some(c) => out.push(c),
+            None => {}
+        }
+    }
+    out
+}
+
+fn option_field(s: &Option<String>) -> String {
+    match s {
+        Some(s) => format!("+{}", escape_field(s)),
+        None => "-".to_string(),
+    }
+}
+
+fn parse_option_field(s: &str) -> Option<Option<String>> {
+    match s {
+        "-" => Some(None),
+        _ => s.strip_prefix('+').map(|s| Some(unescape_field(s))),
+    }
+}
+
+fn state_fields(state: &ParserState) -> String {
+    format!(
+        "{}\t{}\t{}",
+        option_field(&state.namespace),
+        option_field(&state.curnode),
+        option_field(&state.last_filename)
+    )
+}
+
+fn parse_state(fields: &[&str]) -> Option<ParserState> {
+    if fields.len() != 3 {
+        return None;
+    }
+    Some(ParserState {
+        namespace: parse_option_field(fields[0])?,
+        curnode: parse_option_field(fields[1])?,
+        last_filename: parse_option_field(fields[2])?,
+    })
+}
+
+impl Cache {
+    /// Load a cache file. A missing file is an empty cache.
+    /// A cache that can't be understood is also treated as
+    /// empty, which means everything gets rebuilt.
+    pub fn load(path: &str) -> io::Result<Cache> {
+        match fs::read_to_string(path) {
+            Ok(data) => Ok(Cache::parse(&data).unwrap_or_default()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Cache::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.serialize())
+    }
+
+    pub fn serialize(&self) -> String {
+        let mut out = format!("{}\n", CACHE_HEADER);
+
+        for rec in &self.files {
+            out.push_str(&format!("file\t{}\n", escape_field(&rec.filename)));
+            out.push_str(&format!("hash\t{:016x}\n", rec.hash));
+            out.push_str(&format!("start\t{}\n", rec.start));
+            out.push_str(&format!("entry\t{}\n", state_fields(&rec.entry)));
+            out.push_str(&format!("exit\t{}\n", state_fields(&rec.exit)));
+            for node in &rec.nodes {
+                out.push_str(&format!("nn\t{}\n", escape_field(node)));
+            }
+            for co in &rec.connections {
+                out.push_str(&format!(
+                    "co\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                    escape_field(&co.raw[0]),
+                    escape_field(&co.raw[1]),
+                    escape_field(&co.resolved[0]),
+                    escape_field(&co.resolved[1]),
+                    co.linum,
+                    if co.external { "x" } else { "-" }
+                ));
+            }
+            for ns in &rec.namespaces {
+                out.push_str(&format!("gr\t{}\n", escape_field(ns)));
+            }
+            for (filename, hash) in &rec.includes {
+                out.push_str(&format!("in\t{}\t{:016x}\n", escape_field(filename), hash));
+            }
+            if rec.entangled {
+                out.push_str("entangled\n");
+            }
+        }
+
+        out
+    }
+
+    pub fn parse(data: &str) -> Option<Cache> {
+        let mut lines = data.lines();
+
+        if lines.next()? != CACHE_HEADER {
+            return None;
+        }
+
+        let mut cache = Cache::default();
+
+        for line in lines {
+            let fields: Vec<&str> = line.split('\t').collect();
+
+            if fields[0] == "file" {
+                cache.files.push(FileRecord {
+                    filename: unescape_field(fields.get(1)?),
+                    ..Default::default()
+                });
+                continue;
+            }
+
+            let rec = cache.files.last_mut()?;
+
+            match (fields[0], fields.len()) {
+                ("hash", 2) => rec.hash = u64::from_str_radix(fields[1], 16).ok()?,
+                ("start", 2) => rec.start = fields[1].parse().ok()?,
+                ("entry", _) => rec.entry = parse_state(&fields[1..])?,
+                ("exit", _) => rec.exit = parse_state(&fields[1..])?,
+                ("nn", 2) => rec.nodes.push(unescape_field(fields[1])),
+                ("co", 7) => rec.connections.push(CachedConnection {
+                    raw: [unescape_field(fields[1]), unescape_field(fields[2])],
+                    resolved: [unescape_field(fields[3]), unescape_field(fields[4])],
+                    linum: fields[5].parse().ok()?,
+                    external: fields[6] == "x",
+                }),
+                ("gr", 2) => rec.namespaces.push(unescape_field(fields[1])),
+                ("in", 3) => rec.includes.push((
+                    unescape_field(fields[1]),
+                    u64::from_str_radix(fields[2], 16).ok()?,
+                )),
+                ("entangled", 1) => rec.entangled = true,
+                _ => return None,
+            }
+        }
+
+        Some(cache)
+    }
+
+    fn get(&self, filename: &str) -> Option<&FileRecord> {
+        self.files.iter().find(|rec| rec.filename == filename)
+    }
+}
+
+/// What happened to a single file during a build
+struct FileInfo {
+    record: FileRecord,
+    dirty: bool,
+    /// 0-indexed range into the nodelist
+    nodes: Range<usize>,
+    connections: Range<usize>,
+    aliases: Range<usize>,
+}
+
+struct Build {
+    graph: Graph,
+    files: Vec<FileInfo>,
+}
+
+impl Build {
+    fn any_dirty(&self) -> bool {
+        self.files.iter().any(|f| f.dirty)
+    }
+
+    /// Is it unsafe to only write the changes?
+    fn needs_rebuild(&self) -> bool {
+        if !self.any_dirty() {
+            return false;
+        }
+
+        if self.files.iter().any(|f| f.record.entangled) {
+            return true;
+        }
+
+        // clean files whose suffixes now resolve to
+        // something else have stale connections
+        self.files.iter().filter(|f| !f.dirty).any(|f| {
+            f.record
+                .connections
+                .iter()
+                .zip(&self.graph.dz.connections[f.connections.clone()])
+                .any(|(old, new)| &old.resolved != new)
+        })
+    }
+}
+
+/// Parse the sources, skipping any that the cache says are
+/// unchanged. With no cache, everything is parsed.
+fn parse_sources(
+    sources: &[(String, String)],
+    cache: Option<&Cache>,
+) -> Result<Build, Vec<DagZetError>> {
+    let mut compiler = Compiler::new();
+    let mut files = vec![];
+
+    for (filename, data) in sources {
+        let dz = &compiler.dz;
+        let entry = dz.parser_state();
+        let start = dz.nodelist.len();
+        let cstart = dz.connections.len();
+        let astart = dz.alias_list.len();
+        let fstart = dz.files.len();
+        // FNV-1a is plenty for noticing that a file changed
+        let hash = fnv1a(data);
+
+        let cached = cache
+            .and_then(|c| c.get(filename))
+            .filter(|rec| rec.hash == hash && rec.entry == entry)
+            .filter(|rec| {
+                rec.includes
+                    .iter()
+                    .all(|(filename, hash)| hash_file(filename) == Some(*hash))
+            });
+
+        let dirty = cached.is_none();
+
+        let record = match cached {
+            Some(rec) => {
+                let dz = &mut compiler.dz;
+                dz.begin_file(filename);
+                for node in &rec.nodes {
+                    dz.add_node(node);
+                }
+                for co in &rec.connections {
+                    let sref = SourceRef {
+                        linum: co.linum,
+                        ..dz.source_ref()
+                    };
+                    dz.add_connection(&co.raw[0], &co.raw[1], sref, co.external);
+                }
+                dz.set_parser_state(&rec.exit);
+                FileRecord {
+                    start,
+                    ..rec.clone()
+                }
+            }
+            None => {
+                let mut namespaces = vec![];
+                let mut external = HashSet::new();
+                let mut entangled = false;
+
+                let mut inspect = |dz: &DagZet, cmd: &Command| {
+                    if attaches(cmd) {
+                        let outside = match dz.curnode {
+                            Some(id) => (id as usize) <= start,
+                            None => false,
+                        };
+                        entangled |= outside;
+                    }
+
+                    match cmd {
+                        Command::ConnectionRemark(_) => entangled |= dz.connections.len() == cstart,
+                        Command::ConnectExternal(_, _) => {
+                            external.insert(dz.connections.len() - 1);
+                        }
+                        Command::GraphRemark(_) => {
+                            if let Some(ns) = &dz.namespace {
+                                if !namespaces.contains(ns) {
+                                    namespaces.push(ns.clone());
+                                }
+                            }
+                        }
+                        _ => {}
+                    }
+                };
+
+                compiler.parse_source(filename, data.as_bytes(), &mut inspect);
+                let dz = &compiler.dz;
+
+                let connections = (cstart..dz.connections.len())
+                    .map(|idx| CachedConnection {
+                        raw: dz.connections[idx].clone(),
+                        resolved: dz.connections[idx].clone(),
+                        linum: dz.connection_refs[idx].linum,
+                        external: external.contains(&idx),
+                    })
+                    .collect();
+
+                let mut includes: Vec<(String, u64)> = vec![];
+                for included in &dz.files[fstart + 1..] {
+                    if includes.iter().any(|(f, _)| f == included) {
+                        continue;
+                    }
+                    if let Some(hash) = hash_file(included) {
+                        includes.push((included.clone(), hash));
+                    }
+                }
+
+                FileRecord {
+                    filename: filename.clone(),
+                    hash,
+                    entry,
+                    exit: dz.parser_state(),
+                    start,
+                    nodes: dz.nodelist[start..].to_vec(),
+                    connections,
+                    namespaces,
+                    includes,
+                    entangled,
+                }
+            }
+        };
+
+        let dz = &compiler.dz;
+        files.push(FileInfo {
+            record,
+            dirty,
+            nodes: start..dz.nodelist.len(),
+            connections: cstart..dz.connections.len(),
+            aliases: astart..dz.alias_list.len(),
+        });
+    }
+
+    let graph = compiler.build()?;
+
+    // graph remarks for the same namespace in more than one file
+    let mut owners: HashMap<&String, usize> = HashMap::new();
+    let mut shared = HashSet::new();
+    for (idx, file) in files.iter().enumerate() {
+        for ns in &file.record.namespaces {
+            if let Some(owner) = owners.insert(ns, idx) {
+                shared.insert(owner);
+                shared.insert(idx);
+            }
+        }
+    }
+    for idx in shared {
+        files[idx].record.entangled = true;
+    }
+
+    Ok(Build { graph, files })
+}
+
+fn text(s: &str) -> String {
+    Value::Text(s.to_string()).sqlize()
+}
+
+fn quoted_list<'a>(names: impl Iterator<Item = &'a String>) -> String {
+    names.map(|n| text(n)).collect::<Vec<_>>().join(", ")
+}
+
+/// Write a DELETE statement, making sure the table exists first
+fn delete_from<T>(table: &Table<T>, cond: Option<&str>, f: &mut impl io::Write)
+where
+    Table<T>: SQLize,
+{
+    let _ = f.write_all(&table.sqlize().into_bytes());
+    let sql = match cond {
+        Some(cond) => format!("DELETE FROM {} WHERE {};\n", table.name(), cond),
+        None => format!("DELETE FROM {};\n", table.name()),
+    };
+    let _ = f.write_all(&sql.into_bytes());
+}
+
+/// Delete rows from every table keyed by node
+fn delete_node_rows(cond: Option<&str>, f: &mut impl io::Write) {
+    delete_from(&Table::<LinesTable>::default(), cond, f);
+    delete_from(&Table::<NodeRemarksTable>::default(), cond, f);
+    delete_from(&Table::<FileRangesTable>::default(), cond, f);
+    delete_from(&Table::<HyperlinksTable>::default(), cond, f);
+    delete_from(&Table::<TODOTable>::default(), cond, f);
+    delete_from(&Table::<TagsTable>::default(), cond, f);
+    delete_from(&Table::<FlashCardsTable>::default(), cond, f);
+    delete_from(&Table::<ImagesTable>::default(), cond, f);
+    delete_from(&Table::<AudioTable>::default(), cond, f);
+    delete_from(&Table::<EquationsTable>::default(), cond, f);
+    delete_from(&Table::<PagesTable>::default(), cond, f);
+    delete_from(&Table::<AttributesTable>::default(), cond, f);
+    delete_from(&Table::<NodeRefsTable>::default(), cond, f);
+    delete_from(&Table::<LineRefsTable>::default(), cond, f);
+    delete_from(&Table::<NodeRemarkRefsTable>::default(), cond, f);
+    delete_from(&Table::<TagRefsTable>::default(), cond, f);
+    delete_from(&Table::<AttributeRefsTable>::default(), cond, f);
+}
+
+/// Empty every table, then write the whole graph
+fn write_rebuild(build: &Build, f: &mut impl io::Write) {
+    let dz = &build.graph.dz;
+
+    let _ = f.write_all(b"BEGIN;\n");
+
+    delete_node_rows(None, f);
+    delete_from(&Table::<ConnectionsTable>::default(), None, f);
+    delete_from(&Table::<ConnectionRemarksTable>::default(), None, f);
+    delete_from(&Table::<ConnectionRefsTable>::default(), None, f);
+    delete_from(&Table::<ConnectionRemarkRefsTable>::default(), None, f);
+    delete_from(&Table::<GraphRemarksTable>::default(), None, f);
+    delete_from(&Table::<AliasesTable>::default(), None, f);
+    delete_from(&Table::<NodesTable>::default(), None, f);
+
+    let nodes: Table<NodesTable> = Table::default();
+    nodes.generate(dz, f);
+
+    generate_rows(dz, f);
+    build.graph.generate_noderefs(0..dz.nodelist.len(), f);
+    generate_views(f);
+
+    let _ = f.write_all(b"COMMIT;\n");
+}
+
+fn owned<V: Clone>(map: &BTreeMap<u32, V>, ids: &Range<u32>) -> BTreeMap<u32, V> {
+    map.range(ids.clone())
+        .map(|(id, v)| (*id, v.clone()))
+        .collect()
+}
+
+/// A graph containing only what a single file produced, with
+/// the full node list so that node IDs still line up.
+fn file_view(dz: &DagZet, file: &FileInfo) -> DagZet {
+    let ids = (file.nodes.start as u32 + 1)..(file.nodes.end as u32 + 1);
+    let cstart = file.connections.start;
+
+    let connection_remarks = dz
+        .connection_remarks
+        .iter()
+        .filter(|(idx, _)| file.connections.contains(idx))
+        .map(|(idx, rm)| (idx - cstart, rm.clone()))
+        .collect();
+
+    let connection_remark_refs = dz
+        .connection_remark_refs
+        .iter()
+        .filter(|(idx, _)| file.connections.contains(idx))
+        .map(|(idx, refs)| (idx - cstart, refs.clone()))
+        .collect();
+
+    let graph_remarks = dz
+        .graph_remarks
+        .iter()
+        .filter(|(ns, _)| file.record.namespaces.contains(ns))
+        .map(|(ns, rm)| (ns.clone(), rm.clone()))
+        .collect();
+
+    let mut view = DagZet::new();
+    view.nodelist = dz.nodelist.clone();
+    view.files = dz.files.clone();
+    view.lines = owned(&dz.lines, &ids);
+    view.line_refs = owned(&dz.line_refs, &ids);
+    view.connections = dz.connections[file.connections.clone()].to_vec();
+    view.connection_refs = dz.connection_refs[file.connections.clone()].to_vec();
+    view.connection_remarks = connection_remarks;
+    view.connection_remark_refs = connection_remark_refs;
+    view.graph_remarks = graph_remarks;
+    view.node_remarks = owned(&dz.node_remarks, &ids);
+    view.node_remark_refs = owned(&dz.node_remark_refs, &ids);
+    view.file_ranges = owned(&dz.file_ranges, &ids);
+    view.hyperlinks = owned(&dz.hyperlinks, &ids);
+    view.todos = owned(&dz.todos, &ids);
+    view.tags = owned(&dz.tags, &ids);
+    view.tag_refs = owned(&dz.tag_refs, &ids);
+    view.flashcards = owned(&dz.flashcards, &ids);
+    view.images = owned(&dz.images, &ids);
+    view.audio = owned(&dz.audio, &ids);
+    view.equations = owned(&dz.equations, &ids);
+    view.pages = owned(&dz.pages, &ids);
+    view.attr = owned(&dz.attr, &ids);
+    view.attr_refs = owned(&dz.attr_refs, &ids);
+    view.noderefs = owned(&dz.noderefs, &ids);
+    view.alias_list = dz.alias_list[file.aliases.clone()].to_vec();
+    view
+}
+
+/// Delete what changed or removed files produced last time,
+/// then insert what the changed files produce now.
+fn write_delta(build: &Build, cache: &Cache, f: &mut impl io::Write) {
+    let dz = &build.graph.dz;
+    let dirty: HashSet<&String> = build
+        .files
+        .iter()
+        .filter(|file| file.dirty)
+        .map(|file| &file.record.filename)
+        .collect();
+    let current: HashSet<&String> = build
+        .files
+        .iter()
+        .map(|file| &file.record.filename)
+        .collect();
+
+    let _ = f.write_all(b"BEGIN;\n");
+
+    let nodes: Table<NodesTable> = Table::default();
+    let _ = f.write_all(&nodes.sqlize().into_bytes());
+
+    let stale: Vec<&FileRecord> = cache
+        .files
+        .iter()
+        .filter(|rec| dirty.contains(&rec.filename) || !current.contains(&rec.filename))
+        .collect();
+
+    for rec in &stale {
+        if !rec.nodes.is_empty() {
+            let cond = format!(
+                "node IN (SELECT id FROM dz_nodes WHERE name IN ({}))",
+                quoted_list(rec.nodes.iter())
+            );
+            delete_node_rows(Some(&cond), f);
+        }
+
+        for co in &rec.connections {
+            let cond = format!(
+                "left IS {} AND right IS {}",
+                node(&co.resolved[0]).sqlize(),
+                node(&co.resolved[1]).sqlize()
+            );
+            delete_from(&Table::<ConnectionsTable>::default(), Some(&cond), f);
+            delete_from(&Table::<ConnectionRemarksTable>::default(), Some(&cond), f);
+            delete_from(&Table::<ConnectionRefsTable>::default(), Some(&cond), f);
+            delete_from(
+                &Table::<ConnectionRemarkRefsTable>::default(),
+                Some(&cond),
+                f,
+            );
+        }
+
+        if !rec.namespaces.is_empty() {
+            let cond = format!("namespace IN ({})", quoted_list(rec.namespaces.iter()));
+            delete_from(&Table::<GraphRemarksTable>::default(), Some(&cond), f);
+        }
+
+        let cond = format!("filename IS {}", text(&rec.filename));
+        delete_from(&Table::<AliasesTable>::default(), Some(&cond), f);
+    }
+
+    let removed: Vec<&String> = stale
+        .iter()
+        .flat_map(|rec| &rec.nodes)
+        .filter(|name| !dz.nodes.contains_key(*name))
+        .collect();
+
+    if !removed.is_empty() {
+        let cond = format!("name IN ({})", quoted_list(removed.into_iter()));
+        delete_from(&nodes, Some(&cond), f);
+    }
+
+    // new nodes, and nodes that have moved
+    for file in &build.files {
+        let old_start = cache
+            .get(&file.record.filename)
+            .map(|rec| rec.start)
+            .unwrap_or(usize::MAX);
+
+        if file.dirty || old_start != file.nodes.start {
+            nodes.generate_upserts(dz, f, file.nodes.start + 1, file.nodes.end + 1);
+        }
+    }
+
+    for file in build.files.iter().filter(|file| file.dirty) {
+        generate_rows(&file_view(dz, file), f);
+        build.graph.generate_noderefs(file.nodes.clone(), f);
+    }
+
+    let _ = f.write_all(b"COMMIT;\n");
+}
+
+/// Compile in-memory sources (filename, contents), writing
+/// only what changed since the cache was made. The cache
+/// that should be saved for the next run is returned.
+pub fn compile_sources(
+    sources: &[(String, String)],
+    cache: &Cache,
+    f: &mut impl io::Write,
+) -> Result<Cache, Vec<DagZetError>> {
+    let build = if cache.files.is_empty() {
+        None
+    } else {
+        Some(parse_sources(sources, Some(cache))?).filter(|b| !b.needs_rebuild())
+    };
+
+    let build = match build {
+        Some(build) => {
+            if build.any_dirty() || cache.files.len() != build.files.len() {
+                write_delta(&build, cache, f);
+            }
+            build
+        }
+        None => {
+            let build = parse_sources(sources, None)?;
+            write_rebuild(&build, f);
+            build
+        }
+    };
+
+    // remember how suffixes were resolved, so that stale
+    // connections can be deleted next time
+    let mut files = vec![];
+    for file in build.files {
+        let mut record = file.record;
+        let resolved = &build.graph.dz.connections[file.connections];
+        for (co, resolved) in record.connections.iter_mut().zip(resolved) {
+            co.resolved = resolved.clone();
+        }
+        files.push(record);
+    }
+
+    Ok(Cache { files })
+}
+
+/// Compile dagzet files using a cache file, which is updated
+/// once the SQL has been written.
+pub fn compile_incremental(
+    filenames: &[String],
+    cache_path: &str,
+    f: &mut impl io::Write,
+) -> Result<(), Vec<DagZetError>> {
+    let mut sources = vec![];
+    let mut errors = vec![];
+
+    for filename in filenames {
+        match fs::read_to_string(filename) {
+            Ok(data) => sources.push((filename.clone(), data)),
+            Err(e) => errors.push(DagZetError {
+                filename: Some(filename.clone()),
+                linum: 0,
+                col: 0,
+                command: None,
+                reason: ReturnCode::CouldNotRead(e.to_string()),
+                notes: vec![],
+            }),
+        }
+    }
+
+    let cache = match Cache::load(cache_path) {
+        Ok(cache) => cache,
+        Err(e) => {
+            errors.push(DagZetError {
+                filename: Some(cache_path.to_string()),
+                linum: 0,
+                col: 0,
+                command: None,
+                reason: ReturnCode::CouldNotRead(e.to_string()),
+                notes: vec![],
+            });
+            Cache::default()
+        }
+    };
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let cache = compile_sources(&sources, &cache, f)?;
+
+    if let Err(e) = cache.save(cache_path) {
+        return Err(vec![DagZetError {
+            filename: Some(cache_path.to_string()),
+            linum: 0,
+            col: 0,
+            command: None,
+            reason: ReturnCode::CouldNotWrite(e.to_string()),
+            notes: vec![],
+        }]);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(files: &[(&str, &str)]) -> Vec<(String, String)> {
+        files
+            .iter()
+            .map(|(name, data)| (name.to_string(), data.to_string()))
+            .collect()
+    }
+
+    fn run(files: &[(&str, &str)], cache: &Cache) -> (String, Cache) {
+        let mut sql: Vec<u8> = vec![];
+        let cache = compile_sources(&sources(files), cache, &mut sql).unwrap();
+        (String::from_utf8(sql).unwrap(), cache)
+    }
+
+    #[test]
+    fn test_cache_round_trip() {
+        let (_, cache) = run(
+            &[
+                ("a.dz", "ns a\ngr about a\nnn b\nnn c\nco b c\n"),
+                ("b.dz", "ns d\nnn e\tf\ncx d/e x/y\n"),
+            ],
+            &Cache::default(),
+        );
+
+        assert_eq!(cache.files.len(), 2);
+        assert_eq!(cache.files[0].nodes, ["a/b", "a/c"]);
+        assert_eq!(cache.files[0].namespaces, ["a"]);
+        assert!(cache.files[1].connections[0].external);
+        assert_eq!(Cache::parse(&cache.serialize()), Some(cache));
+    }
+
+    #[test]
+    fn test_delta_only_changed_file() {
+        let a = ("a.dz", "ns a\nnn b\nln hello\nnn c\nco b c\n");
+        let b = ("b.dz", "ns d\nnn e\nln one\n");
+
+        let (sql, cache) = run(&[a, b], &Cache::default());
+        assert!(sql.contains("DELETE FROM dz_nodes;"));
+
+        // nothing changed, nothing written
+        let (sql, cache) = run(&[a, b], &cache);
+        assert!(sql.is_empty());
+
+        let b = ("b.dz", "ns d\nnn f\nln two\nco f ?b\n");
+        let (sql, cache) = run(&[a, b], &cache);
+
+        assert!(!sql.contains("'a/b', 1"));
+        assert!(!sql.contains("hello"));
+        assert!(sql.contains("DELETE FROM dz_nodes WHERE name IN ('d/e');"));
+        assert!(sql.contains(
+            "DELETE FROM dz_lines WHERE node IN (SELECT id FROM dz_nodes WHERE name IN ('d/e'));"
+        ));
+        assert!(sql.contains("ON CONFLICT(name) DO UPDATE SET position = excluded.position;"));
+        assert!(sql.contains("[\"two\"]"));
+        assert_eq!(cache.files[1].connections[0].resolved, ["d/f", "a/b"]);
+    }
+
+    #[test]
+    fn test_removed_file() {
+        let a = ("a.dz", "ns a\nnn b\n");
+        let b = ("b.dz", "ns d\nnn e\nco e ?b\n");
+
+        let (_, cache) = run(&[a, b], &Cache::default());
+        let (sql, cache) = run(&[a], &cache);
+
+        assert!(sql.contains("DELETE FROM dz_nodes WHERE name IN ('d/e');"));
+        assert!(sql.contains("DELETE FROM dz_connections WHERE left IS"));
+        assert_eq!(cache.files.len(), 1);
+    }
+
+    #[test]
+    fn test_changed_include() {
+        let dir = std::env::temp_dir().join(format!("dagzet-cache-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let top = dir.join("top.dz");
+        let top = top.to_str().unwrap();
+        let inc = dir.join("inc.dz");
+        fs::write(&inc, "ns x\nnn y\nln one\n").unwrap();
+
+        let a = (top, "ns a\nnn b\nin inc.dz\ncx a/b x/y\n");
+        let c = ("c.dz", "ns c\nnn d\n");
+
+        let (_, cache) = run(&[a, c], &Cache::default());
+        assert_eq!(cache.files[0].nodes, ["a/b", "x/y"]);
+        assert_eq!(cache.files[0].includes.len(), 1);
+        assert_eq!(Cache::parse(&cache.serialize()).as_ref(), Some(&cache));
+
+        let (sql, cache) = run(&[a, c], &cache);
+        assert!(sql.is_empty());
+
+        fs::write(&inc, "ns x\nnn y\nln two\n").unwrap();
+        let (sql, _) = run(&[a, c], &cache);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(!sql.contains("DELETE FROM dz_lines;"));
+        assert!(sql.contains("[\"two\"]"));
+        assert!(sql.contains("inc.dz', 2"));
+        assert!(!sql.contains("'c/d'"));
+    }
+
+    #[test]
+    fn test_entangled_rebuild() {
+        let a = ("a.dz", "ns a\nnn b\n");
+        let b = ("b.dz", "sn b\nln from b\n");
+
+        let (_, cache) = run(&[a, b], &Cache::default());
+        assert!(cache.files[1].entangled);
+
+        let a = ("a.dz", "ns a\nnn b\nnn c\n");
+        let (sql, _) = run(&[a, b], &cache);
+        assert!(sql.contains("DELETE FROM dz_lines;"));
+        assert!(sql.contains("from b"));
+    }
+}
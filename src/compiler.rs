@@ -0,0 +1,607 @@
+use crate::anki;
+use crate::dagzet::{DagZet, DagZetError, ReturnCode};
+use crate::dot::{self, DotOptions};
+use crate::html;
+use crate::json;
+use crate::parser::{self, Command, Span, Statement};
+use crate::sqlite::{Output, Table, WithIds};
+use crate::tables::*;
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// A range of node IDs that were created by a single input.
+/// Includes split the including file into several ranges.
+struct FileMapper {
+    filename: Option<String>,
+    start: usize,
+    end: usize,
+}
+
+/// Parses one or more dagzet inputs into a single graph.
+/// Errors are collected rather than returned immediately,
+/// so that every problem in every input can be reported.
+#[derive(Default)]
+pub struct Compiler {
+    pub(crate) dz: DagZet,
+    file_mappings: Vec<FileMapper>,
+    errors: Vec<DagZetError>,
+    /// Files being parsed, outermost first, as (canonical
+    /// path, filename) pairs. Used to catch include cycles.
+    parsing: Vec<(PathBuf, String)>,
+}
+
+/// Options for writing a graph as SQLite code
+#[derive(Default)]
+pub struct SqlOptions {
+    /// Refer to nodes by literal ID instead of looking each
+    /// one up by name. Connections to nodes outside the graph
+    /// can't be given an ID, so they are reported as errors.
+    pub literal_ids: bool,
+    /// Add the dz_search full-text search table
+    pub search: bool,
+}
+
+/// A graph that has been parsed and validated, ready
+/// to be written out.
+pub struct Graph {
+    pub dz: DagZet,
+    file_mappings: Vec<FileMapper>,
+}
+
+impl Compiler {
+    pub fn new() -> Self {
+        Compiler::default()
+    }
+
+    /// Parse dagzet code from a reader. The filename is used in
+    /// error messages and in the noderefs table, and included
+    /// files are found relative to it.
+    pub fn parse_reader(&mut self, filename: Option<&str>, reader: impl BufRead) {
+        self.parse_reader_with(filename, reader, &mut |_, _| {});
+    }
+
+    /// Like parse_reader, but after each command is applied
+    /// without error, the inspect callback is given the graph
+    /// and that command. Commands in included files are
+    /// inspected too.
+    pub(crate) fn parse_reader_with(
+        &mut self,
+        filename: Option<&str>,
+        reader: impl BufRead,
+        inspect: &mut impl FnMut(&DagZet, &Command),
+    ) {
+        let mut start = self.dz.nodelist.len();
+
+        if let Some(filename) = filename {
+            self.dz.begin_file(filename);
+        }
+
+        for (linum, line) in (1..).zip(reader.lines()) {
+            let dz = &mut self.dz;
+            dz.linum = linum;
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    let err =
+                        dz.error_at(&dz.source_ref(), ReturnCode::CouldNotRead(e.to_string()));
+                    self.errors.push(err);
+                    break;
+                }
+            };
+
+            let result = match parser::parse_line(&line, linum) {
+                Ok(Some(Statement {
+                    command: Command::Include(path),
+                    args_span,
+                    ..
+                })) => {
+                    self.map_nodes(filename, start);
+                    self.include(filename, &path, &line, args_span, inspect);
+                    start = self.dz.nodelist.len();
+                    continue;
+                }
+                Ok(Some(stmt)) => dz
+                    .eval(&stmt.command)
+                    .map(|_| inspect(dz, &stmt.command))
+                    .map_err(|rc| (stmt.args_span, rc)),
+                Ok(None) => Ok(()),
+                Err(e) => Err((e.span, e.reason)),
+            };
+
+            if let Err((span, rc)) = result {
+                self.errors.push(dz.error(&line, span, rc));
+            }
+        }
+
+        self.map_nodes(filename, start);
+    }
+
+    pub fn parse_file(&mut self, filename: &str) {
+        match File::open(filename) {
+            Ok(f) => self.parse_source(filename, BufReader::new(f), &mut |_, _| {}),
+            Err(e) => {
+                self.dz.begin_file(filename);
+                let dz = &self.dz;
+                let err = dz.error_at(&dz.source_ref(), ReturnCode::CouldNotRead(e.to_string()));
+                self.errors.push(err);
+            }
+        }
+    }
+
+    /// Parse the contents of a named file, which may include
+    /// other files
+    pub(crate) fn parse_source(
+        &mut self,
+        filename: &str,
+        reader: impl BufRead,
+        inspect: &mut impl FnMut(&DagZet, &Command),
+    ) {
+        let path = fs::canonicalize(filename).unwrap_or_else(|_| PathBuf::from(filename));
+        self.parsing.push((path, filename.to_string()));
+        self.parse_reader_with(Some(filename), reader, inspect);
+        self.parsing.pop();
+    }
+
+    /// Parse a file included by the "in" command on the
+    /// current line. The path is relative to the including
+    /// file, which carries on with the namespace and selected
+    /// node it had before.
+    fn include(
+        &mut self,
+        from: Option<&str>,
+        path: &str,
+        line: &str,
+        span: Span,
+        inspect: &mut impl FnMut(&DagZet, &Command),
+    ) {
+        let dir = from.and_then(|f| Path::new(f).parent());
+        let filename = match dir {
+            Some(dir) => dir.join(path).to_string_lossy().into_owned(),
+            None => path.to_string(),
+        };
+
+        let f = match File::open(&filename) {
+            Ok(f) => f,
+            Err(e) => {
+                let reason = ReturnCode::CouldNotRead(format!("{}: {}", filename, e));
+                self.errors.push(self.dz.error(line, span, reason));
+                return;
+            }
+        };
+
+        let canonical = fs::canonicalize(&filename).unwrap_or_else(|_| PathBuf::from(&filename));
+        if let Some(pos) = self.parsing.iter().position(|(p, _)| *p == canonical) {
+            let mut files: Vec<String> =
+                self.parsing[pos..].iter().map(|(_, f)| f.clone()).collect();
+            files.push(filename);
+            self.errors
+                .push(self.dz.error(line, span, ReturnCode::IncludeCycle(files)));
+            return;
+        }
+
+        let suspended = self.dz.suspend_file();
+        self.parse_source(&filename, BufReader::new(f), inspect);
+        self.dz.resume_file(suspended);
+    }
+
+    /// Note that the nodes made since start came from a file
+    fn map_nodes(&mut self, filename: Option<&str>, start: usize) {
+        let end = self.dz.nodelist.len();
+        if end > start {
+            self.file_mappings.push(FileMapper {
+                filename: filename.map(String::from),
+                start,
+                end,
+            });
+        }
+    }
+
+    /// Validate everything that has been parsed so far. On
+    /// failure, every error found while parsing and validating
+    /// is returned.
+    pub fn build(mut self) -> Result<Graph, Vec<DagZetError>> {
+        if let Err(mut errors) = self.dz.validate() {
+            self.errors.append(&mut errors);
+        }
+
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+
+        Ok(Graph {
+            dz: self.dz,
+            file_mappings: self.file_mappings,
+        })
+    }
+}
+
+impl Graph {
+    /// Write the graph as SQLite code
+    pub fn generate_sql(&self, f: &mut impl Output) {
+        let dz = &self.dz;
+
+        f.execute("BEGIN;\n");
+        let nodes: Table<NodesTable> = Table::default();
+        nodes.generate(dz, f);
+        f.execute("COMMIT;\n");
+
+        self.generate_sql_rows(f);
+    }
+
+    /// Write the graph as SQLite code, referring to nodes by
+    /// literal ID instead of looking each one up by name.
+    /// Connections to nodes outside the graph can't be given
+    /// an ID, so they are reported as errors and nothing is
+    /// written.
+    fn generate_sql_with_ids(&self, f: &mut impl Output) -> Result<(), Vec<DagZetError>> {
+        let dz = &self.dz;
+
+        let errors = dz.unresolved_connections();
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        f.execute("BEGIN;\n");
+        let mut nodes: Table<NodesTable> = Table::default();
+        nodes.insert_keys();
+        nodes.generate(dz, f);
+        f.execute("COMMIT;\n");
+
+        let ids = node_ids(dz);
+        self.generate_sql_rows(&mut WithIds::new(f, &ids));
+
+        Ok(())
+    }
+
+    /// Write the graph as SQLite code, with a set of options
+    pub fn generate_sql_with(
+        &self,
+        options: &SqlOptions,
+        f: &mut impl Output,
+    ) -> Result<(), Vec<DagZetError>> {
+        if options.literal_ids {
+            self.generate_sql_with_ids(f)?;
+        } else {
+            self.generate_sql(f);
+        }
+
+        if options.search {
+            f.execute("BEGIN;\n");
+            generate_search(f);
+            f.execute("COMMIT;\n");
+        }
+
+        Ok(())
+    }
+
+    /// Everything after the nodes, in a single transaction
+    fn generate_sql_rows(&self, f: &mut impl Output) {
+        let dz = &self.dz;
+
+        f.execute("BEGIN;\n");
+
+        generate_rows(dz, f);
+        self.generate_noderefs(0..dz.nodelist.len(), f);
+        generate_views(f);
+
+        f.execute("COMMIT;\n");
+    }
+
+    /// Write where the nodes in a range (0-indexed into the
+    /// nodelist) were made
+    pub(crate) fn generate_noderefs(&self, nodes: Range<usize>, f: &mut impl Output) {
+        let noderefs: Table<NodeRefsTable> = Table::default();
+        let mappings = self
+            .file_mappings
+            .iter()
+            .filter(|m| m.start >= nodes.start && m.end <= nodes.end);
+        for mapping in mappings {
+            if let Some(filename) = &mapping.filename {
+                noderefs.generate_with_filename(
+                    &self.dz,
+                    f,
+                    Some(filename),
+                    mapping.start + 1,
+                    mapping.end + 1,
+                );
+            }
+        }
+    }
+
+    /// Write the graph as JSON
+    pub fn generate_json(&self, f: &mut impl io::Write) {
+        json::generate(&self.dz, |id| self.node_filename(id), f);
+    }
+
+    /// Write the flashcards as text for Anki to import
+    pub fn generate_anki(&self, f: &mut impl io::Write) {
+        anki::generate(&self.dz, f);
+    }
+
+    /// The graph as a static HTML site, as pages keyed by
+    /// their path in the output directory
+    pub fn generate_html(&self) -> BTreeMap<String, String> {
+        html::generate(&self.dz, |id| self.node_filename(id))
+    }
+
+    /// Write the graph in Graphviz's DOT language
+    pub fn generate_dot(
+        &self,
+        opts: &DotOptions,
+        f: &mut impl io::Write,
+    ) -> Result<(), ReturnCode> {
+        dot::generate(&self.dz, opts, f)
+    }
+
+    /// The name of the file a node was created in
+    pub fn node_filename(&self, id: u32) -> Option<&str> {
+        let idx = id as usize - 1;
+        self.file_mappings
+            .iter()
+            .find(|m| idx >= m.start && idx < m.end)
+            .and_then(|m| m.filename.as_deref())
+    }
+}
+
+/// Write every table that refers to nodes, everything except
+/// the nodes themselves and the noderefs
+pub(crate) fn generate_rows(dz: &DagZet, f: &mut impl Output) {
+    let connections: Table<ConnectionsTable> = Table::default();
+    connections.generate(dz, f);
+
+    let lines: Table<LinesTable> = Table::default();
+    lines.generate(dz, f);
+
+    let graph_remarks: Table<GraphRemarksTable> = Table::default();
+    graph_remarks.generate(dz, f);
+
+    let connection_remarks: Table<ConnectionRemarksTable> = Table::default();
+    connection_remarks.generate(dz, f);
+
+    let node_remarks: Table<NodeRemarksTable> = Table::default();
+    node_remarks.generate(dz, f);
+
+    let file_ranges: Table<FileRangesTable> = Table::default();
+    file_ranges.generate(dz, f);
+
+    let hyperlinks: Table<HyperlinksTable> = Table::default();
+    hyperlinks.generate(dz, f);
+
+    let todos: Table<TODOTable> = Table::default();
+    todos.generate(dz, f);
+
+    let tags: Table<TagsTable> = Table::default();
+    tags.generate(dz, f);
+
+    let flashcards: Table<FlashCardsTable> = Table::default();
+    flashcards.generate(dz, f);
+
+    let images: Table<ImagesTable> = Table::default();
+    images.generate(dz, f);
+
+    let audio: Table<AudioTable> = Table::default();
+    audio.generate(dz, f);
+
+    let equations: Table<EquationsTable> = Table::default();
+    equations.generate(dz, f);
+
+    let pages: Table<PagesTable> = Table::default();
+    pages.generate(dz, f);
+
+    let attributes: Table<AttributesTable> = Table::default();
+    attributes.generate(dz, f);
+
+    let aliases: Table<AliasesTable> = Table::default();
+    aliases.generate(dz, f);
+
+    let connection_refs: Table<ConnectionRefsTable> = Table::default();
+    connection_refs.generate(dz, f);
+
+    let connection_remark_refs: Table<ConnectionRemarkRefsTable> = Table::default();
+    connection_remark_refs.generate(dz, f);
+
+    let line_refs: Table<LineRefsTable> = Table::default();
+    line_refs.generate(dz, f);
+
+    let node_remark_refs: Table<NodeRemarkRefsTable> = Table::default();
+    node_remark_refs.generate(dz, f);
+
+    let tag_refs: Table<TagRefsTable> = Table::default();
+    tag_refs.generate(dz, f);
+
+    let attribute_refs: Table<AttributeRefsTable> = Table::default();
+    attribute_refs.generate(dz, f);
+}
+
+/// Compile a set of dagzet files into SQLite code. If no
+/// files are given, stdin is read instead. Nothing is written
+/// if any errors are found.
+pub fn compile(filenames: &[String], f: &mut impl Output) -> Result<(), Vec<DagZetError>> {
+    let graph = build_files(filenames)?;
+    graph.generate_sql(f);
+    Ok(())
+}
+
+/// Like compile, with options for how the SQLite code
+/// is written
+pub fn compile_with_options(
+    filenames: &[String],
+    options: &SqlOptions,
+    f: &mut impl Output,
+) -> Result<(), Vec<DagZetError>> {
+    build_files(filenames)?.generate_sql_with(options, f)
+}
+
+/// Parse and validate a set of dagzet files. If no files
+/// are given, stdin is read instead.
+pub fn build_files(filenames: &[String]) -> Result<Graph, Vec<DagZetError>> {
+    let mut compiler = Compiler::new();
+
+    if filenames.is_empty() {
+        compiler.parse_reader(None, BufReader::new(io::stdin()));
+    } else {
+        for filename in filenames {
+            compiler.parse_file(filename);
+        }
+    }
+
+    compiler.build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compile_readers() {
+        let mut compiler = Compiler::new();
+        compiler.parse_reader(Some("a.dz"), "ns a\nnn b\nnn c\nco b c\n".as_bytes());
+        compiler.parse_reader(Some("b.dz"), "ns d\nnn e\ncx d/e a/b\n".as_bytes());
+
+        let graph = compiler.build().unwrap();
+        assert_eq!(graph.dz.nodelist, ["a/b", "a/c", "d/e"]);
+
+        let mut sql: Vec<u8> = vec![];
+        graph.generate_sql(&mut sql);
+        let sql = String::from_utf8(sql).unwrap();
+
+        assert!(sql.starts_with("BEGIN;\n"));
+        assert!(sql.contains("INSERT INTO dz_noderefs(node, filename, linum)\nVALUES((SELECT id from dz_nodes WHERE name IS 'd/e' LIMIT 1), 'b.dz', 2);"));
+        assert!(sql.contains("INSERT INTO dz_connection_refs(left, right, filename, linum)\nVALUES((SELECT id from dz_nodes WHERE name IS 'd/e' LIMIT 1), (SELECT id from dz_nodes WHERE name IS 'a/b' LIMIT 1), 'b.dz', 3);"));
+        assert!(sql.ends_with("COMMIT;\n"));
+
+        assert_eq!(graph.node_filename(2), Some("a.dz"));
+        assert_eq!(graph.node_filename(3), Some("b.dz"));
+    }
+
+    #[test]
+    fn test_quotes_escaped() {
+        let mut compiler = Compiler::new();
+        let code = "ns it's\nnn a'b\nat k'ey va'l\ngr it's\nnn c\nco a'b c\n";
+        compiler.parse_reader(Some("it's.dz"), code.as_bytes());
+
+        let mut sql: Vec<u8> = vec![];
+        compiler.build().unwrap().generate_sql(&mut sql);
+        let sql = String::from_utf8(sql).unwrap();
+
+        assert!(sql.contains("VALUES('it''s/a''b', 1);"));
+        assert!(sql.contains("WHERE name IS 'it''s/a''b' LIMIT 1), 'k''ey', 'va''l');"));
+        assert!(sql.contains("VALUES('it''s', '[\"it''s\"]');"));
+        assert!(sql.contains("'it''s.dz', 2);"));
+    }
+
+    #[test]
+    fn test_deterministic_output() {
+        let code = "ns a\nnn c\ntg z y x\nat k2 v\nat k1 v\nnn b\nln one\nnn a\nln two\nco c b\n";
+
+        let sql = || {
+            let mut compiler = Compiler::new();
+            compiler.parse_reader(Some("a.dz"), code.as_bytes());
+            let mut sql: Vec<u8> = vec![];
+            compiler.build().unwrap().generate_sql(&mut sql);
+            String::from_utf8(sql).unwrap()
+        };
+
+        let first = sql();
+        for _ in 0..4 {
+            assert_eq!(sql(), first);
+        }
+
+        let position = |s: &str| first.find(s).unwrap();
+        assert!(position("'a/c', 1") < position("'a/b', 2"));
+        assert!(position("'a/b', 2") < position("'a/a', 3"));
+        assert!(position("'x');") < position("'y');"));
+        assert!(position("'y');") < position("'z');"));
+        assert!(position("'k1', 'v'") < position("'k2', 'v'"));
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join(format!("dagzet-include-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let top = dir.join("top.dz");
+        let top = top.to_str().unwrap();
+        fs::write(top, "ns a\nnn b\nin sub/inc.dz\nln still b\nnn c\n").unwrap();
+        fs::write(dir.join("sub/inc.dz"), "ns x\nal y x/z\nnn z\n").unwrap();
+
+        let mut compiler = Compiler::new();
+        compiler.parse_file(top);
+        let graph = compiler.build().unwrap();
+        let dz = &graph.dz;
+
+        assert_eq!(dz.nodelist, ["a/b", "x/z", "a/c"]);
+        assert_eq!(dz.lines[&1], ["still b"]);
+        let inc = dir.join("sub").join("inc.dz");
+        assert_eq!(graph.node_filename(1), Some(top));
+        assert_eq!(graph.node_filename(2), inc.to_str());
+        assert_eq!(graph.node_filename(3), Some(top));
+
+        fs::write(dir.join("sub/inc.dz"), "in ../top.dz\n").unwrap();
+        let mut compiler = Compiler::new();
+        compiler.parse_file(top);
+        let errors = match compiler.build() {
+            Ok(_) => panic!("expected an include cycle"),
+            Err(errors) => errors,
+        };
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].filename.as_deref(), inc.to_str());
+        assert!(matches!(&errors[0].reason, ReturnCode::IncludeCycle(files) if files.len() == 3));
+    }
+
+    #[test]
+    fn test_literal_ids() {
+        let options = SqlOptions {
+            literal_ids: true,
+            ..Default::default()
+        };
+
+        let mut compiler = Compiler::new();
+        compiler.parse_reader(Some("a.dz"), "ns a\nnn b\nln it\nnn c\nco b c\n".as_bytes());
+
+        let mut sql: Vec<u8> = vec![];
+        let graph = compiler.build().unwrap();
+        graph.generate_sql_with(&options, &mut sql).unwrap();
+        let sql = String::from_utf8(sql).unwrap();
+
+        assert!(sql.contains("INSERT INTO dz_nodes(name, id, position)\nVALUES('a/c', 2, 2);"));
+        assert!(sql.contains("INSERT INTO dz_connections(left, right)\nVALUES(1, 2);"));
+        assert!(sql.contains("INSERT INTO dz_lines(node, lines)\nVALUES(1, '[\"it\"]');"));
+        assert!(!sql.contains("SELECT id from dz_nodes"));
+
+        let mut compiler = Compiler::new();
+        compiler.parse_reader(Some("a.dz"), "ns a\nnn b\ncx a/b x/y\n".as_bytes());
+        let graph = compiler.build().unwrap();
+        let mut sql: Vec<u8> = vec![];
+        let errors = graph.generate_sql_with(&options, &mut sql).unwrap_err();
+        assert!(sql.is_empty());
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].linum, 3);
+        assert!(matches!(&errors[0].reason, ReturnCode::UnknownNode(name) if name == "x/y"));
+    }
+
+    #[test]
+    fn test_compile_collects_errors() {
+        let mut compiler = Compiler::new();
+        compiler.parse_reader(Some("a.dz"), "ns a\nnn b\nnn b\nco b c\n".as_bytes());
+        compiler.parse_reader(Some("b.dz"), "zz ok\nxx not a command\n".as_bytes());
+
+        let errors = match compiler.build() {
+            Ok(_) => panic!("expected errors"),
+            Err(errors) => errors,
+        };
+
+        let locations: Vec<_> = errors
+            .iter()
+            .map(|e| (e.filename.as_deref().unwrap(), e.linum, e.col))
+            .collect();
+        assert_eq!(locations, [("a.dz", 3, 4), ("b.dz", 2, 1), ("a.dz", 4, 0)]);
+        assert!(errors[1].to_string().starts_with("b.dz:2:1: Invalid command"));
+    }
+}
@@ -1,10 +1,14 @@
 use core::fmt;
-use std::collections::hash_map;
+use std::collections::btree_map;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::HashMap;
 use std::collections::HashSet;
 
-use crate::trie::Trie;
+use crate::parser::{self, Command, Span};
+use crate::trie::{SuffixError, Trie};
 
+#[derive(Debug)]
 pub enum ReturnCode {
     Okay,
     Error,
@@ -15,12 +19,22 @@ pub enum ReturnCode {
     NotEnoughArgs,
     AlreadyConnected,
     NoConnections,
-}
-
-impl fmt::Debug for ReturnCode {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> fmt::Result {
-        f.write_str("TODO: implement debug trait")
-    }
+    InvalidNumber(String),
+    InvalidRange(i32, i32),
+    NoPreviousFile,
+    DuplicateTag(String),
+    UnknownNode(String),
+    SuffixNotFound(String),
+    /// A suffix and every node it could be
+    AmbiguousSuffix(String, Vec<String>),
+    DuplicateConnection(String, String),
+    Cycle(Vec<String>),
+    CouldNotRead(String),
+    CouldNotWrite(String),
+    UnknownAlias(String),
+    AliasAlreadyExists(String),
+    IncludeCycle(Vec<String>),
+    IncludeNotSupported,
 }
 
 impl fmt::Display for ReturnCode {
@@ -35,19 +49,138 @@ impl fmt::Display for ReturnCode {
             ReturnCode::NotEnoughArgs => write!(f, "Not Enough arguments"),
             ReturnCode::AlreadyConnected => write!(f, "Already connected"),
             ReturnCode::NoConnections => write!(f, "No connections made"),
+            ReturnCode::InvalidNumber(s) => write!(f, "'{s}' is not a valid number"),
+            ReturnCode::InvalidRange(start, end) => {
+                write!(f, "invalid range: start {start} is after end {end}")
+            }
+            ReturnCode::NoPreviousFile => write!(f, "no previous filename to use with '$'"),
+            ReturnCode::DuplicateTag(tag) => write!(f, "duplicate tag '{tag}'"),
+            ReturnCode::UnknownNode(node) => write!(f, "unknown node '{node}'"),
+            ReturnCode::SuffixNotFound(suffix) => {
+                write!(f, "suffix ?{suffix} does not match any node")
+            }
+            ReturnCode::AmbiguousSuffix(suffix, candidates) => {
+                write!(
+                    f,
+                    "suffix ?{suffix} matches more than one node: {}",
+                    candidates.join(", ")
+                )
+            }
+            ReturnCode::DuplicateConnection(left, right) => {
+                write!(f, "duplicate connection {left} -> {right}")
+            }
+            ReturnCode::Cycle(path) => {
+                write!(f, "cycle found: {}", path.join(" -> "))?;
+                if let Some(first) = path.first() {
+                    write!(f, " -> {first}")?;
+                }
+                Ok(())
+            }
+            ReturnCode::CouldNotRead(reason) => write!(f, "could not read: {reason}"),
+            ReturnCode::CouldNotWrite(reason) => write!(f, "could not write: {reason}"),
+            ReturnCode::UnknownAlias(alias) => write!(f, "unknown alias '@{alias}'"),
+            ReturnCode::AliasAlreadyExists(alias) => {
+                write!(f, "alias '@{alias}' already exists")
+            }
+            ReturnCode::IncludeCycle(files) => {
+                write!(f, "include cycle found: {}", files.join(" -> "))
+            }
+            ReturnCode::IncludeNotSupported => {
+                write!(f, "includes need a compiler to read files")
+            }
+        }
+    }
+}
+
+/// Where something was declared: a file (an index into
+/// DagZet::files, if any) and a line number.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SourceRef {
+    pub file: Option<usize>,
+    pub linum: u32,
+}
+
+/// Another location involved in an error, such as each
+/// connection that makes up a cycle
+#[derive(Debug)]
+pub struct ErrorNote {
+    pub filename: Option<String>,
+    pub linum: u32,
+    pub note: String,
+}
+
+/// An error tied to a location in a dagzet file
+#[derive(Debug)]
+pub struct DagZetError {
+    pub filename: Option<String>,
+    pub linum: u32,
+    /// The column the problem starts at, counting from 1, or
+    /// 0 if it isn't known
+    pub col: usize,
+    /// The offending line, if there is one
+    pub command: Option<String>,
+    pub reason: ReturnCode,
+    pub notes: Vec<ErrorNote>,
+}
+
+impl fmt::Display for DagZetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let filename = self.filename.as_deref().unwrap_or("<stdin>");
+        if self.col > 0 {
+            write!(f, "{}:{}:{}: ", filename, self.linum, self.col)?;
+        } else {
+            write!(f, "{}:{}: ", filename, self.linum)?;
+        }
+        write!(f, "{}", self.reason)?;
+        if let Some(command) = &self.command {
+            write!(f, "\n    {}", command)?;
         }
+        for note in &self.notes {
+            let filename = note.filename.as_deref().unwrap_or("<stdin>");
+            write!(f, "\n    {}:{}: {}", filename, note.linum, note.note)?;
+        }
+        Ok(())
     }
 }
 
+impl std::error::Error for DagZetError {}
+
+/// The bits of parser state that carry over from
+/// one file to the next
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ParserState {
+    pub namespace: Option<String>,
+    pub curnode: Option<String>,
+    pub last_filename: Option<String>,
+}
+
+/// Where parsing was up to in a file, saved while another
+/// file that it includes gets parsed
+pub struct SuspendedFile {
+    state: ParserState,
+    file: Option<usize>,
+    linum: u32,
+    aliases: HashMap<String, (String, bool)>,
+}
+
 #[allow(dead_code)]
+#[derive(Clone)]
 pub struct FileRange {
     pub filename: String,
     pub start: i32,
     pub end: i32,
 }
 
+/// A short name for a long node path, made with "al"
+#[derive(Clone)]
+pub struct Alias {
+    pub name: String,
+    pub path: String,
+    pub sref: SourceRef,
+}
+
 #[allow(dead_code)]
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct FlashCard {
     pub front: Vec<String>,
     pub back: Vec<String>,
@@ -58,7 +191,7 @@ pub struct DagZet {
     /// The current namespace
     pub namespace: Option<String>,
     /// For each graph namespace, have some remarks represented as lines
-    pub graph_remarks: HashMap<String, Vec<String>>,
+    pub graph_remarks: BTreeMap<String, Vec<String>>,
 
     /// The local ID value of the currently selected node
     pub curnode: Option<u32>,
@@ -72,7 +205,10 @@ pub struct DagZet {
     pub nodelist: Vec<String>,
 
     /// Each line can have text content called "lines" (ln)
-    pub lines: HashMap<u32, Vec<String>>,
+    pub lines: BTreeMap<u32, Vec<String>>,
+
+    /// Where each line was added, parallel to lines
+    pub line_refs: BTreeMap<u32, Vec<SourceRef>>,
 
     /// Edges of the knowledge graph. These are represented
     /// as strings instead of IDs so they can be resolved
@@ -80,86 +216,219 @@ pub struct DagZet {
     /// nodes are made, which is more flexible.
     pub connections: Vec<[String; 2]>,
 
+    /// Where each connection was made, parallel to connections
+    pub connection_refs: Vec<SourceRef>,
+
     /// Remarks can be made about last connection made
-    pub connection_remarks: HashMap<usize, Vec<String>>,
+    pub connection_remarks: BTreeMap<usize, Vec<String>>,
+
+    /// Where each connection remark was made, parallel to
+    /// connection_remarks
+    pub connection_remark_refs: BTreeMap<usize, Vec<SourceRef>>,
 
     /// Remarks can be made about last node selected
-    pub node_remarks: HashMap<u32, Vec<String>>,
+    pub node_remarks: BTreeMap<u32, Vec<String>>,
+
+    /// Where each node remark was made, parallel to node_remarks
+    pub node_remark_refs: BTreeMap<u32, Vec<SourceRef>>,
 
     /// tie a node to a range of lines of a file
-    pub file_ranges: HashMap<u32, FileRange>,
+    pub file_ranges: BTreeMap<u32, FileRange>,
 
     last_filename: Option<String>,
 
     // Tie a hyperlink URL to a node. One per node.
-    pub hyperlinks: HashMap<u32, String>,
+    pub hyperlinks: BTreeMap<u32, String>,
 
     // Add a TODO item, one per node
-    pub todos: HashMap<u32, String>,
+    pub todos: BTreeMap<u32, String>,
 
-    pub tags: HashMap<u32, HashSet<String>>,
+    pub tags: BTreeMap<u32, BTreeSet<String>>,
+
+    /// Where each tag was added
+    pub tag_refs: BTreeMap<u32, BTreeMap<String, SourceRef>>,
 
     // Any nodes used in the "cx" command get stored here
     // External nodes will be ignored by the check_unknown_nodes
     pub xnodes: HashSet<String>,
 
-    pub flashcards: HashMap<u32, FlashCard>,
+    pub flashcards: BTreeMap<u32, FlashCard>,
 
     // multimedia: images and audio map nodes to filenames
-    pub images: HashMap<u32, String>,
-    pub audio: HashMap<u32, String>,
+    pub images: BTreeMap<u32, String>,
+    pub audio: BTreeMap<u32, String>,
+
+    // equations: LaTeX-style source, several per node
+    pub equations: BTreeMap<u32, Vec<String>>,
 
-    pub noderefs: HashMap<u32, u32>,
+    // page references (book pages, PDF pages), one per node
+    pub pages: BTreeMap<u32, String>,
+
+    pub noderefs: BTreeMap<u32, u32>,
     pub linum: u32,
 
+    /// Every file that has been parsed, in order
+    pub files: Vec<String>,
+    curfile: Option<usize>,
+
     // attributes: Every node can have a hashmap with key/value pairs
-    pub attr: HashMap<u32, HashMap<String, Option<String>>>,
+    pub attr: BTreeMap<u32, BTreeMap<String, Option<String>>>,
+
+    /// Where each attribute was last set
+    pub attr_refs: BTreeMap<u32, BTreeMap<String, SourceRef>>,
 
     // suffix tree
     suffix_tree: Trie,
+
+    /// The file each node was made in, parallel to nodelist
+    node_files: Vec<Option<usize>>,
+
+    /// Aliases currently in scope, with their paths and
+    /// whether they were made in a namespace. Those only last
+    /// until the namespace changes, and the rest until the
+    /// end of the file they were made in.
+    aliases: HashMap<String, (String, bool)>,
+
+    /// Every alias made, in order
+    pub alias_list: Vec<Alias>,
 }
 
-fn does_loop_exist(edges: &Vec<[u32; 2]>, a: u32, b: u32) -> bool {
-    for edge in edges {
-        if edge[0] == b && edge[1] == a {
-            return true;
-        }
+/// The namespace of a node, which is everything before
+/// the last '/'
+pub fn namespace(name: &str) -> &str {
+    match name.rfind('/') {
+        Some(pos) => &name[..pos],
+        None => "",
     }
-    false
 }
 
-fn remove_edge(edges: &mut Vec<[u32; 2]>, a: u32, b: u32) {
-    let mut edges_to_remove = vec![];
-    for (idx, edge) in edges.iter_mut().enumerate() {
-        if edge[0] == a && edge[1] == b {
-            edges_to_remove.push(idx);
-        }
-    }
+/// Whether a node is in a namespace, or one below it
+pub fn in_namespace(name: &str, ns: &str) -> bool {
+    let nodens = namespace(name);
+    nodens == ns || nodens.starts_with(&format!("{}/", ns))
+}
 
-    for idx in edges_to_remove {
-        edges.remove(idx);
+fn search_suffix(tree: &Trie, suffix: &str) -> Result<String, ReturnCode> {
+    match tree.search(suffix) {
+        Ok(path) => Ok(path),
+        Err(SuffixError::NotFound) => Err(ReturnCode::SuffixNotFound(suffix.to_string())),
+        Err(SuffixError::Ambiguous(candidates)) => {
+            Err(ReturnCode::AmbiguousSuffix(suffix.to_string(), candidates))
+        }
     }
 }
 
-fn no_incoming_nodes(node: u32, edges: &Vec<[u32; 2]>) -> bool {
-    for edge in edges {
-        if edge[1] == node {
-            return false;
+/// Find the strongly connected components of a graph with
+/// nodes 1..=nnodes, using Tarjan's algorithm. Only components
+/// that contain a cycle are returned, each sorted by node ID.
+fn cyclic_components(adj: &HashMap<u32, Vec<u32>>, nnodes: u32) -> Vec<Vec<u32>> {
+    let mut index: HashMap<u32, u32> = HashMap::new();
+    let mut lowlink: HashMap<u32, u32> = HashMap::new();
+    let mut on_stack: HashSet<u32> = HashSet::new();
+    let mut stack: Vec<u32> = vec![];
+    let mut components = vec![];
+    let mut next_index = 0;
+    let empty = vec![];
+
+    for root in 1..=nnodes {
+        if index.contains_key(&root) {
+            continue;
+        }
+
+        // iterative DFS, each frame is a node and the
+        // position of the next neighbour to visit
+        let mut frames: Vec<(u32, usize)> = vec![(root, 0)];
+        index.insert(root, next_index);
+        lowlink.insert(root, next_index);
+        next_index += 1;
+        stack.push(root);
+        on_stack.insert(root);
+
+        while let Some((node, pos)) = frames.last_mut() {
+            let node = *node;
+            let neighbours = adj.get(&node).unwrap_or(&empty);
+
+            if *pos < neighbours.len() {
+                let next = neighbours[*pos];
+                *pos += 1;
+
+                if let Some(next_idx) = index.get(&next) {
+                    if on_stack.contains(&next) {
+                        let low = lowlink[&node].min(*next_idx);
+                        lowlink.insert(node, low);
+                    }
+                } else {
+                    index.insert(next, next_index);
+                    lowlink.insert(next, next_index);
+                    next_index += 1;
+                    stack.push(next);
+                    on_stack.insert(next);
+                    frames.push((next, 0));
+                }
+                continue;
+            }
+
+            frames.pop();
+
+            if let Some((parent, _)) = frames.last() {
+                let low = lowlink[parent].min(lowlink[&node]);
+                lowlink.insert(*parent, low);
+            }
+
+            if lowlink[&node] == index[&node] {
+                let mut component = vec![];
+                while let Some(top) = stack.pop() {
+                    on_stack.remove(&top);
+                    component.push(top);
+                    if top == node {
+                        break;
+                    }
+                }
+
+                let self_loop = neighbours.contains(&node);
+                if component.len() > 1 || self_loop {
+                    component.sort();
+                    components.push(component);
+                }
+            }
         }
     }
-    true
-}
 
-fn nodes_connected_to(node: u32, edges: &Vec<[u32; 2]>) -> HashSet<u32> {
-    let mut connected: HashSet<u32> = HashSet::new();
+    components.sort();
+    components
+}
 
-    for edge in edges {
-        if edge[0] == node {
-            connected.insert(edge[1]);
+/// Find the shortest cycle through the first node in a
+/// strongly connected component, as an ordered path that
+/// leads back to where it started.
+fn shortest_cycle(adj: &HashMap<u32, Vec<u32>>, component: &[u32]) -> Vec<u32> {
+    let members: HashSet<u32> = component.iter().copied().collect();
+    let start = component[0];
+    let mut parent: HashMap<u32, u32> = HashMap::new();
+    let mut queue = std::collections::VecDeque::from([start]);
+    let empty = vec![];
+
+    while let Some(node) = queue.pop_front() {
+        for next in adj.get(&node).unwrap_or(&empty) {
+            if *next == start {
+                let mut path = vec![node];
+                let mut cur = node;
+                while cur != start {
+                    cur = parent[&cur];
+                    path.push(cur);
+                }
+                path.reverse();
+                return path;
+            }
+            if members.contains(next) && !parent.contains_key(next) {
+                parent.insert(*next, node);
+                queue.push_back(*next);
+            }
         }
     }
 
-    connected
+    // a strongly connected component always has a cycle
+    component.to_vec()
 }
 
 fn doubledot(fullpath: &str, path: &str) -> String {
@@ -193,31 +462,39 @@ impl DagZet {
     }
 
     pub fn parse_line_with_result(&mut self, line: &str) -> Result<ReturnCode, ReturnCode> {
-        if line.is_empty() {
-            return Ok(ReturnCode::Okay);
-        }
-
-        if line.len() < 3 {
-            return Err(ReturnCode::Error);
+        match parser::parse_line(line, self.linum) {
+            Ok(Some(stmt)) => self.eval(&stmt.command),
+            Ok(None) => Ok(ReturnCode::Okay),
+            Err(e) => Err(e.reason),
         }
+    }
 
-        let cmd = &line[0..2];
-        let args = &line[3..];
-
+    /// Apply a parsed command to the graph
+    pub fn eval(&mut self, cmd: &Command) -> Result<ReturnCode, ReturnCode> {
         match cmd {
-            "ns" => {
-                let mut new_namespace = args.trim().to_string();
+            Command::Namespace(args) => {
+                let mut new_namespace = args.to_string();
                 if new_namespace.contains("..") {
-                    let old_namespace = self.namespace.as_ref().unwrap();
+                    let old_namespace = match &self.namespace {
+                        Some(n) => n,
+                        None => return Err(ReturnCode::NameSpaceNotSet),
+                    };
                     new_namespace = doubledot(old_namespace, &new_namespace);
                 } else if new_namespace.contains('+') {
-                    let old_namespace = self.namespace.as_ref().unwrap();
+                    let old_namespace = match &self.namespace {
+                        Some(n) => n,
+                        None => return Err(ReturnCode::NameSpaceNotSet),
+                    };
                     new_namespace =
                         old_namespace.to_string() + "/" + new_namespace.get(1..).unwrap();
                 }
+                if self.namespace.as_ref() != Some(&new_namespace) {
+                    // aliases made in the old namespace go with it
+                    self.aliases.retain(|_, (_, scoped)| !*scoped);
+                }
                 self.namespace = Some(new_namespace);
             }
-            "gr" => {
+            Command::GraphRemark(args) => {
                 let gr = &mut self.graph_remarks;
 
                 let ns = match &self.namespace {
@@ -232,7 +509,7 @@ impl DagZet {
                     }
                 }
             }
-            "nn" => {
+            Command::NewNode(args) => {
                 let ns = match &self.namespace {
                     Some(n) => n,
                     None => return Err(ReturnCode::NameSpaceNotSet),
@@ -244,20 +521,13 @@ impl DagZet {
                 } else {
                     nodename = String::from(ns);
                     nodename.push('/');
-                    nodename.push_str(args.trim());
+                    nodename.push_str(args);
                 }
-                let nodes = &mut self.nodes;
-
-                if nodes.contains_key(&nodename) {
+                if self.nodes.contains_key(&nodename) {
                     return Err(ReturnCode::NodeAlreadyExists);
                 }
 
-                let node_id = nodes.len() as u32 + 1;
-
-                // add to suffix tree
-                self.suffix_tree.add_path(&nodename);
-                self.nodelist.push(nodename.clone());
-                nodes.insert(nodename, node_id);
+                let node_id = self.add_node(&nodename);
 
                 self.curnode = Some(node_id);
 
@@ -265,7 +535,7 @@ impl DagZet {
 
                 self.noderefs.insert(node_id, self.linum);
             }
-            "ln" => {
+            Command::Line(args) => {
                 let curnode = match &self.curnode {
                     Some(id) => *id,
                     _ => return Err(ReturnCode::NodeNotSelected),
@@ -278,27 +548,27 @@ impl DagZet {
                         lines.insert(curnode, vec![args.to_string()]);
                     }
                 }
+
+                let sref = self.source_ref();
+                self.line_refs.entry(curnode).or_default().push(sref);
             }
-            "co" => {
+            Command::Connect(left_arg, right_arg) => {
                 let ns = match &self.namespace {
                     Some(n) => n,
                     None => return Err(ReturnCode::NameSpaceNotSet),
                 };
 
-                let connect_args: Vec<_> = args.split_whitespace().collect();
-
-                if connect_args.len() < 2 {
-                    return Err(ReturnCode::NotEnoughArgs);
-                }
+                let left_alias = self.resolve_alias(left_arg)?;
+                let right_alias = self.resolve_alias(right_arg)?;
 
-                let use_left_shorthand = connect_args[0] == "$";
-                let use_right_shorthand = connect_args[1] == "$";
+                let use_left_shorthand = left_arg == "$";
+                let use_right_shorthand = right_arg == "$";
 
-                let use_suffix_left = connect_args[0].starts_with('?');
-                let use_suffix_right = connect_args[1].starts_with('?');
+                let use_suffix_left = left_arg.starts_with('?');
+                let use_suffix_right = right_arg.starts_with('?');
 
-                let use_left_doubledot = connect_args[0].contains("..");
-                let use_right_doubledot = connect_args[1].contains("..");
+                let use_left_doubledot = left_alias.is_none() && left_arg.contains("..");
+                let use_right_doubledot = right_alias.is_none() && right_arg.contains("..");
 
                 let shorthand_used = use_left_shorthand || use_right_shorthand;
                 let doubledot_used = use_left_doubledot || use_right_doubledot;
@@ -328,16 +598,20 @@ impl DagZet {
                     }
                 };
 
-                let left = if use_suffix_left {
-                    connect_args[0].to_string()
+                let left = if let Some(path) = left_alias {
+                    path
+                } else if use_suffix_left {
+                    left_arg.to_string()
                 } else {
-                    process_arg(connect_args[0], use_left_shorthand, use_left_doubledot)
+                    process_arg(left_arg, use_left_shorthand, use_left_doubledot)
                 };
 
-                let right = if use_suffix_right {
-                    connect_args[1].to_string()
+                let right = if let Some(path) = right_alias {
+                    path
+                } else if use_suffix_right {
+                    right_arg.to_string()
                 } else {
-                    process_arg(connect_args[1], use_right_shorthand, use_right_doubledot)
+                    process_arg(right_arg, use_right_shorthand, use_right_doubledot)
                 };
 
                 // skip the duplicate connections check if suffix syntax (?suffix)
@@ -347,23 +621,32 @@ impl DagZet {
                 }
 
                 self.connections.push([left, right]);
+                self.connection_refs.push(self.source_ref());
             }
-            "cr" => {
+            Command::ConnectionRemark(args) => {
                 if self.connections.is_empty() {
                     return Err(ReturnCode::NoConnections);
                 }
 
                 let cid = self.connections.len() - 1;
 
-                if let hash_map::Entry::Vacant(e) = self.connection_remarks.entry(cid) {
+                if let btree_map::Entry::Vacant(e) = self.connection_remarks.entry(cid) {
                     e.insert(vec![args.to_string()]);
                 } else {
                     let rm = &mut self.connection_remarks.get_mut(&cid).unwrap();
                     rm.push(args.to_string());
                 }
+
+                let sref = self.source_ref();
+                self.connection_remark_refs
+                    .entry(cid)
+                    .or_default()
+                    .push(sref);
             }
-            "zz" => {}
-            "rm" => {
+            Command::Comment(_) => {}
+            // includes need files to be read, which is up to the compiler
+            Command::Include(_) => return Err(ReturnCode::IncludeNotSupported),
+            Command::Remark(args) => {
                 let curnode = match &self.curnode {
                     Some(id) => *id,
                     _ => return Err(ReturnCode::NodeNotSelected),
@@ -376,88 +659,50 @@ impl DagZet {
                         remarks.insert(curnode, vec![args.to_string()]);
                     }
                 }
+
+                let sref = self.source_ref();
+                self.node_remark_refs.entry(curnode).or_default().push(sref);
             }
-            "fr" => {
+            Command::FileRange {
+                filename,
+                start,
+                end,
+            } => {
                 let curnode = match &self.curnode {
                     Some(id) => *id,
                     _ => return Err(ReturnCode::NodeNotSelected),
                 };
 
-                let args: Vec<_> = args.split_whitespace().collect();
-
-                if args.is_empty() {
-                    return Err(ReturnCode::NotEnoughArgs);
-                }
-
-                let filename = if args[0] == "$" {
+                let filename = if filename == "$" {
                     match &self.last_filename {
                         Some(x) => x.to_string(),
-                        // TODO: better error handling
-                        None => return Err(ReturnCode::Error),
+                        None => return Err(ReturnCode::NoPreviousFile),
                     }
                 } else {
-                    args[0].to_string()
+                    filename.to_string()
                 };
 
-                let (start, end) = if args.len() == 2 {
-                    let start = match args[1].to_string().parse::<i32>() {
-                        Ok(x) => x,
-
-                        // TODO: better error handling
-                        Err(_) => return Err(ReturnCode::Error),
-                    };
-                    (start, -1)
-                } else if args.len() >= 3 {
-                    let start = match args[1].to_string().parse::<i32>() {
-                        Ok(x) => x,
-
-                        // TODO: better error handling
-                        Err(_) => return Err(ReturnCode::Error),
-                    };
-                    let end = match args[2].to_string().parse::<i32>() {
-                        Ok(x) => x,
-                        Err(_) => return Err(ReturnCode::Error),
-                    };
-                    (start, end)
-                } else {
-                    (-1, -1)
-                };
-
-                if start >= 0 && end >= 0 && start > end {
-                    return Err(ReturnCode::Error);
-                }
-
                 self.last_filename = Some(filename.clone());
 
                 self.file_ranges.insert(
                     curnode,
                     FileRange {
                         filename,
-                        start,
-                        end,
+                        start: start.unwrap_or(-1),
+                        end: end.unwrap_or(-1),
                     },
                 );
             }
 
-            "hl" => {
-                let args: Vec<_> = args.split_whitespace().collect();
-                if args.is_empty() {
-                    // realistically, this error will never happen
-                    return Err(ReturnCode::NotEnoughArgs);
-                }
-
+            Command::Hyperlink(url) => {
                 let curnode = match &self.curnode {
                     Some(id) => *id,
                     _ => return Err(ReturnCode::NodeNotSelected),
                 };
-                self.hyperlinks.insert(curnode, args[0].to_string());
+                self.hyperlinks.insert(curnode, url.to_string());
             }
 
-            "td" => {
-                if args.is_empty() {
-                    // realistically, this error will never happen
-                    return Err(ReturnCode::NotEnoughArgs);
-                }
+            Command::Todo(args) => {
                 let curnode = match &self.curnode {
                     Some(id) => *id,
                     _ => return Err(ReturnCode::NodeNotSelected),
@@ -465,57 +710,57 @@ impl DagZet {
                 self.todos.insert(curnode, args.to_string());
             }
 
-            "tg" => {
+            Command::Tags(args) => {
                 let curnode = match &self.curnode {
                     Some(id) => *id,
                     _ => return Err(ReturnCode::NodeNotSelected),
                 };
 
+                let sref = self.source_ref();
                 let tagsmap = &mut self.tags;
-                let args: Vec<_> = args.split_whitespace().collect();
 
                 let tags = match tagsmap.get_mut(&curnode) {
                     Some(x) => x,
                     None => {
-                        tagsmap.insert(curnode, HashSet::new());
+                        tagsmap.insert(curnode, BTreeSet::new());
                         tagsmap.get_mut(&curnode).unwrap()
                     }
                 };
 
-                for arg in &args {
+                let tag_refs = self.tag_refs.entry(curnode).or_default();
+
+                for arg in args {
                     if !tags.insert(arg.to_string()) {
-                        // TODO: better error handling
-                        return Err(ReturnCode::Error);
+                        return Err(ReturnCode::DuplicateTag(arg.to_string()));
                     }
+                    tag_refs.insert(arg.to_string(), sref);
                 }
             }
 
-            "sn" => {
+            Command::SelectNode(arg) => {
                 let ns = match &self.namespace {
                     Some(n) => n,
                     None => return Err(ReturnCode::NameSpaceNotSet),
                 };
 
-                let args: Vec<_> = args.split_whitespace().collect();
-                let nodename = format!("{}/{}", ns, args[0]);
+                let nodename = match self.resolve_alias(arg)? {
+                    Some(path) => path,
+                    None => match self.resolve_suffix_arg(arg)? {
+                        Some(path) => path,
+                        None => format!("{}/{}", ns, arg),
+                    },
+                };
                 let node_id = match self.nodes.get(&nodename) {
                     Some(x) => x,
-
-                    // TODO: better error handling
-                    None => return Err(ReturnCode::Error),
+                    None => return Err(ReturnCode::UnknownNode(nodename)),
                 };
 
                 self.curnode = Some(*node_id);
             }
 
-            "cx" => {
-                let args: Vec<_> = args.split_whitespace().collect();
-                if args.len() < 2 {
-                    return Err(ReturnCode::NotEnoughArgs);
-                }
-
-                let mut left = args[0].to_string();
-                let mut right = args[1].to_string();
+            Command::ConnectExternal(left, right) => {
+                let mut left = left.to_string();
+                let mut right = right.to_string();
 
                 if left == "$" || right == "$" {
                     let curnode = match &self.curnode {
@@ -547,19 +792,34 @@ impl DagZet {
                     }
                 }
 
-                if left.get(0..1).unwrap() == "@" || right.get(0..1).unwrap() == "@" {
-                    todo!("Aliases not yet implemented");
+                if let Some(path) = self.resolve_alias(&left)? {
+                    left = path;
+                }
+
+                if let Some(path) = self.resolve_alias(&right)? {
+                    right = path;
                 }
 
-                if self.already_connected(&left, &right) {
+                // like "co", suffixes are resolved once every
+                // node has been made, and checked for
+                // duplicates then
+                let left_suffix = left.starts_with('?');
+                let right_suffix = right.starts_with('?');
+
+                if !(left_suffix || right_suffix) && self.already_connected(&left, &right) {
                     return Err(ReturnCode::AlreadyConnected);
                 }
-                self.xnodes.insert(left.clone());
-                self.xnodes.insert(right.clone());
+                if !left_suffix {
+                    self.xnodes.insert(left.clone());
+                }
+                if !right_suffix {
+                    self.xnodes.insert(right.clone());
+                }
                 self.connections.push([left, right]);
+                self.connection_refs.push(self.source_ref());
             }
 
-            "ff" => {
+            Command::FlashCardFront(args) => {
                 let curnode = match &self.curnode {
                     Some(id) => *id,
                     _ => return Err(ReturnCode::NodeNotSelected),
@@ -578,7 +838,7 @@ impl DagZet {
                 card.front.push(args.to_string());
             }
 
-            "fb" => {
+            Command::FlashCardBack(args) => {
                 let curnode = match &self.curnode {
                     Some(id) => *id,
                     _ => return Err(ReturnCode::NodeNotSelected),
@@ -597,7 +857,7 @@ impl DagZet {
                 card.back.push(args.to_string());
             }
 
-            "im" => {
+            Command::Image(args) => {
                 let curnode = match &self.curnode {
                     Some(id) => *id,
                     _ => return Err(ReturnCode::NodeNotSelected),
@@ -608,19 +868,53 @@ impl DagZet {
                 images.insert(curnode, args.to_string());
             }
 
-            "eq" => {
-                todo!("eq command not yet implemented");
+            Command::Equation(equation) => {
+                let curnode = match &self.curnode {
+                    Some(id) => *id,
+                    _ => return Err(ReturnCode::NodeNotSelected),
+                };
+
+                self.equations
+                    .entry(curnode)
+                    .or_default()
+                    .push(equation.to_string());
             }
 
-            "pg" => {
-                todo!("pg command not yet implemented");
+            Command::Page(page) => {
+                let curnode = match &self.curnode {
+                    Some(id) => *id,
+                    _ => return Err(ReturnCode::NodeNotSelected),
+                };
+
+                self.pages.insert(curnode, page.to_string());
             }
 
-            "al" => {
-                todo!("al command not yet implemented");
+            Command::Alias(name, path) => {
+                if self.aliases.contains_key(name) {
+                    return Err(ReturnCode::AliasAlreadyExists(name.to_string()));
+                }
+
+                // aliases can be made from other aliases, or
+                // suffixes of nodes made so far
+                let path = match self.resolve_alias(path)? {
+                    Some(path) => path,
+                    None => match self.resolve_suffix_arg(path)? {
+                        Some(path) => path,
+                        None => path.to_string(),
+                    },
+                };
+
+                let scoped = self.namespace.is_some();
+                self.aliases
+                    .insert(name.to_string(), (path.clone(), scoped));
+                self.alias_list.push(Alias {
+                    name: name.to_string(),
+                    path,
+                    sref: self.source_ref(),
+                });
             }
 
-            "au" => {
+            Command::Audio(args) => {
                 let curnode = match &self.curnode {
                     Some(id) => *id,
                     _ => return Err(ReturnCode::NodeNotSelected),
@@ -629,38 +923,143 @@ impl DagZet {
                 self.audio.insert(curnode, args.to_string());
             }
 
-            "at" => {
+            Command::Attribute(key, value) => {
                 let curnode = match &self.curnode {
                     Some(id) => *id,
                     _ => return Err(ReturnCode::NodeNotSelected),
                 };
 
-                let args: Vec<_> = args.split_whitespace().collect();
+                let attr = &mut self.attr;
 
-                if args.is_empty() {
-                    return Err(ReturnCode::NotEnoughArgs);
-                }
+                let node_attributes = attr.entry(curnode).or_default();
+                node_attributes.insert(key.to_string(), value.clone());
 
-                let key = args[0];
+                let sref = self.source_ref();
+                self.attr_refs
+                    .entry(curnode)
+                    .or_default()
+                    .insert(key.to_string(), sref);
+            }
+        }
+        Ok(ReturnCode::Okay)
+    }
 
-                let value = if args.len() > 1 {
-                    Some(args[1].to_string())
-                } else {
-                    None
-                };
+    /// Add a node without selecting it, returning its ID.
+    /// Does not check if the node already exists.
+    pub fn add_node(&mut self, nodename: &str) -> u32 {
+        let node_id = self.nodes.len() as u32 + 1;
 
-                let attr = &mut self.attr;
+        // add to suffix tree
+        self.suffix_tree.add_path(nodename);
+        self.nodelist.push(nodename.to_string());
+        self.node_files.push(self.curfile);
+        self.nodes.insert(nodename.to_string(), node_id);
 
-                // if !attr.contains_key(&curnode) {
-                //     attr.insert(curnode, HashMap::new());
-                // }
-                let node_attributes = attr.entry(curnode).or_default();
-                node_attributes.insert(key.to_string(), value);
+        node_id
+    }
+
+    /// Add a connection that has already been resolved. External
+    /// connections are treated like the ones made with "cx".
+    pub fn add_connection(&mut self, left: &str, right: &str, sref: SourceRef, external: bool) {
+        if external {
+            for node in [left, right] {
+                if !node.starts_with('?') {
+                    self.xnodes.insert(node.to_string());
+                }
             }
+        }
+        self.connections.push([left.to_string(), right.to_string()]);
+        self.connection_refs.push(sref);
+    }
+
+    /// Every node made so far, for looking up ?suffixes
+    pub fn suffix_tree(&self) -> &Trie {
+        &self.suffix_tree
+    }
+
+    /// Find the node a suffix (without the leading '?') refers
+    /// to, out of the nodes made so far
+    pub fn resolve_suffix(&self, suffix: &str) -> Result<String, ReturnCode> {
+        search_suffix(&self.suffix_tree, suffix)
+    }
 
-            _ => return Err(ReturnCode::InvalidCommand),
+    /// Resolve an argument given as a ?suffix. Anything else
+    /// returns None.
+    fn resolve_suffix_arg(&self, arg: &str) -> Result<Option<String>, ReturnCode> {
+        match arg.strip_prefix('?') {
+            Some(suffix) => self.resolve_suffix(suffix).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Find the ID of a node, by name, ?suffix or @alias.
+    /// Aliases are the ones in scope where parsing stopped,
+    /// as if the name came at the end of the last file.
+    pub fn lookup(&self, name: &str) -> Result<u32, ReturnCode> {
+        let fullname = match self.resolve_alias(name)? {
+            Some(path) => path,
+            None => match self.resolve_suffix_arg(name)? {
+                Some(path) => path,
+                None => name.to_string(),
+            },
+        };
+
+        match self.nodes.get(&fullname) {
+            Some(id) => Ok(*id),
+            None => Err(ReturnCode::UnknownNode(name.to_string())),
+        }
+    }
+
+    /// Where a node was made
+    pub fn node_ref(&self, id: u32) -> SourceRef {
+        SourceRef {
+            file: self.node_files.get(id as usize - 1).copied().flatten(),
+            linum: self.noderefs.get(&id).copied().unwrap_or(0),
+        }
+    }
+
+    pub fn parser_state(&self) -> ParserState {
+        ParserState {
+            namespace: self.namespace.clone(),
+            curnode: self
+                .curnode
+                .map(|id| self.nodelist[id as usize - 1].clone()),
+            last_filename: self.last_filename.clone(),
+        }
+    }
+
+    pub fn set_parser_state(&mut self, state: &ParserState) {
+        self.namespace = state.namespace.clone();
+        self.curnode = match &state.curnode {
+            Some(name) => self.nodes.get(name).copied(),
+            None => None,
+        };
+        self.last_filename = state.last_filename.clone();
+    }
+
+    /// Expand an alias reference (@name, optionally followed
+    /// by /more/path) into a full node path. Arguments that
+    /// aren't alias references return None.
+    fn resolve_alias(&self, arg: &str) -> Result<Option<String>, ReturnCode> {
+        let alias = match arg.strip_prefix('@') {
+            Some(alias) => alias,
+            None => return Ok(None),
+        };
+
+        let (name, rest) = match alias.split_once('/') {
+            Some((name, rest)) => (name, Some(rest)),
+            None => (alias, None),
+        };
+
+        let path = match self.aliases.get(name) {
+            Some((path, _)) => path,
+            None => return Err(ReturnCode::UnknownAlias(name.to_string())),
+        };
+
+        match rest {
+            Some(rest) => Ok(Some(format!("{}/{}", path, rest))),
+            None => Ok(Some(path.to_string())),
         }
-        Ok(ReturnCode::Okay)
     }
 
     fn already_connected(&self, left: &str, right: &str) -> bool {
@@ -703,9 +1102,7 @@ impl DagZet {
             let left_id = self.nodes.get(&co[0]);
             let right_id = self.nodes.get(&co[1]);
 
-            if left_id.is_some() && right_id.is_some() {
-                let left_id = left_id.unwrap();
-                let right_id = right_id.unwrap();
+            if let (Some(left_id), Some(right_id)) = (left_id, right_id) {
                 edges.push([*left_id, *right_id]);
             }
         }
@@ -713,116 +1110,79 @@ impl DagZet {
         edges
     }
 
+    /// Look for cycles in the graph. Each cycle found is
+    /// returned as a path of node IDs, where the last node
+    /// connects back to the first one. There is one cycle
+    /// for each group of nodes that are tangled up together.
     #[allow(dead_code)]
-    pub fn check_for_loops(&mut self, edges: &[[u32; 2]]) -> Result<ReturnCode, Vec<[u32; 2]>> {
-        // Generate set of nodes
-        let mut nodelist: HashSet<u32> = HashSet::new();
-
-        for id in self.nodes.values() {
-            nodelist.insert(*id);
+    pub fn check_for_loops(&mut self, edges: &[[u32; 2]]) -> Result<ReturnCode, Vec<Vec<u32>>> {
+        let mut adj: HashMap<u32, Vec<u32>> = HashMap::new();
+        for [left, right] in edges {
+            adj.entry(*left).or_default().push(*right);
         }
 
-        // deep copy edge
-        let mut edges = edges.to_owned();
-
-        // Determine initial set of nodes with no incoming nodes
-        let mut no_incoming: HashSet<u32> = HashSet::new();
-
-        for node in &nodelist {
-            if no_incoming_nodes(*node, &edges) {
-                no_incoming.insert(*node);
-            }
-        }
-
-        // Main Loop
-
-        while !no_incoming.is_empty() {
-            let mut new_no_incoming = HashSet::new();
-            for n in &no_incoming {
-                let nodesfrom = nodes_connected_to(*n, &edges);
-
-                for m in &nodesfrom {
-                    remove_edge(&mut edges, *n, *m);
-
-                    if no_incoming_nodes(*m, &edges) {
-                        new_no_incoming.insert(*m);
-                    }
-                }
-            }
-
-            no_incoming = new_no_incoming;
-        }
-        // Look for any remaining edges
-        if !edges.is_empty() {
-            // Check remaining edges for loops
-            let mut found_loops: Vec<[u32; 2]> = vec![];
-            for edge in &edges {
-                if does_loop_exist(&edges, edge[0], edge[1]) {
-                    found_loops.push(*edge);
-                }
-            }
+        let cycles: Vec<Vec<u32>> = cyclic_components(&adj, self.nodelist.len() as u32)
+            .iter()
+            .map(|component| shortest_cycle(&adj, component))
+            .collect();
 
-            // Keep track of loops found and return.
-            if !found_loops.is_empty() {
-                return Err(found_loops);
-            } else {
-                // If there are remaining edges but no loops,
-                // panic. That's weird and probably shouldn't happen?
-                //panic!("Not sure why there are remaining edges.");
-                //
-                // larger loops detected
-                for edge in &edges {
-                    found_loops.push(*edge);
-                }
-                return Err(found_loops);
-            }
+        if cycles.is_empty() {
+            Ok(ReturnCode::Okay)
+        } else {
+            Err(cycles)
         }
-
-        Ok(ReturnCode::Okay)
     }
 
     #[allow(dead_code)]
-    pub fn resolve_connections(&mut self) {
+    pub fn resolve_connections(&mut self) -> Result<(), Vec<DagZetError>> {
         // dynamically populate an adjacency list
         // TODO: move this into dagzet struct, this could be used
         // with already_connected() method
         let mut adj: HashMap<String, HashSet<String>> = HashMap::new();
+        let mut failed: Vec<(usize, ReturnCode)> = vec![];
 
         let connections = &mut self.connections;
 
-        let generate = |suffix: bool, con: &str| -> String {
+        let generate = |suffix: bool, con: &str| -> Result<String, ReturnCode> {
             if suffix {
-                let res = self.suffix_tree.search(&con[1..]);
-                match res {
-                    Ok(s) => s,
-                    _ => panic!("suffix error: {con}"),
-                }
+                search_suffix(&self.suffix_tree, &con[1..])
             } else {
-                con.to_string()
+                Ok(con.to_string())
             }
         };
 
-        for co in connections {
+        for (idx, co) in connections.iter_mut().enumerate() {
             let left_suffix = co[0].starts_with('?');
             let right_suffix = co[1].starts_with('?');
 
             if !(left_suffix || right_suffix) {
-                if !adj.contains_key(&co[0]) {
-                    adj.insert(co[0].to_string(), HashSet::new());
+                // duplicates without suffixes are caught while
+                // parsing, unless a suffix got there first
+                let left = adj.entry(co[0].to_string()).or_default();
+                if !left.insert(co[1].to_string()) {
+                    let rc = ReturnCode::DuplicateConnection(co[0].clone(), co[1].clone());
+                    failed.push((idx, rc));
                 }
-                let left = adj.get_mut(&co[0]).unwrap();
-                left.insert(co[1].to_string());
                 continue;
             }
 
-            let left = generate(left_suffix, &co[0]);
-            let right = generate(right_suffix, &co[1]);
+            let (left, right) = match (
+                generate(left_suffix, &co[0]),
+                generate(right_suffix, &co[1]),
+            ) {
+                (Ok(left), Ok(right)) => (left, right),
+                (Err(rc), _) | (_, Err(rc)) => {
+                    failed.push((idx, rc));
+                    continue;
+                }
+            };
 
             if adj.contains_key(&left) {
                 let lefty = adj.get(&left).unwrap();
 
                 if lefty.contains(&right) {
-                    todo!("Duplicate connections: {left} -> {right}");
+                    failed.push((idx, ReturnCode::DuplicateConnection(left, right)));
+                    continue;
                 }
             }
 
@@ -837,6 +1197,189 @@ impl DagZet {
             co[0] = left;
             co[1] = right;
         }
+
+        if failed.is_empty() {
+            return Ok(());
+        }
+
+        Err(failed
+            .into_iter()
+            .map(|(idx, rc)| self.connection_error(idx, rc))
+            .collect())
+    }
+
+    /// Run every check that needs the whole graph: suffix
+    /// resolution, unknown nodes, and loops. All problems
+    /// found are returned together.
+    pub fn validate(&mut self) -> Result<(), Vec<DagZetError>> {
+        let mut errors = match self.resolve_connections() {
+            Ok(_) => vec![],
+            Err(errors) => errors,
+        };
+
+        let unknowns = self.check_unknown_nodes();
+        for (idx, co) in self.connections.iter().enumerate() {
+            for node in co {
+                // unresolved suffixes have already been reported
+                if node.starts_with('?') {
+                    continue;
+                }
+                if unknowns.contains(node) {
+                    errors.push(self.connection_error(idx, ReturnCode::UnknownNode(node.clone())));
+                }
+            }
+        }
+
+        if let Err(cycles) = self.check_for_loops(&self.generate_edges()) {
+            for cycle in cycles {
+                errors.push(self.cycle_error(&cycle));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Find connections to nodes that don't exist in this
+    /// graph, such as external nodes made with "cx". These
+    /// are fine when nodes are looked up by name, but can't
+    /// be given a literal ID.
+    pub fn unresolved_connections(&self) -> Vec<DagZetError> {
+        let mut errors = vec![];
+        for (idx, co) in self.connections.iter().enumerate() {
+            for node in co {
+                if !self.nodes.contains_key(node) {
+                    errors.push(self.connection_error(idx, ReturnCode::UnknownNode(node.clone())));
+                }
+            }
+        }
+        errors
+    }
+
+    /// Start parsing a new file. Locations of anything parsed
+    /// after this will refer to this file.
+    pub fn begin_file(&mut self, filename: &str) {
+        self.files.push(filename.to_string());
+        self.curfile = Some(self.files.len() - 1);
+        self.linum = 0;
+        self.aliases.clear();
+    }
+
+    /// Save the current file's place before parsing a file
+    /// it includes. The namespace and selected node are
+    /// saved too, so the include can't change them.
+    pub fn suspend_file(&self) -> SuspendedFile {
+        SuspendedFile {
+            state: self.parser_state(),
+            file: self.curfile,
+            linum: self.linum,
+            aliases: self.aliases.clone(),
+        }
+    }
+
+    /// Pick up where a suspended file left off
+    pub fn resume_file(&mut self, suspended: SuspendedFile) {
+        self.set_parser_state(&suspended.state);
+        self.curfile = suspended.file;
+        self.linum = suspended.linum;
+        self.aliases = suspended.aliases;
+    }
+
+    pub fn source_ref(&self) -> SourceRef {
+        SourceRef {
+            file: self.curfile,
+            linum: self.linum,
+        }
+    }
+
+    pub fn error_at(&self, sref: &SourceRef, reason: ReturnCode) -> DagZetError {
+        DagZetError {
+            filename: sref.file.map(|f| self.files[f].clone()),
+            linum: sref.linum,
+            col: 0,
+            command: None,
+            notes: self.notes(&reason),
+            reason,
+        }
+    }
+
+    /// Notes that go with an error. For ambiguous suffixes,
+    /// that is where each candidate was made.
+    fn notes(&self, reason: &ReturnCode) -> Vec<ErrorNote> {
+        let candidates = match reason {
+            ReturnCode::AmbiguousSuffix(_, candidates) => candidates,
+            _ => return vec![],
+        };
+
+        candidates
+            .iter()
+            .filter_map(|name| self.nodes.get(name))
+            .map(|id| {
+                let sref = self.node_ref(*id);
+                ErrorNote {
+                    filename: sref.file.map(|f| self.files[f].clone()),
+                    linum: sref.linum,
+                    note: format!("could be {}", self.nodelist[*id as usize - 1]),
+                }
+            })
+            .collect()
+    }
+
+    /// Create an error for the line currently being parsed,
+    /// about the part of it in a span
+    pub fn error(&self, line: &str, span: Span, reason: ReturnCode) -> DagZetError {
+        DagZetError {
+            col: span.start + 1,
+            command: Some(line.to_string()),
+            ..self.error_at(&self.source_ref(), reason)
+        }
+    }
+
+    /// An error for a cycle, pointing at the first connection
+    /// in it, with a note for every connection involved
+    fn cycle_error(&self, cycle: &[u32]) -> DagZetError {
+        let names: Vec<String> = cycle
+            .iter()
+            .map(|id| self.nodelist[*id as usize - 1].clone())
+            .collect();
+
+        let mut notes = vec![];
+        for (i, left) in names.iter().enumerate() {
+            let right = &names[(i + 1) % names.len()];
+            let idx = self
+                .connections
+                .iter()
+                .position(|co| &co[0] == left && &co[1] == right);
+            let sref = idx
+                .and_then(|idx| self.connection_refs.get(idx).copied())
+                .unwrap_or_default();
+            notes.push(ErrorNote {
+                filename: sref.file.map(|f| self.files[f].clone()),
+                linum: sref.linum,
+                note: format!("{} -> {}", left, right),
+            });
+        }
+
+        DagZetError {
+            filename: notes[0].filename.clone(),
+            linum: notes[0].linum,
+            col: 0,
+            command: None,
+            reason: ReturnCode::Cycle(names),
+            notes,
+        }
+    }
+
+    fn connection_error(&self, idx: usize, reason: ReturnCode) -> DagZetError {
+        let sref = self.connection_refs.get(idx).copied().unwrap_or_default();
+        let co = &self.connections[idx];
+        DagZetError {
+            command: Some(format!("{} -> {}", co[0], co[1])),
+            ..self.error_at(&sref, reason)
+        }
     }
 }
 
@@ -625,6 +625,47 @@ fn test_audio() {
     assert_eq!(filename, &"c.mp3");
 }
 
+#[test]
+fn test_equation() {
+    let mut dz = DagZet::new();
+    dz.parse_line("ns a");
+    let result = dz.parse_line_with_result("eq x^2");
+    assert!(result.is_err_and(|x| matches!(x, ReturnCode::NodeNotSelected)));
+
+    dz.parse_line("nn b");
+    dz.parse_line("eq e^{i\\pi} + 1 = 0");
+    dz.parse_line("eq \\frac{a}{b}");
+
+    let result = dz.parse_line_with_result("eq  ");
+    assert!(result.is_err_and(|x| matches!(x, ReturnCode::NotEnoughArgs)));
+
+    assert_eq!(dz.equations.len(), 1);
+    let curnode = dz.curnode.unwrap();
+
+    let equations = dz.equations.get(&curnode).unwrap();
+
+    assert_eq!(equations, &["e^{i\\pi} + 1 = 0", "\\frac{a}{b}"]);
+}
+
+#[test]
+fn test_page() {
+    let mut dz = DagZet::new();
+    dz.parse_line("ns a");
+    dz.parse_line("nn b");
+    dz.parse_line("pg 42");
+
+    assert_eq!(dz.pages.len(), 1);
+    let curnode = dz.curnode.unwrap();
+
+    let page = &dz.pages.get(&curnode).unwrap();
+
+    assert_eq!(page, &"42");
+
+    dz.parse_line("pg xii ");
+    let page = &dz.pages.get(&curnode).unwrap();
+    assert_eq!(page, &"xii");
+}
+
 #[test]
 fn test_cx_shorthand_curnode() {
     let mut dz = DagZet::new();
@@ -796,7 +837,7 @@ fn test_suffix_basic() {
     dz.parse_line("nn x/y/z");
     dz.parse_line("co ?d/e $");
 
-    dz.resolve_connections();
+    dz.resolve_connections().unwrap();
 
     let co = &dz.connections[0];
 
@@ -810,7 +851,6 @@ fn test_suffix_basic() {
 }
 
 #[test]
-#[should_panic]
 fn test_suffix_duplicates() {
     let mut dz = DagZet::new();
 
@@ -820,7 +860,309 @@ fn test_suffix_duplicates() {
     dz.parse_line("co $ ?d/e");
     dz.parse_line("co $ ?c/d/e");
 
-    dz.resolve_connections();
+    let errors = dz.resolve_connections().unwrap_err();
+
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(
+        &errors[0].reason,
+        ReturnCode::DuplicateConnection(left, right) if left == "a/f" && right == "a/b/c/d/e"
+    ));
+}
+
+#[test]
+fn test_suffix_errors() {
+    let mut dz = DagZet::new();
+
+    dz.begin_file("test.dz");
+    dz.linum = 1;
+    dz.parse_line("ns a");
+    dz.linum = 2;
+    dz.parse_line("nn b/foo");
+    dz.linum = 3;
+    dz.parse_line("nn c/foo");
+    dz.linum = 4;
+    dz.parse_line("co ?foo ?b/foo");
+    dz.linum = 5;
+    dz.parse_line("co ?bar ?b/foo");
+
+    let errors = dz.resolve_connections().unwrap_err();
+    assert_eq!(errors.len(), 2);
+
+    assert!(matches!(
+        &errors[0].reason,
+        ReturnCode::AmbiguousSuffix(s, candidates) if s == "foo" && candidates == &["a/b/foo", "a/c/foo"]
+    ));
+    assert_eq!(errors[0].filename.as_deref(), Some("test.dz"));
+    assert_eq!(errors[0].linum, 4);
+
+    // each candidate gets a note with where it was made
+    let notes: Vec<(u32, &str)> = errors[0]
+        .notes
+        .iter()
+        .map(|n| (n.linum, n.note.as_str()))
+        .collect();
+    assert_eq!(notes, [(2, "could be a/b/foo"), (3, "could be a/c/foo")]);
+    assert_eq!(errors[0].notes[0].filename.as_deref(), Some("test.dz"));
+
+    assert!(matches!(&errors[1].reason, ReturnCode::SuffixNotFound(s) if s == "bar"));
+    assert_eq!(errors[1].linum, 5);
+}
+
+#[test]
+fn test_suffix_everywhere() {
+    let mut dz = DagZet::new();
+    dz.parse_line("ns a");
+    dz.parse_line("nn b/c");
+    dz.parse_line("nn d/c");
+    dz.parse_line("nn e");
+
+    dz.parse_line("sn ?b/c");
+    assert_eq!(dz.nodelist[dz.curnode.unwrap() as usize - 1], "a/b/c");
+
+    let result = dz.parse_line_with_result("sn ?c");
+    assert!(result.is_err_and(|x| matches!(x, ReturnCode::AmbiguousSuffix(_, c) if c.len() == 2)));
+
+    dz.parse_line("al bc ?b/c");
+    assert_eq!(dz.alias_list[0].path, "a/b/c");
+
+    let result = dz.parse_line_with_result("al nope ?nope");
+    assert!(result.is_err_and(|x| matches!(x, ReturnCode::SuffixNotFound(s) if s == "nope")));
+
+    dz.parse_line("cx ?e ext/node");
+    dz.resolve_connections().unwrap();
+    assert_eq!(dz.connections[0], ["a/e", "ext/node"]);
+    assert!(dz.check_unknown_nodes().is_empty());
+}
+
+#[test]
+fn test_suffix_duplicates_plain_connection() {
+    let mut dz = DagZet::new();
+    dz.parse_line("ns a");
+    dz.parse_line("nn b");
+    dz.parse_line("nn c");
+    dz.parse_line("co ?b c");
+    dz.parse_line("co b c");
+
+    let errors = dz.resolve_connections().unwrap_err();
+    assert_eq!(errors.len(), 1);
+    assert!(matches!(
+        &errors[0].reason,
+        ReturnCode::DuplicateConnection(left, right) if left == "a/b" && right == "a/c"
+    ));
+}
+
+#[test]
+fn test_error_location() {
+    let mut dz = DagZet::new();
+    dz.begin_file("errors.dz");
+    dz.parse_line("ns aaa");
+    dz.parse_line("nn bbb");
+    dz.linum = 3;
+    let line = "fr foo one 4";
+    let e = parser::parse_line(line, 3).unwrap_err();
+    let err = dz.error(line, e.span, e.reason);
+
+    assert_eq!(err.filename.as_deref(), Some("errors.dz"));
+    assert_eq!(err.linum, 3);
+    assert_eq!(err.command.as_deref(), Some(line));
+    assert!(matches!(&err.reason, ReturnCode::InvalidNumber(s) if s == "one"));
+    assert_eq!(
+        err.to_string(),
+        "errors.dz:3:4: 'one' is not a valid number\n    fr foo one 4"
+    );
+}
+
+#[test]
+fn test_validate_collects_errors() {
+    let mut dz = DagZet::new();
+    dz.begin_file("a.dz");
+    dz.linum = 1;
+    dz.parse_line("ns top");
+    dz.linum = 2;
+    dz.parse_line("nn aaa");
+    dz.linum = 3;
+    dz.parse_line("co aaa bbb");
+    dz.linum = 4;
+    dz.parse_line("co ccc aaa");
+
+    dz.begin_file("b.dz");
+    dz.linum = 1;
+    dz.parse_line("ns other");
+    dz.linum = 2;
+    dz.parse_line("nn x");
+    dz.linum = 3;
+    dz.parse_line("nn y");
+    dz.linum = 4;
+    dz.parse_line("co x y");
+    dz.linum = 5;
+    dz.parse_line("co y x");
+
+    let errors = dz.validate().unwrap_err();
+
+    let unknowns: Vec<_> = errors
+        .iter()
+        .filter(|e| matches!(e.reason, ReturnCode::UnknownNode(_)))
+        .map(|e| (e.filename.as_deref().unwrap(), e.linum))
+        .collect();
+    assert_eq!(unknowns, [("a.dz", 3), ("a.dz", 4)]);
+
+    let cycles: Vec<_> = errors
+        .iter()
+        .filter(|e| matches!(e.reason, ReturnCode::Cycle(_)))
+        .collect();
+    assert_eq!(cycles.len(), 1);
+    assert_eq!(cycles[0].filename.as_deref(), Some("b.dz"));
+    assert_eq!(cycles[0].linum, 4);
+    assert_eq!(cycles[0].notes.len(), 2);
+}
+
+#[test]
+fn test_cycle_path() {
+    let mut dz = DagZet::new();
+    dz.begin_file("a.dz");
+    let code = [
+        "ns top", "nn a", "nn b", "nn c", "nn d", "co a b", "co b c", "co c a", "co c d", "co d d",
+    ];
+    for (linum, line) in code.iter().enumerate() {
+        dz.linum = linum as u32 + 1;
+        dz.parse_line(line);
+    }
+
+    let errors = dz.validate().unwrap_err();
+    assert_eq!(errors.len(), 2);
+
+    assert_eq!(
+        errors[0].to_string(),
+        concat!(
+            "a.dz:6: cycle found: top/a -> top/b -> top/c -> top/a\n",
+            "    a.dz:6: top/a -> top/b\n",
+            "    a.dz:7: top/b -> top/c\n",
+            "    a.dz:8: top/c -> top/a"
+        )
+    );
+
+    assert!(matches!(
+        &errors[1].reason,
+        ReturnCode::Cycle(path) if path == &["top/d"]
+    ));
+    assert_eq!(errors[1].linum, 10);
+}
+
+#[test]
+fn test_source_refs() {
+    let mut dz = DagZet::new();
+    dz.begin_file("a.dz");
+    let code = [
+        "ns a",
+        "nn b",
+        "ln one",
+        "ln two",
+        "rm remark",
+        "tg x y",
+        "at k v",
+        "nn c",
+        "co b c",
+        "cr why",
+        "cx c ext/d",
+    ];
+    for (linum, line) in code.iter().enumerate() {
+        dz.linum = linum as u32 + 1;
+        dz.parse_line(line);
+    }
+
+    let at = |linum| SourceRef {
+        file: Some(0),
+        linum,
+    };
+
+    assert_eq!(dz.line_refs[&1], [at(3), at(4)]);
+    assert_eq!(dz.node_remark_refs[&1], [at(5)]);
+    assert_eq!(dz.tag_refs[&1]["x"], at(6));
+    assert_eq!(dz.tag_refs[&1]["y"], at(6));
+    assert_eq!(dz.attr_refs[&1]["k"], at(7));
+    assert_eq!(dz.connection_refs, [at(9), at(11)]);
+    assert_eq!(dz.connection_remark_refs[&0], [at(10)]);
+}
+
+#[test]
+fn test_alias() {
+    let mut dz = DagZet::new();
+    dz.begin_file("a.dz");
+    dz.parse_line("ns knowledge/systems");
+    dz.parse_line("nn foo");
+    dz.parse_line("nn foo/bar");
+    dz.parse_line("ns other");
+    dz.parse_line("nn baz");
+
+    let result = dz.parse_line_with_result("al ks");
+    assert!(result.is_err_and(|x| matches!(x, ReturnCode::NotEnoughArgs)));
+
+    dz.parse_line("al ks knowledge/systems/foo");
+    dz.parse_line("al ksb @ks/bar");
+
+    let result = dz.parse_line_with_result("al ks knowledge");
+    assert!(result.is_err_and(|x| matches!(x, ReturnCode::AliasAlreadyExists(_))));
+
+    dz.parse_line("co $ @ks");
+    dz.parse_line("cx @ksb ext/node");
+
+    assert_eq!(dz.connections[0], ["other/baz", "knowledge/systems/foo"]);
+    assert_eq!(dz.connections[1], ["knowledge/systems/foo/bar", "ext/node"]);
+
+    dz.parse_line("sn @ks");
+    let curnode = dz.curnode.unwrap();
+    assert_eq!(dz.nodelist[curnode as usize - 1], "knowledge/systems/foo");
+
+    let result = dz.parse_line_with_result("co $ @nope");
+    assert!(result.is_err_and(|x| matches!(x, ReturnCode::UnknownAlias(a) if a == "nope")));
+
+    assert_eq!(dz.alias_list.len(), 2);
+    assert_eq!(dz.alias_list[1].path, "knowledge/systems/foo/bar");
+
+    // aliases are scoped to the file they were made in
+    dz.begin_file("b.dz");
+    let result = dz.parse_line_with_result("sn @ks");
+    assert!(result.is_err_and(|x| matches!(x, ReturnCode::UnknownAlias(_))));
+}
+
+#[test]
+fn test_lookup() {
+    let mut dz = DagZet::new();
+    dz.begin_file("a.dz");
+    for line in ["ns a", "nn b", "nn b/c", "al old a/b", "ns d", "al b a/b"] {
+        dz.parse_line(line);
+    }
+
+    assert_eq!(dz.lookup("a/b").unwrap(), 1);
+    assert_eq!(dz.lookup("?b/c").unwrap(), 2);
+    assert_eq!(dz.lookup("@b").unwrap(), 1);
+    assert_eq!(dz.lookup("@b/c").unwrap(), 2);
+
+    // the same aliases the language would accept here
+    let result = dz.lookup("@old");
+    assert!(result.is_err_and(|x| matches!(x, ReturnCode::UnknownAlias(a) if a == "old")));
+    let result = dz.lookup("@b/nope");
+    assert!(result.is_err_and(|x| matches!(x, ReturnCode::UnknownNode(n) if n == "@b/nope")));
+}
+
+#[test]
+fn test_alias_scope() {
+    let mut dz = DagZet::new();
+    dz.begin_file("a.dz");
+    dz.parse_line("al top a/b");
+    dz.parse_line("ns a");
+    dz.parse_line("nn b");
+    dz.parse_line("al here a/b");
+    dz.parse_line("ns a");
+    dz.parse_line("sn @here");
+    dz.parse_line("ns c");
+
+    // aliases made in a namespace go when it changes, and
+    // ones made before any namespace last the whole file
+    let result = dz.parse_line_with_result("sn @here");
+    assert!(result.is_err_and(|x| matches!(x, ReturnCode::UnknownAlias(a) if a == "here")));
+    assert!(dz.parse_line_with_result("sn @top").is_ok());
 
-    dbg!(&dz.connections);
+    // the name can be used again in the new namespace
+    assert!(dz.parse_line_with_result("al here a/b").is_ok());
 }
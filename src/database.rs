@@ -0,0 +1,275 @@
+//! Writes straight to a SQLite database file, instead of
+//! printing SQL code to be piped into sqlite3. Rows are
+//! inserted with prepared statements, so values get bound
+//! as-is rather than escaped into string literals.
+
+use crate::sqlite::{Output, SQLize, Table, Value};
+use rusqlite::types::{ToSqlOutput, ValueRef};
+use rusqlite::{params_from_iter, Connection, ToSql};
+use std::collections::HashSet;
+
+/// What to do with tables that already exist in the database
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WriteMode {
+    /// Drop and recreate each table before writing to it
+    Replace,
+    /// Keep existing rows, adding new rows after them
+    Append,
+}
+
+impl ToSql for Value {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            Value::Null => ToSqlOutput::Borrowed(ValueRef::Null),
+            Value::Integer(i) => ToSqlOutput::Borrowed(ValueRef::Integer(*i)),
+            Value::Text(s) | Value::Lookup(_, s) => {
+                ToSqlOutput::Borrowed(ValueRef::Text(s.as_bytes()))
+            }
+        })
+    }
+}
+
+pub struct Database {
+    conn: Connection,
+    mode: WriteMode,
+    /// tables created so far, which are only replaced once
+    created: HashSet<String>,
+    /// the first error, reported by finish()
+    error: Option<rusqlite::Error>,
+}
+
+impl Database {
+    pub fn open(path: &str, mode: WriteMode) -> rusqlite::Result<Self> {
+        Ok(Database::new(Connection::open(path)?, mode))
+    }
+
+    pub fn new(conn: Connection, mode: WriteMode) -> Self {
+        let mut db = Database {
+            conn,
+            mode,
+            created: HashSet::new(),
+            error: None,
+        };
+        // Match the sqlite3 shell, which leaves foreign keys
+        // unenforced. Otherwise replacing dz_nodes would fail
+        // while the other tables still refer to it.
+        db.execute("PRAGMA foreign_keys = OFF;");
+        db
+    }
+
+    fn check(&mut self, result: rusqlite::Result<()>) {
+        if let Err(e) = result {
+            self.error.get_or_insert(e);
+        }
+    }
+
+    /// Finish writing. If anything went wrong along the way,
+    /// the first error is returned and any open transaction
+    /// is rolled back.
+    pub fn finish(self) -> rusqlite::Result<Connection> {
+        match self.error {
+            Some(e) => {
+                if !self.conn.is_autocommit() {
+                    let _ = self.conn.execute_batch("ROLLBACK;");
+                }
+                Err(e)
+            }
+            None => Ok(self.conn),
+        }
+    }
+}
+
+impl Output for Database {
+    fn execute(&mut self, sql: &str) {
+        if self.error.is_some() {
+            return;
+        }
+        let result = self.conn.execute_batch(sql);
+        self.check(result);
+    }
+
+    fn create<T>(&mut self, table: &Table<T>) {
+        let name = table.name().to_string();
+        if self.mode == WriteMode::Replace && !self.created.contains(&name) {
+            self.execute(&format!("DROP TABLE IF EXISTS {};", name));
+        }
+        self.execute(&table.sqlize());
+        self.execute(&table.sqlize_indexes());
+        self.created.insert(name);
+    }
+
+    fn insert_values<T>(&mut self, table: &Table<T>, values: Vec<Value>) {
+        if self.error.is_some() {
+            return;
+        }
+        let sql = table.insert_statement(&values);
+        let result = self
+            .conn
+            .prepare_cached(&sql)
+            .and_then(|mut stmt| stmt.execute(params_from_iter(values.iter())))
+            .map(|_| ());
+        self.check(result);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{Compiler, SqlOptions};
+
+    fn compile(code: &str, db: &mut Database) {
+        let mut compiler = Compiler::new();
+        compiler.parse_reader(Some("a.dz"), code.as_bytes());
+        compiler.build().unwrap().generate_sql(db);
+    }
+
+    fn count(conn: &Connection, table: &str) -> i64 {
+        conn.query_row(&format!("SELECT COUNT(*) FROM {}", table), [], |r| r.get(0))
+            .unwrap()
+    }
+
+    #[test]
+    fn test_bound_values() {
+        let mut db = Database::new(Connection::open_in_memory().unwrap(), WriteMode::Replace);
+        compile(
+            "ns a\nnn it's\nln a \"quoted\" line\nat k it's\nnn b\nco b it's\n",
+            &mut db,
+        );
+        let conn = db.finish().unwrap();
+
+        let lines: String = conn
+            .query_row(
+                "SELECT lines FROM dz_lines INNER JOIN dz_nodes ON dz_nodes.id = node \
+                 WHERE name IS 'a/it''s'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(lines, "[\"a \\\"quoted\\\" line\"]");
+
+        let value: String = conn
+            .query_row("SELECT value FROM dz_attributes", [], |r| r.get(0))
+            .unwrap();
+        assert_eq!(value, "it's");
+
+        let right: String = conn
+            .query_row(
+                "SELECT name FROM dz_connections INNER JOIN dz_nodes ON dz_nodes.id = right",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(right, "a/it's");
+    }
+
+    #[test]
+    fn test_replace_and_append() {
+        let conn = Connection::open_in_memory().unwrap();
+
+        let mut db = Database::new(conn, WriteMode::Append);
+        compile("ns a\nnn b\nln one\n", &mut db);
+        let conn = db.finish().unwrap();
+
+        let mut db = Database::new(conn, WriteMode::Append);
+        compile("ns c\nnn d\nln two\n", &mut db);
+        let conn = db.finish().unwrap();
+        assert_eq!(count(&conn, "dz_lines"), 2);
+
+        let mut db = Database::new(conn, WriteMode::Replace);
+        compile("ns c\nnn d\nln two\n", &mut db);
+        let conn = db.finish().unwrap();
+        assert_eq!(count(&conn, "dz_lines"), 1);
+        assert_eq!(count(&conn, "dz_nodes"), 1);
+    }
+
+    #[test]
+    fn test_indexes_and_views() {
+        let mut db = Database::new(Connection::open_in_memory().unwrap(), WriteMode::Replace);
+        compile("ns a\nnn b\ntg x\nnn c\nco b c\n", &mut db);
+        let conn = db.finish().unwrap();
+
+        let index: String = conn
+            .query_row(
+                "SELECT sql FROM sqlite_master WHERE name IS 'dz_tags_node'",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(index, "CREATE INDEX dz_tags_node ON dz_tags(node)");
+
+        let edge: (String, String) = conn
+            .query_row("SELECT left_name, right_name FROM dz_edges", [], |r| {
+                Ok((r.get(0)?, r.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(edge, ("a/b".to_string(), "a/c".to_string()));
+
+        let violations = conn
+            .prepare("PRAGMA foreign_key_check")
+            .unwrap()
+            .query_map([], |_| Ok(()))
+            .unwrap()
+            .count();
+        assert_eq!(violations, 0);
+    }
+
+    #[test]
+    fn test_search() {
+        let mut db = Database::new(Connection::open_in_memory().unwrap(), WriteMode::Replace);
+        let mut compiler = Compiler::new();
+        compiler.parse_reader(
+            Some("a.dz"),
+            concat!(
+                "ns a\n",
+                "nn groups\nln a set with an operation\nrm see rings\n",
+                "nn rings\nln groups with a second operation\n",
+                "co rings groups\ncr rings are groups under addition\n",
+            )
+            .as_bytes(),
+        );
+        let options = SqlOptions {
+            search: true,
+            ..Default::default()
+        };
+        compiler
+            .build()
+            .unwrap()
+            .generate_sql_with(&options, &mut db)
+            .unwrap();
+        let conn = db.finish().unwrap();
+
+        let search = |query: &str| -> Vec<String> {
+            conn.prepare("SELECT name FROM dz_search WHERE dz_search MATCH ? ORDER BY rank, name")
+                .unwrap()
+                .query_map([query], |r| r.get(0))
+                .unwrap()
+                .map(|r| r.unwrap())
+                .collect()
+        };
+
+        assert_eq!(search("rings"), ["a/rings", "a/groups"]);
+        assert_eq!(search("addition"), ["a/rings"]);
+        assert_eq!(search("remarks:rings"), ["a/groups"]);
+
+        conn.execute_batch(concat!(
+            "UPDATE dz_lines SET lines = '[\"monoids\"]' WHERE node = 1;\n",
+            "DELETE FROM dz_connection_remarks;\n",
+            "INSERT INTO dz_nodes(name, position) VALUES('a/monoids', 3);\n",
+        ))
+        .unwrap();
+
+        assert_eq!(search("monoids"), ["a/monoids", "a/groups"]);
+        assert_eq!(search("operation"), ["a/rings"]);
+        assert!(search("addition").is_empty());
+    }
+
+    #[test]
+    fn test_error_rolls_back() {
+        let mut db = Database::new(Connection::open_in_memory().unwrap(), WriteMode::Replace);
+        db.execute("CREATE TABLE t(x INTEGER NOT NULL);\nBEGIN;\n");
+        db.execute("INSERT INTO t VALUES(1);");
+        db.execute("INSERT INTO t VALUES(NULL);");
+        db.execute("COMMIT;");
+        assert!(db.finish().is_err());
+    }
+}
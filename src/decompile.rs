@@ -0,0 +1,594 @@
+//! Turns a database written by dagzet back into dagzet code,
+//! for when the database has been edited directly. Nodes are
+//! split into one file per namespace, or into the files they
+//! were made in, going by dz_noderefs.
+//!
+//! Compiling the files again gives the same nodes, lines,
+//! tags, connections and so on. Source locations are not kept,
+//! since they point into the new files. Connections between
+//! namespaces are written with "cx", as "co" can only refer to
+//! nodes in the current namespace.
+
+use crate::dagzet::namespace;
+use rusqlite::{Connection, Row};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::{Component, Path, PathBuf};
+
+/// How nodes are split up into files
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Layout {
+    /// One file per namespace, so a/b/c goes in a/b.dz
+    Namespace,
+    /// The file each node was made in. Nodes without one
+    /// (from stdin) go in their namespace's file.
+    File,
+}
+
+struct Node {
+    id: i64,
+    name: String,
+    file: PathBuf,
+    linum: i64,
+    position: i64,
+}
+
+/// What gets written in one file
+#[derive(Default)]
+struct File<'a> {
+    aliases: Vec<(String, String)>,
+    graph_remarks: Vec<(&'a str, &'a [String])>,
+    nodes: Vec<&'a Node>,
+}
+
+/// Whether the database has a table with this name
+pub fn has_table(conn: &Connection, name: &str) -> rusqlite::Result<bool> {
+    conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type IS 'table' AND name IS ?",
+        [name],
+        |r| r.get::<_, i64>(0),
+    )
+    .map(|n| n > 0)
+}
+
+/// Every row of a query on a table, or nothing if the
+/// table doesn't exist
+fn rows<T>(
+    conn: &Connection,
+    table: &str,
+    sql: &str,
+    f: impl FnMut(&Row) -> rusqlite::Result<T>,
+) -> rusqlite::Result<Vec<T>> {
+    if !has_table(conn, table)? {
+        return Ok(vec![]);
+    }
+    let mut stmt = conn.prepare(sql)?;
+    let rows = stmt.query_map([], f)?.collect();
+    rows
+}
+
+/// A node and a column of text for each row in a table, in
+/// the order they were inserted
+fn node_text(conn: &Connection, table: &str, column: &str) -> rusqlite::Result<Vec<(i64, String)>> {
+    let sql = format!(
+        "SELECT node, {} FROM {} WHERE node IS NOT NULL ORDER BY rowid",
+        column, table
+    );
+    rows(conn, table, &sql, |r| {
+        Ok((
+            r.get(0)?,
+            r.get::<_, Option<String>>(1)?.unwrap_or_default(),
+        ))
+    })
+}
+
+/// A command and its arguments, which can be empty
+fn command(name: &str, args: &str) -> String {
+    if args.is_empty() {
+        name.to_string()
+    } else {
+        format!("{} {}", name, args)
+    }
+}
+
+/// The strings in a JSON array of lines. Anything else, like
+/// JSON that was broken by hand, has no lines.
+fn json_lines(conn: &Connection, text: &str) -> Vec<String> {
+    let sql = "SELECT value FROM json_each(?1) WHERE json_type(?1) IS 'array' AND type IS 'text'";
+    conn.prepare_cached(sql)
+        .and_then(|mut stmt| stmt.query_map([text], |r| r.get(0))?.collect())
+        .unwrap_or_default()
+}
+
+/// Whether a node can be written with "ns" and "nn", which
+/// needs both a namespace and a name after it. Rows edited by
+/// hand might have neither.
+fn valid_name(name: &str) -> bool {
+    let ns = namespace(name);
+    !ns.is_empty() && name.len() > ns.len() + 1
+}
+
+fn namespace_file(ns: &str) -> PathBuf {
+    PathBuf::from(format!("{}.dz", ns))
+}
+
+/// A filename from the database as a relative path, so it
+/// can't point outside of the output directory
+fn relative(filename: &str) -> Option<PathBuf> {
+    let path: PathBuf = Path::new(filename)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part),
+            _ => None,
+        })
+        .collect();
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+/// The commands that follow "nn" for each node
+fn node_bodies(
+    conn: &Connection,
+    names: &HashMap<i64, &str>,
+) -> rusqlite::Result<HashMap<i64, Vec<String>>> {
+    let mut bodies: HashMap<i64, Vec<String>> = HashMap::new();
+    let mut add = |id: i64, cmd: String| bodies.entry(id).or_default().push(cmd);
+
+    for (id, lines) in node_text(conn, "dz_lines", "lines")? {
+        for line in json_lines(conn, &lines) {
+            add(id, command("ln", &line));
+        }
+    }
+
+    for (id, remarks) in node_text(conn, "dz_remarks", "remarks")? {
+        for remark in json_lines(conn, &remarks) {
+            add(id, command("rm", &remark));
+        }
+    }
+
+    let ranges = rows(
+        conn,
+        "dz_file_ranges",
+        "SELECT node, filename, start, end FROM dz_file_ranges \
+         WHERE node IS NOT NULL ORDER BY rowid",
+        |r| {
+            let start: Option<i64> = r.get(2)?;
+            let end: Option<i64> = r.get(3)?;
+            Ok((r.get(0)?, r.get::<_, String>(1)?, start, end))
+        },
+    )?;
+    for (id, filename, start, end) in ranges {
+        let args = match (start.unwrap_or(-1), end.unwrap_or(-1)) {
+            (-1, -1) => filename,
+            (start, -1) => format!("{} {}", filename, start),
+            (start, end) => format!("{} {} {}", filename, start, end),
+        };
+        add(id, command("fr", &args));
+    }
+
+    for (id, url) in node_text(conn, "dz_hyperlinks", "hyperlink")? {
+        add(id, command("hl", &url));
+    }
+
+    for (id, task) in node_text(conn, "dz_todo", "task")? {
+        add(id, command("td", &task));
+    }
+
+    let mut tags: BTreeMap<i64, Vec<String>> = BTreeMap::new();
+    for (id, tag) in node_text(conn, "dz_tags", "tag")? {
+        tags.entry(id).or_default().push(tag);
+    }
+    for (id, mut tags) in tags {
+        tags.sort();
+        add(id, command("tg", &tags.join(" ")));
+    }
+
+    let cards = rows(
+        conn,
+        "dz_flashcards",
+        "SELECT node, front, back FROM dz_flashcards WHERE node IS NOT NULL ORDER BY rowid",
+        |r| {
+            let front: Option<String> = r.get(1)?;
+            let back: Option<String> = r.get(2)?;
+            Ok((
+                r.get(0)?,
+                front.unwrap_or_default(),
+                back.unwrap_or_default(),
+            ))
+        },
+    )?;
+    for (id, front, back) in cards {
+        for line in json_lines(conn, &front) {
+            add(id, command("ff", &line));
+        }
+        for line in json_lines(conn, &back) {
+            add(id, command("fb", &line));
+        }
+    }
+
+    for (id, image) in node_text(conn, "dz_images", "image")? {
+        add(id, command("im", &image));
+    }
+
+    for (id, audio) in node_text(conn, "dz_audio", "audio")? {
+        add(id, command("au", &audio));
+    }
+
+    for (id, equation) in node_text(conn, "dz_equations", "equation")? {
+        add(id, command("eq", &equation));
+    }
+
+    for (id, page) in node_text(conn, "dz_pages", "page")? {
+        add(id, command("pg", &page));
+    }
+
+    let attributes = rows(
+        conn,
+        "dz_attributes",
+        "SELECT node, key, value FROM dz_attributes WHERE node IS NOT NULL ORDER BY rowid",
+        |r| {
+            let value: Option<String> = r.get(2)?;
+            Ok((r.get(0)?, r.get::<_, String>(1)?, value.unwrap_or_default()))
+        },
+    )?;
+    for (id, key, value) in attributes {
+        add(id, command("at", &command(&key, &value)));
+    }
+
+    let remarks: HashMap<(i64, i64), String> = rows(
+        conn,
+        "dz_connection_remarks",
+        "SELECT left, right, remarks FROM dz_connection_remarks \
+         WHERE left IS NOT NULL AND right IS NOT NULL",
+        |r| Ok(((r.get(0)?, r.get(1)?), r.get(2)?)),
+    )?
+    .into_iter()
+    .collect();
+
+    let connections = rows(
+        conn,
+        "dz_connections",
+        "SELECT left, right FROM dz_connections ORDER BY rowid",
+        |r| Ok((r.get::<_, i64>(0)?, r.get::<_, i64>(1)?)),
+    )?;
+    for (left, right) in connections {
+        let (left_name, right_name) = match (names.get(&left), names.get(&right)) {
+            (Some(left), Some(right)) => (left, right),
+            _ => continue,
+        };
+
+        // "co" puts the current namespace in front of names
+        let prefix = format!("{}/", namespace(left_name));
+        let cmd = match right_name.strip_prefix(&prefix) {
+            Some(rest) => command("co", &format!("$ {}", rest)),
+            None => command("cx", &format!("$ {}", right_name)),
+        };
+        add(left, cmd);
+
+        if let Some(text) = remarks.get(&(left, right)) {
+            for remark in json_lines(conn, text) {
+                add(left, command("cr", &remark));
+            }
+        }
+    }
+
+    Ok(bodies)
+}
+
+/// Turn a dagzet database back into dagzet code. Each file
+/// is returned as a path, relative to wherever the files
+/// are to be written, and its contents. Nodes with no
+/// namespace can't be written, so they are skipped.
+pub fn decompile(conn: &Connection, layout: Layout) -> rusqlite::Result<BTreeMap<PathBuf, String>> {
+    let noderefs: HashMap<i64, (String, i64)> = rows(
+        conn,
+        "dz_noderefs",
+        "SELECT node, filename, linum FROM dz_noderefs WHERE node IS NOT NULL",
+        |r| {
+            let filename: Option<String> = r.get(1)?;
+            let linum: Option<i64> = r.get(2)?;
+            Ok((
+                r.get(0)?,
+                (filename.unwrap_or_default(), linum.unwrap_or(0)),
+            ))
+        },
+    )?
+    .into_iter()
+    .collect();
+
+    let mut nodes = rows(
+        conn,
+        "dz_nodes",
+        "SELECT id, name, position FROM dz_nodes ORDER BY position, id",
+        |r| {
+            let id: i64 = r.get(0)?;
+            let name: String = r.get(1)?;
+            let position: Option<i64> = r.get(2)?;
+            let (filename, linum) = match noderefs.get(&id) {
+                Some((filename, linum)) => (filename.as_str(), *linum),
+                None => ("", 0),
+            };
+            let file = match layout {
+                Layout::File => relative(filename),
+                Layout::Namespace => None,
+            };
+            Ok(Node {
+                id,
+                file: file.unwrap_or_else(|| namespace_file(namespace(&name))),
+                name,
+                linum,
+                position: position.unwrap_or(id),
+            })
+        },
+    )?;
+
+    // nodes that can't be written are left out, along with
+    // everything that refers to them
+    nodes.retain(|n| valid_name(&n.name));
+
+    let names: HashMap<i64, &str> = nodes.iter().map(|n| (n.id, n.name.as_str())).collect();
+    let bodies = node_bodies(conn, &names)?;
+
+    let mut files: BTreeMap<PathBuf, File> = BTreeMap::new();
+    for node in &nodes {
+        files.entry(node.file.clone()).or_default().nodes.push(node);
+    }
+    // line numbers only mean something within a single file
+    for file in files.values_mut() {
+        match layout {
+            Layout::File => file.nodes.sort_by_key(|n| (n.linum, n.position)),
+            Layout::Namespace => file.nodes.sort_by_key(|n| n.position),
+        }
+    }
+
+    let mut graph_remarks: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let rows_found = rows(
+        conn,
+        "dz_graph_remarks",
+        "SELECT namespace, remarks FROM dz_graph_remarks ORDER BY rowid",
+        |r| Ok((r.get::<_, String>(0)?, r.get::<_, String>(1)?)),
+    )?;
+    for (ns, remarks) in rows_found {
+        graph_remarks
+            .entry(ns)
+            .or_default()
+            .extend(json_lines(conn, &remarks));
+    }
+
+    // graph remarks go with the first node made in their
+    // namespace
+    for (ns, remarks) in &graph_remarks {
+        let path = nodes
+            .iter()
+            .find(|n| namespace(&n.name) == ns)
+            .map(|n| n.file.clone())
+            .unwrap_or_else(|| namespace_file(ns));
+        let file = files.entry(path).or_default();
+        file.graph_remarks.push((ns, remarks));
+    }
+
+    let aliases = rows(
+        conn,
+        "dz_aliases",
+        "SELECT alias, path, filename FROM dz_aliases ORDER BY rowid",
+        |r| {
+            let filename: Option<String> = r.get(2)?;
+            Ok((
+                r.get::<_, String>(0)?,
+                r.get::<_, String>(1)?,
+                filename.unwrap_or_default(),
+            ))
+        },
+    )?;
+    for (name, path, filename) in aliases {
+        let file = match layout {
+            Layout::File => relative(&filename),
+            Layout::Namespace => None,
+        };
+        let file = file.unwrap_or_else(|| namespace_file(namespace(&path)));
+        files.entry(file).or_default().aliases.push((name, path));
+    }
+
+    Ok(files
+        .into_iter()
+        .map(|(path, file)| (path, write_file(&file, &bodies)))
+        .collect())
+}
+
+fn write_file(file: &File, bodies: &HashMap<i64, Vec<String>>) -> String {
+    let mut blocks: Vec<Vec<String>> = vec![];
+    let mut current_ns: Option<&str> = None;
+
+    // an alias can only be made once per file
+    let mut seen = HashSet::new();
+    let aliases: Vec<String> = file
+        .aliases
+        .iter()
+        .filter(|(name, _)| seen.insert(name))
+        .map(|(name, path)| format!("al @{} {}", name, path))
+        .collect();
+    if !aliases.is_empty() {
+        blocks.push(aliases);
+    }
+
+    for (ns, remarks) in &file.graph_remarks {
+        let mut block = vec![command("ns", ns)];
+        block.extend(remarks.iter().map(|remark| command("gr", remark)));
+        blocks.push(block);
+        current_ns = Some(ns);
+    }
+
+    for node in &file.nodes {
+        let ns = namespace(&node.name);
+        let mut block = vec![];
+        if current_ns != Some(ns) {
+            block.push(command("ns", ns));
+            current_ns = Some(ns);
+        }
+        block.push(command("nn", &node.name[ns.len() + 1..]));
+        block.extend(bodies.get(&node.id).into_iter().flatten().cloned());
+        blocks.push(block);
+    }
+
+    let blocks: Vec<String> = blocks.iter().map(|block| block.join("\n")).collect();
+    blocks.join("\n\n") + "\n"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+    use crate::database::{Database, WriteMode};
+
+    fn compile(files: &[(&str, &str)]) -> Connection {
+        let mut compiler = Compiler::new();
+        for (filename, code) in files {
+            compiler.parse_reader(Some(filename), code.as_bytes());
+        }
+        let mut db = Database::new(Connection::open_in_memory().unwrap(), WriteMode::Replace);
+        compiler.build().unwrap().generate_sql(&mut db);
+        db.finish().unwrap()
+    }
+
+    /// Everything in a database except source locations, by name
+    fn dump(conn: &Connection) -> Vec<String> {
+        let queries = [
+            "SELECT name FROM dz_nodes",
+            "SELECT left_name || ' ' || right_name FROM dz_edges",
+            "SELECT name || ' ' || lines FROM dz_node_lines",
+            "SELECT name || ' ' || tag FROM dz_node_tags",
+            "SELECT name || ' ' || key || ' ' || value FROM dz_attributes \
+             INNER JOIN dz_nodes ON dz_nodes.id = node",
+            "SELECT name || ' ' || remarks FROM dz_remarks \
+             INNER JOIN dz_nodes ON dz_nodes.id = node",
+            "SELECT name || ' ' || filename || start || end FROM dz_file_ranges \
+             INNER JOIN dz_nodes ON dz_nodes.id = node",
+            "SELECT name || ' ' || front || back FROM dz_flashcards \
+             INNER JOIN dz_nodes ON dz_nodes.id = node",
+            "SELECT remarks FROM dz_connection_remarks",
+            "SELECT namespace || ' ' || remarks FROM dz_graph_remarks",
+            "SELECT alias || ' ' || path FROM dz_aliases",
+            "SELECT task FROM dz_todo",
+        ];
+        let mut out = vec![];
+        for sql in queries {
+            let mut stmt = conn.prepare(sql).unwrap();
+            let mut rows: Vec<String> = stmt
+                .query_map([], |r| r.get(0))
+                .unwrap()
+                .map(|r| r.unwrap())
+                .collect();
+            rows.sort();
+            out.extend(rows);
+        }
+        out
+    }
+
+    const CODE: &str = concat!(
+        "al @top a/b\n",
+        "ns a\n",
+        "gr about a\n",
+        "nn b\n",
+        "ln  spaced \n",
+        "rm a remark\n",
+        "tg y x\n",
+        "at k v\n",
+        "at flag\n",
+        "fr f.c 3\n",
+        "nn c\n",
+        "td later\n",
+        "ff front\n",
+        "fb back\n",
+        "co b c\n",
+        "cr why\n",
+        "ns a/d\n",
+        "nn e\n",
+        "cx $ a/c\n",
+        "ns z\n",
+        "gr no nodes here\n",
+    );
+
+    #[test]
+    fn test_round_trip() {
+        let conn = compile(&[("one.dz", CODE)]);
+
+        for layout in [Layout::Namespace, Layout::File] {
+            let files = decompile(&conn, layout).unwrap();
+            let sources: Vec<(String, &str)> = files
+                .iter()
+                .map(|(path, code)| (path.display().to_string(), code.as_str()))
+                .collect();
+            let sources: Vec<(&str, &str)> =
+                sources.iter().map(|(p, c)| (p.as_str(), *c)).collect();
+            assert_eq!(dump(&compile(&sources)), dump(&conn));
+        }
+    }
+
+    #[test]
+    fn test_layout() {
+        let conn = compile(&[("one.dz", CODE), ("/abs/../two.dz", "ns a\nnn f\nco f b\n")]);
+
+        let files = decompile(&conn, Layout::Namespace).unwrap();
+        let paths: Vec<String> = files.keys().map(|p| p.display().to_string()).collect();
+        assert_eq!(paths, ["a/d.dz", "a.dz", "z.dz"]);
+        assert_eq!(files[Path::new("a/d.dz")], "ns a/d\nnn e\ncx $ a/c\n");
+
+        let files = decompile(&conn, Layout::File).unwrap();
+        let paths: Vec<String> = files.keys().map(|p| p.display().to_string()).collect();
+        assert_eq!(paths, ["abs/two.dz", "one.dz", "z.dz"]);
+        assert_eq!(files[Path::new("abs/two.dz")], "ns a\nnn f\nco $ b\n");
+        assert_eq!(
+            files[Path::new("one.dz")],
+            concat!(
+                "al @top a/b\n",
+                "\n",
+                "ns a\n",
+                "gr about a\n",
+                "\n",
+                "nn b\n",
+                "ln  spaced \n",
+                "rm a remark\n",
+                "fr f.c 3\n",
+                "tg x y\n",
+                "at flag\n",
+                "at k v\n",
+                "co $ c\n",
+                "cr why\n",
+                "\n",
+                "nn c\n",
+                "td later\n",
+                "ff front\n",
+                "fb back\n",
+                "\n",
+                "ns a/d\n",
+                "nn e\n",
+                "cx $ a/c\n",
+            )
+        );
+    }
+
+    #[test]
+    fn test_namespace_order() {
+        let conn = compile(&[("one.dz", "ns a\nnn x\nnn y\n"), ("two.dz", "ns a\nnn z\n")]);
+        conn.execute_batch(concat!(
+            "INSERT INTO dz_nodes(name, position) VALUES('bare', 4), ('', 5), ('a/', 6);\n",
+            "INSERT INTO dz_connections(left, right) VALUES(1, 4);\n",
+        ))
+        .unwrap();
+
+        let files = decompile(&conn, Layout::Namespace).unwrap();
+        let paths: Vec<String> = files.keys().map(|p| p.display().to_string()).collect();
+        assert_eq!(paths, ["a.dz"]);
+        assert_eq!(files[Path::new("a.dz")], "ns a\nnn x\n\nnn y\n\nnn z\n");
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let conn = Connection::open_in_memory().unwrap();
+        assert_eq!(
+            json_lines(&conn, r#"["a \"b\"", "c\\d", "é"]"#),
+            ["a \"b\"", "c\\d", "é"]
+        );
+        assert!(json_lines(&conn, r#"["a", 1"#).is_empty());
+        assert!(json_lines(&conn, r#"{"a": "b"}"#).is_empty());
+        assert_eq!(json_lines(&conn, r#"["a", 1, null]"#), ["a"]);
+    }
+}
@@ -0,0 +1,235 @@
+//! Graphviz DOT export. Nodes are grouped into clusters by
+//! namespace. Edges with connection remarks are labelled,
+//! and nodes with TODOs or tags are styled so they stand out.
+
+use crate::dagzet::{in_namespace, namespace, DagZet, ReturnCode};
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::io;
+
+/// Restricts which part of the graph gets drawn
+#[derive(Default)]
+pub struct DotOptions {
+    /// Only draw nodes in this namespace (or below it)
+    pub namespace: Option<String>,
+    /// Only draw nodes within this many hops of a node,
+    /// following edges in either direction
+    pub around: Option<(String, usize)>,
+}
+
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+fn quote(s: &str) -> String {
+    format!("\"{}\"", escape(s))
+}
+
+/// Node IDs reachable from start within some number of hops
+fn neighbourhood(edges: &[[u32; 2]], start: u32, hops: usize) -> HashSet<u32> {
+    let mut adj: HashMap<u32, Vec<u32>> = HashMap::new();
+    for [left, right] in edges {
+        adj.entry(*left).or_default().push(*right);
+        adj.entry(*right).or_default().push(*left);
+    }
+
+    let mut found = HashSet::from([start]);
+    let mut queue = VecDeque::from([(start, 0)]);
+
+    while let Some((id, depth)) = queue.pop_front() {
+        if depth == hops {
+            continue;
+        }
+        for next in adj.get(&id).into_iter().flatten() {
+            if found.insert(*next) {
+                queue.push_back((*next, depth + 1));
+            }
+        }
+    }
+
+    found
+}
+
+fn node_attributes(dz: &DagZet, id: u32) -> String {
+    let name = &dz.nodelist[id as usize - 1];
+    let label = name.rsplit('/').next().unwrap_or(name);
+    let mut attrs = vec![format!("label={}", quote(label))];
+
+    if let Some(tags) = dz.tags.get(&id).filter(|tags| !tags.is_empty()) {
+        let tags: Vec<&str> = tags.iter().map(|s| s.as_str()).collect();
+        attrs.push("style=filled".to_string());
+        attrs.push("fillcolor=lightgrey".to_string());
+        attrs.push(format!("tooltip={}", quote(&tags.join(" "))));
+    }
+
+    if dz.todos.contains_key(&id) {
+        attrs.push("color=red".to_string());
+        attrs.push("penwidth=2".to_string());
+    }
+
+    attrs.join(", ")
+}
+
+/// Write the graph (or part of it) in the DOT language
+pub fn generate(dz: &DagZet, opts: &DotOptions, f: &mut impl io::Write) -> Result<(), ReturnCode> {
+    let edges = dz.generate_edges();
+
+    let mut include: Vec<u32> = (1..=dz.nodelist.len() as u32).collect();
+
+    if let Some(ns) = &opts.namespace {
+        include.retain(|id| in_namespace(&dz.nodelist[*id as usize - 1], ns));
+    }
+
+    if let Some((name, hops)) = &opts.around {
+        let near = neighbourhood(&edges, dz.lookup(name)?, *hops);
+        include.retain(|id| near.contains(id));
+    }
+
+    let included: HashSet<u32> = include.iter().copied().collect();
+
+    // group by namespace, keeping creation order within each
+    let mut clusters: BTreeMap<&str, Vec<u32>> = BTreeMap::new();
+    for id in &include {
+        let ns = namespace(&dz.nodelist[*id as usize - 1]);
+        clusters.entry(ns).or_default().push(*id);
+    }
+
+    let _ = f.write_all(b"digraph dagzet {\n");
+
+    for (ns, ids) in &clusters {
+        let _ = writeln!(f, "    subgraph {} {{", quote(&format!("cluster_{}", ns)));
+        let _ = writeln!(f, "        label={};", quote(ns));
+        for id in ids {
+            let name = &dz.nodelist[*id as usize - 1];
+            let _ = writeln!(f, "        {} [{}];", quote(name), node_attributes(dz, *id));
+        }
+        let _ = f.write_all(b"    }\n");
+    }
+
+    for (idx, co) in dz.connections.iter().enumerate() {
+        let (left, right) = match (dz.nodes.get(&co[0]), dz.nodes.get(&co[1])) {
+            (Some(left), Some(right)) => (left, right),
+            _ => continue,
+        };
+
+        if !(included.contains(left) && included.contains(right)) {
+            continue;
+        }
+
+        let label = match dz.connection_remarks.get(&idx) {
+            Some(remarks) => {
+                let remarks: Vec<String> = remarks.iter().map(|s| escape(s)).collect();
+                format!(" [label=\"{}\"]", remarks.join("\\n"))
+            }
+            None => "".to_string(),
+        };
+
+        let _ = writeln!(f, "    {} -> {}{};", quote(&co[0]), quote(&co[1]), label);
+    }
+
+    let _ = f.write_all(b"}\n");
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> DagZet {
+        let mut dz = DagZet::new();
+        let code = [
+            "ns a",
+            "nn b",
+            "td finish this",
+            "nn c",
+            "tg x",
+            "co b c",
+            "cr because",
+            "ns a/d",
+            "nn e",
+            "tg",
+            "nn f",
+            "co f e",
+            "ns a",
+            "co d/e c",
+            "al c a/c",
+        ];
+        for line in code {
+            dz.parse_line_with_result(line).unwrap();
+        }
+        dz
+    }
+
+    fn render(dz: &DagZet, opts: &DotOptions) -> String {
+        let mut out: Vec<u8> = vec![];
+        generate(dz, opts, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_generate() {
+        let out = render(&graph(), &DotOptions::default());
+
+        let expected = concat!(
+            "digraph dagzet {\n",
+            "    subgraph \"cluster_a\" {\n",
+            "        label=\"a\";\n",
+            "        \"a/b\" [label=\"b\", color=red, penwidth=2];\n",
+            "        \"a/c\" [label=\"c\", style=filled, fillcolor=lightgrey, tooltip=\"x\"];\n",
+            "    }\n",
+            "    subgraph \"cluster_a/d\" {\n",
+            "        label=\"a/d\";\n",
+            "        \"a/d/e\" [label=\"e\"];\n",
+            "        \"a/d/f\" [label=\"f\"];\n",
+            "    }\n",
+            "    \"a/b\" -> \"a/c\" [label=\"because\"];\n",
+            "    \"a/d/f\" -> \"a/d/e\";\n",
+            "    \"a/d/e\" -> \"a/c\";\n",
+            "}\n",
+        );
+
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn test_namespace_filter() {
+        let opts = DotOptions {
+            namespace: Some("a/d".to_string()),
+            ..Default::default()
+        };
+        let out = render(&graph(), &opts);
+        assert!(!out.contains("\"a/b\""));
+        assert!(out.contains("\"a/d/f\" -> \"a/d/e\";"));
+        assert!(!out.contains("\"a/d/e\" -> \"a/c\";"));
+    }
+
+    #[test]
+    fn test_neighbourhood() {
+        let opts = DotOptions {
+            around: Some(("a/c".to_string(), 1)),
+            ..Default::default()
+        };
+        let out = render(&graph(), &opts);
+        assert!(out.contains("\"a/b\" ["));
+        assert!(out.contains("\"a/d/e\" ["));
+        assert!(!out.contains("\"a/d/f\" ["));
+
+        for name in ["?c", "@c"] {
+            let opts = DotOptions {
+                around: Some((name.to_string(), 1)),
+                ..Default::default()
+            };
+            assert_eq!(render(&graph(), &opts), out);
+        }
+
+        let opts = DotOptions {
+            around: Some(("a/nope".to_string(), 1)),
+            ..Default::default()
+        };
+        let mut out: Vec<u8> = vec![];
+        assert!(matches!(
+            generate(&graph(), &opts, &mut out),
+            Err(ReturnCode::UnknownNode(name)) if name == "a/nope"
+        ));
+    }
+}
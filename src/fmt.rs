@@ -0,0 +1,143 @@
+//! Rewrites dagzet code into a canonical form. Every command
+//! name is followed by a single space, and arguments that get
+//! split into words are joined with single spaces. Free text
+//! (lines, remarks, comments) keeps its leading whitespace but
+//! loses trailing whitespace. Tags are sorted. Blank lines are
+//! kept for grouping, but runs of them are collapsed into one,
+//! and the file ends with exactly one newline.
+//!
+//! Formatting is idempotent: formatted code formats to itself.
+
+use crate::parser::{self, Command, ParseError, Statement};
+
+fn words(args: &str) -> Vec<&str> {
+    args.split_whitespace().collect()
+}
+
+/// The canonical arguments of a statement
+fn canonical_args(stmt: &Statement) -> String {
+    let args = stmt.args.as_str();
+    match &stmt.command {
+        Command::Namespace(arg)
+        | Command::NewNode(arg)
+        | Command::Include(arg)
+        | Command::Equation(arg)
+        | Command::Page(arg) => arg.to_string(),
+        Command::Tags(tags) => {
+            let mut tags: Vec<&str> = tags.iter().map(|s| s.as_str()).collect();
+            tags.sort();
+            tags.join(" ")
+        }
+        Command::Alias(name, _) => {
+            let mut words = words(args);
+            words[0] = name;
+            words.join(" ")
+        }
+        Command::Connect(_, _)
+        | Command::ConnectExternal(_, _)
+        | Command::FileRange { .. }
+        | Command::Hyperlink(_)
+        | Command::SelectNode(_)
+        | Command::Attribute(_, _) => words(args).join(" "),
+        _ => args.trim_end().to_string(),
+    }
+}
+
+/// Format a single line, which has already been parsed
+pub fn format_statement(stmt: &Statement) -> String {
+    let args = canonical_args(stmt);
+    let name = stmt.command.name();
+    if args.is_empty() {
+        name.to_string()
+    } else {
+        format!("{} {}", name, args)
+    }
+}
+
+/// Format dagzet code. Code that doesn't parse can't be
+/// formatted, so every parse error is returned instead.
+pub fn format(source: &str) -> Result<String, Vec<ParseError>> {
+    let mut out: Vec<String> = vec![];
+    let mut errors = vec![];
+    let mut blank = false;
+
+    for (linum, line) in (1..).zip(source.lines()) {
+        if line.trim().is_empty() {
+            blank = !out.is_empty();
+            continue;
+        }
+
+        match parser::parse_line(line, linum) {
+            Ok(Some(stmt)) => {
+                if blank {
+                    out.push(String::new());
+                    blank = false;
+                }
+                out.push(format_statement(&stmt));
+            }
+            Ok(None) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let mut formatted = out.join("\n");
+    if !formatted.is_empty() {
+        formatted.push('\n');
+    }
+    Ok(formatted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dagzet::ReturnCode;
+
+    #[test]
+    fn test_format() {
+        let code = concat!(
+            "\n",
+            "zz a comment   \n",
+            "ns  a \n",
+            "nn b\t\n",
+            "ln   indented text  \n",
+            "tg  z   x y\n",
+            "\n",
+            "   \n",
+            "\n",
+            "co b   c\n",
+            "al @c a/c\n",
+            "at key    value\n",
+            "ln \n",
+            "\n",
+        );
+
+        let expected = concat!(
+            "zz a comment\n",
+            "ns a\n",
+            "nn b\n",
+            "ln   indented text\n",
+            "tg x y z\n",
+            "\n",
+            "co b c\n",
+            "al c a/c\n",
+            "at key value\n",
+            "ln\n",
+        );
+
+        let formatted = format(code).unwrap();
+        assert_eq!(formatted, expected);
+        assert_eq!(format(&formatted).unwrap(), formatted);
+    }
+
+    #[test]
+    fn test_format_errors() {
+        let errors = format("ns a\nxx nope\nco a\n").unwrap_err();
+        let lines: Vec<u32> = errors.iter().map(|e| e.span.line).collect();
+        assert_eq!(lines, [2, 3]);
+        assert!(matches!(errors[1].reason, ReturnCode::NotEnoughArgs));
+    }
+}
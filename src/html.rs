@@ -0,0 +1,542 @@
+//! Static HTML export, for browsing the graph offline. There
+//! is a page for every node, a page for every namespace with
+//! its graph remarks, and an index of tags. Pages link to each
+//! other with relative paths, so the output directory can be
+//! opened straight from disk or copied anywhere.
+//!
+//! Node pages go in node/, mirroring the node name, so a/b/c
+//! is node/a/b/c.html. Namespace pages go in ns/ the same way.
+//! Characters that aren't safe in a filename or a URL are
+//! written as ~XX, one per byte.
+
+use crate::dagzet::{namespace, DagZet};
+use crate::text::escape_html;
+use std::collections::{BTreeMap, BTreeSet};
+
+const STYLE: &str = "\
+body { font-family: sans-serif; max-width: 50em; margin: 2em auto; padding: 0 1em; }
+nav a { margin-right: 1em; }
+.todo { color: #b00; }
+.source, .namespace { color: #666; }
+.lines { white-space: pre-line; }
+img { max-width: 100%; }
+";
+
+/// A name as a relative path, one segment per part of the
+/// name, that is the same on disk and in a link
+fn path(name: &str) -> String {
+    let segments: Vec<String> = name
+        .split('/')
+        .map(|segment| {
+            let mut out = String::new();
+            for (i, b) in segment.bytes().enumerate() {
+                match b {
+                    b'.' if i == 0 => out.push_str("~2E"),
+                    b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' => {
+                        out.push(b as char)
+                    }
+                    _ => out.push_str(&format!("~{:02X}", b)),
+                }
+            }
+            out
+        })
+        .collect();
+    segments.join("/")
+}
+
+fn node_page(name: &str) -> String {
+    format!("node/{}.html", path(name))
+}
+
+fn namespace_page(ns: &str) -> String {
+    format!("ns/{}.html", path(ns))
+}
+
+/// Links to things outside the site, like URLs, are left
+/// alone. Anything else is relative to the output directory.
+fn media(root: &str, src: &str) -> String {
+    if src.contains("://") || src.starts_with('/') {
+        escape_html(src)
+    } else {
+        escape_html(&format!("{}{}", root, src))
+    }
+}
+
+/// Whether a hyperlink is safe to follow: http and https
+/// URLs, and relative ones. Other schemes, like javascript:,
+/// could run code when clicked.
+fn linkable(url: &str) -> bool {
+    // browsers skip control characters when finding the scheme
+    if url.chars().any(|c| c.is_control()) {
+        return false;
+    }
+
+    let scheme = match url.find([':', '/', '?', '#']) {
+        Some(pos) if url[pos..].starts_with(':') => &url[..pos],
+        _ => return true,
+    };
+
+    scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https")
+}
+
+/// A whole page. Everything is relative to root, which leads
+/// back up to the output directory.
+fn page(root: &str, title: &str, body: &str) -> String {
+    format!(
+        concat!(
+            "<!DOCTYPE html>\n",
+            "<html>\n",
+            "<head>\n",
+            "<meta charset=\"utf-8\">\n",
+            "<title>{title}</title>\n",
+            "<link rel=\"stylesheet\" href=\"{root}style.css\">\n",
+            "</head>\n",
+            "<body>\n",
+            "<nav><a href=\"{root}index.html\">index</a>",
+            "<a href=\"{root}tags.html\">tags</a></nav>\n",
+            "<h1>{title}</h1>\n",
+            "{body}",
+            "</body>\n",
+            "</html>\n",
+        ),
+        root = root,
+        title = escape_html(title),
+        body = body,
+    )
+}
+
+/// The way back up to the output directory from a page
+fn root(page: &str) -> String {
+    "../".repeat(page.matches('/').count())
+}
+
+struct Site<'a> {
+    dz: &'a DagZet,
+    /// For each node, (parent, connection index) pairs
+    parents: BTreeMap<u32, Vec<(&'a str, usize)>>,
+    /// For each node, (child, connection index) pairs
+    children: BTreeMap<u32, Vec<(&'a str, usize)>>,
+}
+
+impl<'a> Site<'a> {
+    fn new(dz: &'a DagZet) -> Self {
+        let mut parents: BTreeMap<u32, Vec<(&str, usize)>> = BTreeMap::new();
+        let mut children: BTreeMap<u32, Vec<(&str, usize)>> = BTreeMap::new();
+
+        for (idx, [left, right]) in dz.connections.iter().enumerate() {
+            if let Some(id) = dz.nodes.get(left) {
+                parents.entry(*id).or_default().push((right, idx));
+            }
+            if let Some(id) = dz.nodes.get(right) {
+                children.entry(*id).or_default().push((left, idx));
+            }
+        }
+
+        Site {
+            dz,
+            parents,
+            children,
+        }
+    }
+
+    fn name(&self, id: u32) -> &'a str {
+        &self.dz.nodelist[id as usize - 1]
+    }
+
+    /// A link to a node, or just its name if it isn't part
+    /// of the graph (from "cx")
+    fn link(&self, root: &str, name: &str) -> String {
+        if self.dz.nodes.contains_key(name) {
+            format!(
+                "<a href=\"{}{}\">{}</a>",
+                root,
+                node_page(name),
+                escape_html(name)
+            )
+        } else {
+            escape_html(name)
+        }
+    }
+
+    /// A list of connected nodes, with their connection remarks
+    fn edges(&self, root: &str, heading: &str, edges: Option<&Vec<(&str, usize)>>) -> String {
+        let edges = match edges {
+            Some(edges) if !edges.is_empty() => edges,
+            _ => return String::new(),
+        };
+
+        let mut html = format!("<h2>{}</h2>\n<ul>\n", heading);
+        for (name, idx) in edges {
+            html.push_str(&format!("<li>{}", self.link(root, name)));
+            if let Some(remarks) = self.dz.connection_remarks.get(idx) {
+                html.push_str(&format!(": {}", escape_html(&remarks.join(" "))));
+            }
+            html.push_str("</li>\n");
+        }
+        html.push_str("</ul>\n");
+        html
+    }
+
+    fn node(&self, id: u32, source: Option<&str>) -> String {
+        let dz = self.dz;
+        let name = self.name(id);
+        let root = root(&node_page(name));
+        let mut body = String::new();
+
+        let ns = namespace(name);
+        if !ns.is_empty() {
+            body.push_str(&format!(
+                "<p class=\"namespace\">in <a href=\"{}{}\">{}</a></p>\n",
+                root,
+                namespace_page(ns),
+                escape_html(ns)
+            ));
+        }
+
+        if let Some(todo) = dz.todos.get(&id) {
+            body.push_str(&format!(
+                "<p class=\"todo\">TODO: {}</p>\n",
+                escape_html(todo)
+            ));
+        }
+
+        if let Some(lines) = dz.lines.get(&id) {
+            body.push_str(&format!(
+                "<p class=\"lines\">{}</p>\n",
+                escape_html(&lines.join("\n"))
+            ));
+        }
+
+        for eq in dz.equations.get(&id).into_iter().flatten() {
+            body.push_str(&format!(
+                "<pre class=\"equation\">{}</pre>\n",
+                escape_html(eq)
+            ));
+        }
+
+        if let Some(image) = dz.images.get(&id) {
+            body.push_str(&format!(
+                "<p><img src=\"{}\" alt=\"{}\"></p>\n",
+                media(&root, image),
+                escape_html(name)
+            ));
+        }
+
+        if let Some(audio) = dz.audio.get(&id) {
+            body.push_str(&format!(
+                "<p><audio controls src=\"{}\"></audio></p>\n",
+                media(&root, audio)
+            ));
+        }
+
+        if let Some(url) = dz.hyperlinks.get(&id) {
+            let link = escape_html(url);
+            if linkable(url) {
+                body.push_str(&format!("<p><a href=\"{}\">{}</a></p>\n", link, link));
+            } else {
+                body.push_str(&format!("<p>{}</p>\n", link));
+            }
+        }
+
+        if let Some(fr) = dz.file_ranges.get(&id) {
+            let range = match (fr.start, fr.end) {
+                (-1, _) => String::new(),
+                (start, -1) => format!(", from line {}", start),
+                (start, end) => format!(", lines {}-{}", start, end),
+            };
+            body.push_str(&format!(
+                "<p>File: <code>{}</code>{}</p>\n",
+                escape_html(&fr.filename),
+                range
+            ));
+        }
+
+        if let Some(page) = dz.pages.get(&id) {
+            body.push_str(&format!("<p>Page: {}</p>\n", escape_html(page)));
+        }
+
+        if let Some(card) = dz.flashcards.get(&id) {
+            body.push_str(&format!(
+                concat!(
+                    "<h2>Flashcard</h2>\n",
+                    "<p class=\"lines\">{}</p>\n",
+                    "<details><summary>Answer</summary>",
+                    "<p class=\"lines\">{}</p></details>\n",
+                ),
+                escape_html(&card.front.join("\n")),
+                escape_html(&card.back.join("\n"))
+            ));
+        }
+
+        if let Some(remarks) = dz.node_remarks.get(&id) {
+            body.push_str(&format!(
+                "<h2>Remarks</h2>\n<p class=\"lines\">{}</p>\n",
+                escape_html(&remarks.join("\n"))
+            ));
+        }
+
+        if let Some(tags) = dz.tags.get(&id).filter(|tags| !tags.is_empty()) {
+            body.push_str("<h2>Tags</h2>\n<ul>\n");
+            for tag in tags {
+                body.push_str(&format!(
+                    "<li><a href=\"{}tags.html#{}\">{}</a></li>\n",
+                    root,
+                    escape_html(&path(tag)),
+                    escape_html(tag)
+                ));
+            }
+            body.push_str("</ul>\n");
+        }
+
+        if let Some(attrs) = dz.attr.get(&id).filter(|attrs| !attrs.is_empty()) {
+            body.push_str("<h2>Attributes</h2>\n<dl>\n");
+            for (key, value) in attrs {
+                body.push_str(&format!("<dt>{}</dt>", escape_html(key)));
+                if let Some(value) = value {
+                    body.push_str(&format!("<dd>{}</dd>", escape_html(value)));
+                }
+                body.push('\n');
+            }
+            body.push_str("</dl>\n");
+        }
+
+        body.push_str(&self.edges(&root, "Parents", self.parents.get(&id)));
+        body.push_str(&self.edges(&root, "Children", self.children.get(&id)));
+
+        if let (Some(filename), Some(linum)) = (source, dz.noderefs.get(&id)) {
+            body.push_str(&format!(
+                "<p class=\"source\">{}:{}</p>\n",
+                escape_html(filename),
+                linum
+            ));
+        }
+
+        page(&root, name, &body)
+    }
+
+    /// A list of nodes, with the first line of each
+    fn node_list(&self, root: &str, ids: &[u32]) -> String {
+        let mut html = String::from("<ul>\n");
+        for id in ids {
+            html.push_str(&format!("<li>{}", self.link(root, self.name(*id))));
+            if let Some(line) = self.dz.lines.get(id).and_then(|lines| lines.first()) {
+                html.push_str(&format!(": {}", escape_html(line)));
+            }
+            html.push_str("</li>\n");
+        }
+        html.push_str("</ul>\n");
+        html
+    }
+
+    fn graph_remarks(&self, ns: &str) -> String {
+        match self.dz.graph_remarks.get(ns) {
+            Some(remarks) => format!(
+                "<p class=\"lines\">{}</p>\n",
+                escape_html(&remarks.join("\n"))
+            ),
+            None => String::new(),
+        }
+    }
+
+    fn namespace(&self, ns: &str, ids: &[u32]) -> String {
+        let root = root(&namespace_page(ns));
+        let mut body = self.graph_remarks(ns);
+        if !ids.is_empty() {
+            body.push_str("<h2>Nodes</h2>\n");
+            body.push_str(&self.node_list(&root, ids));
+        }
+        page(&root, ns, &body)
+    }
+
+    fn index(&self, namespaces: &BTreeMap<&str, Vec<u32>>) -> String {
+        let mut body = self.graph_remarks("");
+
+        let named: Vec<_> = namespaces.iter().filter(|(ns, _)| !ns.is_empty()).collect();
+        if !named.is_empty() {
+            body.push_str("<h2>Namespaces</h2>\n<ul>\n");
+            for (ns, ids) in named {
+                body.push_str(&format!(
+                    "<li><a href=\"{}\">{}</a> ({})</li>\n",
+                    namespace_page(ns),
+                    escape_html(ns),
+                    ids.len()
+                ));
+            }
+            body.push_str("</ul>\n");
+        }
+
+        if let Some(ids) = namespaces.get("").filter(|ids| !ids.is_empty()) {
+            body.push_str("<h2>Nodes</h2>\n");
+            body.push_str(&self.node_list("", ids));
+        }
+
+        page("", "Index", &body)
+    }
+
+    fn tags(&self) -> String {
+        let mut tagged: BTreeMap<&str, Vec<u32>> = BTreeMap::new();
+        for (id, tags) in &self.dz.tags {
+            for tag in tags {
+                tagged.entry(tag).or_default().push(*id);
+            }
+        }
+
+        let mut body = String::new();
+        for (tag, ids) in &tagged {
+            body.push_str(&format!(
+                "<h2 id=\"{}\">{}</h2>\n",
+                escape_html(&path(tag)),
+                escape_html(tag)
+            ));
+            body.push_str(&self.node_list("", ids));
+        }
+
+        page("", "Tags", &body)
+    }
+}
+
+/// The pages of the site, keyed by their path in the output
+/// directory. The source callback gives the name of the file
+/// a node came from.
+pub fn generate<'a>(
+    dz: &'a DagZet,
+    source: impl Fn(u32) -> Option<&'a str>,
+) -> BTreeMap<String, String> {
+    let site = Site::new(dz);
+    let mut pages = BTreeMap::new();
+
+    // Every namespace with a node or graph remarks, and the
+    // nodes in it, in the order they were made
+    let mut namespaces: BTreeMap<&str, Vec<u32>> = BTreeMap::new();
+    for ns in dz.graph_remarks.keys() {
+        namespaces.entry(ns).or_default();
+    }
+    for id in 1..=dz.nodelist.len() as u32 {
+        namespaces
+            .entry(namespace(site.name(id)))
+            .or_default()
+            .push(id);
+        pages.insert(node_page(site.name(id)), site.node(id, source(id)));
+    }
+
+    let named: BTreeSet<_> = namespaces.keys().filter(|ns| !ns.is_empty()).collect();
+    for ns in named {
+        pages.insert(namespace_page(ns), site.namespace(ns, &namespaces[ns]));
+    }
+
+    pages.insert("index.html".to_string(), site.index(&namespaces));
+    pages.insert("tags.html".to_string(), site.tags());
+    pages.insert("style.css".to_string(), STYLE.to_string());
+
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::Compiler;
+
+    #[test]
+    fn test_path() {
+        assert_eq!(path("a/b_c/d-1.2"), "a/b_c/d-1.2");
+        assert_eq!(path("a/../it's é"), "a/~2E./it~27s~20~C3~A9");
+        assert_eq!(root("node/a/b.html"), "../../");
+    }
+
+    #[test]
+    fn test_linkable() {
+        for url in [
+            "https://example.com/a:b",
+            "HTTP://example.com",
+            "b.html",
+            "../a?x=y:z",
+        ] {
+            assert!(linkable(url), "{} should be linked", url);
+        }
+        for url in [
+            "javascript:alert(1)",
+            "JavaScript:x",
+            "data:text/html,x",
+            "\u{1}javascript:x",
+        ] {
+            assert!(!linkable(url), "{} should not be linked", url);
+        }
+    }
+
+    #[test]
+    fn test_generate() {
+        let code = [
+            "ns a",
+            "gr about <a>",
+            "nn b",
+            "ln it's a line",
+            "tg z y",
+            "im img/b.png",
+            "nn c",
+            "ff front",
+            "fb back",
+            "fr c.txt 3",
+            "hl javascript:alert(1)",
+            "co b c",
+            "cr why",
+            "ns a/d",
+            "nn e",
+            "tg z",
+            "cx a/d/e x/y",
+        ];
+        let mut compiler = Compiler::new();
+        compiler.parse_reader(Some("a.dz"), code.join("\n").as_bytes());
+
+        let pages = compiler.build().unwrap().generate_html();
+        let paths: Vec<&str> = pages.keys().map(|p| p.as_str()).collect();
+        assert_eq!(
+            paths,
+            [
+                "index.html",
+                "node/a/b.html",
+                "node/a/c.html",
+                "node/a/d/e.html",
+                "ns/a.html",
+                "ns/a/d.html",
+                "style.css",
+                "tags.html",
+            ]
+        );
+
+        let b = &pages["node/a/b.html"];
+        assert!(b.contains("<link rel=\"stylesheet\" href=\"../../style.css\">"));
+        assert!(b.contains("in <a href=\"../../ns/a.html\">a</a>"));
+        assert!(b.contains("<p class=\"lines\">it&#39;s a line</p>"));
+        assert!(b.contains("<img src=\"../../img/b.png\" alt=\"a/b\">"));
+        assert!(b.contains(
+            "<h2>Parents</h2>\n<ul>\n<li><a href=\"../../node/a/c.html\">a/c</a>: why</li>"
+        ));
+        assert!(b.contains("<li><a href=\"../../tags.html#y\">y</a></li>\n<li><a href=\"../../tags.html#z\">z</a></li>"));
+        assert!(b.contains("<p class=\"source\">a.dz:3</p>"));
+
+        let c = &pages["node/a/c.html"];
+        assert!(c.contains(
+            "<h2>Children</h2>\n<ul>\n<li><a href=\"../../node/a/b.html\">a/b</a>: why</li>"
+        ));
+        assert!(c.contains("<p>File: <code>c.txt</code>, from line 3</p>"));
+        assert!(c.contains("<p>javascript:alert(1)</p>"));
+        assert!(!c.contains("href=\"javascript:"));
+        assert!(
+            c.contains("<details><summary>Answer</summary><p class=\"lines\">back</p></details>")
+        );
+
+        let e = &pages["node/a/d/e.html"];
+        assert!(e.contains("<h2>Parents</h2>\n<ul>\n<li>x/y</li>"));
+
+        let ns = &pages["ns/a.html"];
+        assert!(ns.contains("<p class=\"lines\">about &lt;a&gt;</p>"));
+        assert!(ns.contains("<li><a href=\"../node/a/b.html\">a/b</a>: it&#39;s a line</li>"));
+
+        assert!(pages["index.html"].contains("<li><a href=\"ns/a/d.html\">a/d</a> (1)</li>"));
+        assert!(pages["tags.html"].contains(concat!(
+            "<h2 id=\"z\">z</h2>\n<ul>\n",
+            "<li><a href=\"node/a/b.html\">a/b</a>: it&#39;s a line</li>\n",
+            "<li><a href=\"node/a/d/e.html\">a/d/e</a></li>\n",
+        )));
+    }
+}
@@ -0,0 +1,201 @@
+//! JSON export, for feeding the graph to things that aren't
+//! SQLite (web front-ends, mostly). Nodes are written in the
+//! order they were created, one per line, followed by the
+//! edges and the graph remarks.
+
+use crate::dagzet::DagZet;
+use std::collections::BTreeMap;
+use std::io;
+
+/// Encode a string as a JSON string literal
+pub fn string(s: &str) -> String {
+    let mut out = String::from("\"");
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn optional(s: Option<&String>) -> String {
+    match s {
+        Some(s) => string(s),
+        None => "null".to_string(),
+    }
+}
+
+fn array<'a>(items: impl IntoIterator<Item = &'a String>) -> String {
+    let items: Vec<String> = items.into_iter().map(|s| string(s)).collect();
+    format!("[{}]", items.join(","))
+}
+
+fn object(fields: &[(&str, String)]) -> String {
+    let fields: Vec<String> = fields
+        .iter()
+        .map(|(key, val)| format!("{}:{}", string(key), val))
+        .collect();
+    format!("{{{}}}", fields.join(","))
+}
+
+fn lines(map: &BTreeMap<u32, Vec<String>>, id: u32) -> String {
+    array(map.get(&id).into_iter().flatten())
+}
+
+fn node(dz: &DagZet, id: u32, source: Option<&str>) -> String {
+    let tags = dz.tags.get(&id).into_iter().flatten();
+
+    let attributes: Vec<(&str, String)> = dz
+        .attr
+        .get(&id)
+        .into_iter()
+        .flatten()
+        .map(|(k, v)| (k.as_str(), optional(v.as_ref())))
+        .collect();
+
+    let flashcard = match dz.flashcards.get(&id) {
+        Some(card) => object(&[("front", array(&card.front)), ("back", array(&card.back))]),
+        None => "null".to_string(),
+    };
+
+    let file_range = match dz.file_ranges.get(&id) {
+        Some(fr) => object(&[
+            ("filename", string(&fr.filename)),
+            ("start", fr.start.to_string()),
+            ("end", fr.end.to_string()),
+        ]),
+        None => "null".to_string(),
+    };
+
+    let source = match dz.noderefs.get(&id) {
+        Some(linum) => object(&[
+            ("filename", source.map(string).unwrap_or("null".to_string())),
+            ("line", linum.to_string()),
+        ]),
+        None => "null".to_string(),
+    };
+
+    object(&[
+        ("id", id.to_string()),
+        ("name", string(&dz.nodelist[id as usize - 1])),
+        ("lines", lines(&dz.lines, id)),
+        ("remarks", lines(&dz.node_remarks, id)),
+        ("tags", array(tags)),
+        ("hyperlink", optional(dz.hyperlinks.get(&id))),
+        ("todo", optional(dz.todos.get(&id))),
+        ("flashcard", flashcard),
+        ("image", optional(dz.images.get(&id))),
+        ("audio", optional(dz.audio.get(&id))),
+        ("equations", lines(&dz.equations, id)),
+        ("page", optional(dz.pages.get(&id))),
+        ("attributes", object(&attributes)),
+        ("file_range", file_range),
+        ("source", source),
+    ])
+}
+
+/// Write the graph as a single JSON object. The source
+/// callback gives the name of the file a node came from.
+pub fn generate<'a>(
+    dz: &'a DagZet,
+    source: impl Fn(u32) -> Option<&'a str>,
+    f: &mut impl io::Write,
+) {
+    let _ = f.write_all(b"{\"nodes\":[\n");
+
+    let nodes: Vec<String> = (1..=dz.nodelist.len() as u32)
+        .map(|id| node(dz, id, source(id)))
+        .collect();
+    let _ = f.write_all(nodes.join(",\n").as_bytes());
+
+    let _ = f.write_all(b"\n],\"edges\":[\n");
+
+    let edges: Vec<String> = dz
+        .connections
+        .iter()
+        .enumerate()
+        .map(|(idx, co)| {
+            object(&[
+                ("left", string(&co[0])),
+                ("right", string(&co[1])),
+                (
+                    "remarks",
+                    array(dz.connection_remarks.get(&idx).into_iter().flatten()),
+                ),
+            ])
+        })
+        .collect();
+    let _ = f.write_all(edges.join(",\n").as_bytes());
+
+    let _ = f.write_all(b"\n],\"graph_remarks\":");
+
+    let remarks: Vec<(&str, String)> = dz
+        .graph_remarks
+        .iter()
+        .map(|(ns, rm)| (ns.as_str(), array(rm)))
+        .collect();
+    let _ = f.write_all(object(&remarks).as_bytes());
+
+    let _ = f.write_all(b"}\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_string() {
+        assert_eq!(string("a \"b\"\\\n\u{1}"), "\"a \\\"b\\\"\\\\\\n\\u0001\"");
+    }
+
+    #[test]
+    fn test_generate() {
+        let mut dz = DagZet::new();
+        let code = [
+            "ns a",
+            "gr about a",
+            "nn b",
+            "ln it's a \"line\"",
+            "tg z y",
+            "at k v",
+            "nn c",
+            "ff front",
+            "co b c",
+            "cr why",
+        ];
+        for line in code {
+            dz.parse_line_with_result(line).unwrap();
+        }
+
+        let mut out: Vec<u8> = vec![];
+        generate(&dz, |_| Some("a.dz"), &mut out);
+        let out = String::from_utf8(out).unwrap();
+
+        let expected = concat!(
+            "{\"nodes\":[\n",
+            "{\"id\":1,\"name\":\"a/b\",\"lines\":[\"it's a \\\"line\\\"\"],\"remarks\":[],",
+            "\"tags\":[\"y\",\"z\"],\"hyperlink\":null,\"todo\":null,\"flashcard\":null,",
+            "\"image\":null,\"audio\":null,\"equations\":[],\"page\":null,",
+            "\"attributes\":{\"k\":\"v\"},\"file_range\":null,",
+            "\"source\":{\"filename\":\"a.dz\",\"line\":0}},\n",
+            "{\"id\":2,\"name\":\"a/c\",\"lines\":[],\"remarks\":[],",
+            "\"tags\":[],\"hyperlink\":null,\"todo\":null,",
+            "\"flashcard\":{\"front\":[\"front\"],\"back\":[]},",
+            "\"image\":null,\"audio\":null,\"equations\":[],\"page\":null,",
+            "\"attributes\":{},\"file_range\":null,",
+            "\"source\":{\"filename\":\"a.dz\",\"line\":0}}\n",
+            "],\"edges\":[\n",
+            "{\"left\":\"a/b\",\"right\":\"a/c\",\"remarks\":[\"why\"]}\n",
+            "],\"graph_remarks\":{\"a\":[\"about a\"]}}\n",
+        );
+
+        assert_eq!(out, expected);
+    }
+}
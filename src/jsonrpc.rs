@@ -0,0 +1,250 @@
+//! JSON values for the language server's JSON-RPC messages.
+//! Only what the messages need is supported: objects keep
+//! their field order and numbers are plain f64s.
+
+use crate::json::string;
+
+/// A parsed JSON value. Object fields keep their order.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    Object(Vec<(String, Value)>),
+}
+
+impl Value {
+    pub fn object(fields: Vec<(&str, Value)>) -> Value {
+        Value::Object(
+            fields
+                .into_iter()
+                .map(|(k, v)| (k.to_string(), v))
+                .collect(),
+        )
+    }
+
+    pub fn string(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+
+    /// Look up a field of an object
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    /// Follow a path of object fields, such as
+    /// ["textDocument", "uri"]
+    pub fn path(&self, keys: &[&str]) -> Option<&Value> {
+        keys.iter().try_fold(self, |v, key| v.get(key))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Value::Number(n) if *n >= 0.0 && n.fract() == 0.0 => Some(*n as u64),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+
+    /// Encode the value as JSON text
+    pub fn encode(&self) -> String {
+        match self {
+            Value::Null => "null".to_string(),
+            Value::Bool(b) => b.to_string(),
+            Value::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => (*n as i64).to_string(),
+            Value::Number(n) => n.to_string(),
+            Value::String(s) => string(s),
+            Value::Array(items) => {
+                let items: Vec<String> = items.iter().map(|v| v.encode()).collect();
+                format!("[{}]", items.join(","))
+            }
+            Value::Object(fields) => {
+                let fields: Vec<String> = fields
+                    .iter()
+                    .map(|(k, v)| format!("{}:{}", string(k), v.encode()))
+                    .collect();
+                format!("{{{}}}", fields.join(","))
+            }
+        }
+    }
+}
+
+impl From<u32> for Value {
+    fn from(n: u32) -> Value {
+        Value::Number(n as f64)
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| c.is_whitespace()).is_some() {}
+    }
+
+    fn expect(&mut self, word: &str) -> Option<()> {
+        for c in word.chars() {
+            self.chars.next_if_eq(&c)?;
+        }
+        Some(())
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        let mut n = 0;
+        for _ in 0..4 {
+            n = n * 16 + self.chars.next()?.to_digit(16)?;
+        }
+        Some(n)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.expect("\"")?;
+        let mut out = String::new();
+        loop {
+            match self.chars.next()? {
+                '"' => return Some(out),
+                '\\' => match self.chars.next()? {
+                    'n' => out.push('\n'),
+                    'r' => out.push('\r'),
+                    't' => out.push('\t'),
+                    'b' => out.push('\u{8}'),
+                    'f' => out.push('\u{c}'),
+                    'u' => {
+                        let mut code = self.hex4()?;
+                        // surrogate pairs are two escapes in a row
+                        if (0xd800..0xdc00).contains(&code) {
+                            self.expect("\\u")?;
+                            let low = self.hex4()?;
+                            code = 0x10000 + ((code - 0xd800) << 10) + (low.checked_sub(0xdc00)?);
+                        }
+                        out.push(char::from_u32(code)?);
+                    }
+                    c => out.push(c),
+                },
+                c => out.push(c),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Option<f64> {
+        let mut text = String::new();
+        while let Some(c) = self
+            .chars
+            .next_if(|c| c.is_ascii_digit() || "+-.eE".contains(*c))
+        {
+            text.push(c);
+        }
+        text.parse().ok()
+    }
+
+    fn value(&mut self) -> Option<Value> {
+        self.skip_whitespace();
+        let value = match *self.chars.peek()? {
+            'n' => self.expect("null").map(|_| Value::Null)?,
+            't' => self.expect("true").map(|_| Value::Bool(true))?,
+            'f' => self.expect("false").map(|_| Value::Bool(false))?,
+            '"' => Value::String(self.string()?),
+            '[' => {
+                self.chars.next();
+                let mut items = vec![];
+                self.skip_whitespace();
+                if self.chars.next_if_eq(&']').is_none() {
+                    loop {
+                        items.push(self.value()?);
+                        self.skip_whitespace();
+                        match self.chars.next()? {
+                            ',' => continue,
+                            ']' => break,
+                            _ => return None,
+                        }
+                    }
+                }
+                Value::Array(items)
+            }
+            '{' => {
+                self.chars.next();
+                let mut fields = vec![];
+                self.skip_whitespace();
+                if self.chars.next_if_eq(&'}').is_none() {
+                    loop {
+                        self.skip_whitespace();
+                        let key = self.string()?;
+                        self.skip_whitespace();
+                        self.expect(":")?;
+                        fields.push((key, self.value()?));
+                        self.skip_whitespace();
+                        match self.chars.next()? {
+                            ',' => continue,
+                            '}' => break,
+                            _ => return None,
+                        }
+                    }
+                }
+                Value::Object(fields)
+            }
+            _ => Value::Number(self.number()?),
+        };
+        Some(value)
+    }
+}
+
+/// Parse JSON text. Anything that isn't valid JSON gives None.
+pub fn parse(text: &str) -> Option<Value> {
+    let mut parser = Parser {
+        chars: text.chars().peekable(),
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        Some(_) => None,
+        None => Some(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let text = r#" {"a": [1, -2.5, true, null], "b": "x\"\u00e9\ud83d\ude00", "c": {}} "#;
+        let value = parse(text).unwrap();
+
+        assert_eq!(value.path(&["a"]).unwrap().as_array().unwrap().len(), 4);
+        assert_eq!(value.get("b").unwrap().as_str(), Some("x\"é😀"));
+        assert_eq!(value.get("c"), Some(&Value::Object(vec![])));
+        assert_eq!(
+            value.encode(),
+            "{\"a\":[1,-2.5,true,null],\"b\":\"x\\\"é😀\",\"c\":{}}"
+        );
+
+        assert_eq!(parse("[1, 2"), None);
+        assert_eq!(parse("{} x"), None);
+    }
+}
@@ -1,2 +1,21 @@
+pub mod anki;
+pub mod cache;
+pub mod compiler;
+pub mod dagzet;
+#[cfg(feature = "database")]
+pub mod database;
+#[cfg(feature = "database")]
+pub mod decompile;
+pub mod dot;
+pub mod fmt;
+pub mod html;
+pub mod json;
+pub mod jsonrpc;
 pub mod logzet;
+pub mod lsp;
+pub mod parser;
+pub mod query;
 pub mod sqlite;
+pub mod tables;
+pub mod text;
+pub mod trie;
@@ -2,6 +2,8 @@ use crate::logzet::id::WithId;
 use crate::logzet::statement::Statement;
 use crate::logzet::{BlockData, Date, TextBlock, Time};
 use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::fmt;
 
 pub type EntityId = usize;
 
@@ -53,11 +55,82 @@ impl EntityList {
     }
 }
 
-pub fn statements_to_entities(stmts: Vec<Statement>) -> EntityList {
+/// Why a "#!" command couldn't be used
+#[derive(Debug, PartialEq)]
+pub enum CommandErrorReason {
+    UnknownAlias(String),
+    UnknownCommand(String),
+    NotEnoughArgs,
+    /// There is no session, entry or block to connect to yet
+    NothingToConnect,
+    /// "$" was used before any node was connected
+    NoPreviousNode,
+}
+
+impl fmt::Display for CommandErrorReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CommandErrorReason::UnknownAlias(alias) => write!(f, "unknown alias '@{alias}'"),
+            CommandErrorReason::UnknownCommand(cmd) => write!(f, "unknown command '{cmd}'"),
+            CommandErrorReason::NotEnoughArgs => write!(f, "not enough arguments"),
+            CommandErrorReason::NothingToConnect => write!(f, "nothing to connect to yet"),
+            CommandErrorReason::NoPreviousNode => write!(f, "$ used before any node"),
+        }
+    }
+}
+
+/// A "#!" command that couldn't be used, and where it was
+#[derive(Debug)]
+pub struct CommandError {
+    pub linum: usize,
+    /// The command, as it was written
+    pub command: String,
+    pub reason: CommandErrorReason,
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}\n    {}", self.linum, self.reason, self.command)
+    }
+}
+
+impl std::error::Error for CommandError {}
+
+/// Expand an @alias reference (made with "#! al") into
+/// a full dagzet path. Anything else is returned as-is.
+fn expand_alias(
+    aliases: &HashMap<String, String>,
+    arg: &str,
+) -> Result<String, CommandErrorReason> {
+    let alias = match arg.strip_prefix('@') {
+        Some(alias) => alias,
+        None => return Ok(arg.to_string()),
+    };
+
+    let (name, rest) = match alias.split_once('/') {
+        Some((name, rest)) => (name, Some(rest)),
+        None => (alias, None),
+    };
+
+    let path = match aliases.get(name) {
+        Some(path) => path,
+        None => return Err(CommandErrorReason::UnknownAlias(name.to_string())),
+    };
+
+    Ok(match rest {
+        Some(rest) => format!("{}/{}", path, rest),
+        None => path.to_string(),
+    })
+}
+
+/// Turn statements into entities, and the dagzet nodes they
+/// connect to. Stops at the first command that can't be used.
+pub fn statements_to_entities(stmts: Vec<Statement>) -> Result<EntityList, CommandError> {
     let mut entities = vec![];
     let mut curblock: Option<Vec<String>> = None;
     let mut connections: BTreeMap<EntityId, DagzetPathList> = BTreeMap::new();
     let mut last_node: Option<String> = None;
+    let mut aliases: HashMap<String, String> = HashMap::new();
 
     for stmt in stmts {
         if let Statement::Date(date) = stmt {
@@ -105,26 +178,43 @@ pub fn statements_to_entities(stmts: Vec<Statement>) -> EntityList {
         }
 
         if let Statement::Command(cmd) = stmt {
-            let args = cmd.args;
+            let error = |reason| CommandError {
+                linum: cmd.linum,
+                command: format!("#! {}", cmd.args.join(" ")),
+                reason,
+            };
+            let args = &cmd.args;
             if args.is_empty() {
                 continue;
             }
 
+            if args[0] == "al" {
+                if args.len() < 3 {
+                    return Err(error(CommandErrorReason::NotEnoughArgs));
+                }
+                let name = args[1].strip_prefix('@').unwrap_or(&args[1]);
+                let path = expand_alias(&aliases, &args[2]).map_err(error)?;
+                aliases.insert(name.to_string(), path);
+                continue;
+            }
+
             if args[0] != "dz" {
-                // TODO: error handling
-                panic!("Unrecognized command: {}", args[0]);
+                return Err(error(CommandErrorReason::UnknownCommand(
+                    args[0].to_string(),
+                )));
             }
 
             if args.len() < 2 {
-                // TODO: error handling
-                panic!("Not enough args for dz");
+                return Err(error(CommandErrorReason::NotEnoughArgs));
             }
 
             // TODO: get ID
+            // sessions don't have IDs to connect to
             let last_entity_id = match entities.last() {
+                Some(Entity::Session(_)) | None => {
+                    return Err(error(CommandErrorReason::NothingToConnect))
+                }
                 Some(entity) => entity.id(),
-                // TODO: error handling
-                None => panic!("No entity found"),
             };
 
             let con = connections.get_mut(&last_entity_id);
@@ -138,12 +228,12 @@ pub fn statements_to_entities(stmts: Vec<Statement>) -> EntityList {
                         last_node.clone()
                     }
                 } else {
-                    // TODO: error handling
-                    panic!("$ not set");
+                    return Err(error(CommandErrorReason::NoPreviousNode));
                 }
             } else {
-                last_node = Some(args[1].clone());
-                args[1].clone()
+                let node = expand_alias(&aliases, &args[1]).map_err(error)?;
+                last_node = Some(node.clone());
+                node
             };
 
             if let Some(con) = con {
@@ -159,15 +249,16 @@ pub fn statements_to_entities(stmts: Vec<Statement>) -> EntityList {
     if let Some(blk) = curblock {
         entities.push(Entity::Block(BlockData::Text(TextBlock::new(blk))).with_id(entities.len()));
     }
-    EntityList {
+    Ok(EntityList {
         entities,
         connections,
-    }
+    })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::logzet::statement::StatementBuilder;
     use crate::logzet::Command;
     #[test]
     fn test_dz_prev_operator() {
@@ -176,16 +267,19 @@ mod tests {
             Statement::Time(Time::default().hour(12).minute(34)),
             Statement::Command(Command {
                 args: ["dz", "a/b"].into_iter().map(String::from).collect(),
+                ..Default::default()
             }),
             Statement::Time(Time::default().hour(13).minute(37)),
             Statement::Command(Command {
                 args: ["dz", "$"].into_iter().map(String::from).collect(),
+                ..Default::default()
             }),
             Statement::Command(Command {
                 args: ["dz", "$/c"].into_iter().map(String::from).collect(),
+                ..Default::default()
             }),
         ];
-        let entities = statements_to_entities(stmts);
+        let entities = statements_to_entities(stmts).unwrap();
         let connections = entities.connections;
         let generated: Vec<(usize, Vec<String>)> = connections.into_iter().collect();
         let expected: Vec<(usize, Vec<String>)> = [(1, vec!["a/b"]), (2, vec!["a/b", "a/b/c"])]
@@ -194,4 +288,59 @@ mod tests {
             .collect();
         assert_eq!(generated, expected);
     }
+
+    #[test]
+    fn test_dz_alias() {
+        let cmd = |args: &[&str]| {
+            Statement::Command(Command {
+                args: args.iter().map(|s| s.to_string()).collect(),
+                ..Default::default()
+            })
+        };
+        let stmts: Vec<Statement> = vec![
+            Statement::Date(Date::default()),
+            cmd(&["al", "ks", "knowledge/systems"]),
+            Statement::Time(Time::default().hour(12).minute(34)),
+            cmd(&["dz", "@ks"]),
+            cmd(&["dz", "@ks/foo"]),
+        ];
+        let entities = statements_to_entities(stmts).unwrap();
+        let generated: Vec<(usize, Vec<String>)> = entities.connections.into_iter().collect();
+        let expected: Vec<(usize, Vec<String>)> = vec![(
+            1,
+            vec![
+                "knowledge/systems".to_string(),
+                "knowledge/systems/foo".to_string(),
+            ],
+        )];
+        assert_eq!(generated, expected);
+    }
+
+    #[test]
+    fn test_command_errors() {
+        let parse = |lines: &[&str]| {
+            let mut builder = StatementBuilder::new();
+            for line in lines {
+                builder.parse(line.to_string());
+            }
+            statements_to_entities(builder.build()).err()
+        };
+
+        let err = parse(&["@2025-01-01", "@10:00 entry", "", "#! dz @nope/x"]).unwrap();
+        assert_eq!(err.linum, 4);
+        assert_eq!(err.command, "#! dz @nope/x");
+        assert_eq!(
+            err.reason,
+            CommandErrorReason::UnknownAlias("nope".to_string())
+        );
+
+        let err = parse(&["@2025-01-01", "#! al @ks"]).unwrap();
+        assert_eq!(err.reason, CommandErrorReason::NotEnoughArgs);
+        assert_eq!(err.to_string(), "2: not enough arguments\n    #! al @ks");
+
+        let err = parse(&["@2025-01-01", "#! dz a/b"]).unwrap();
+        assert_eq!(err.reason, CommandErrorReason::NothingToConnect);
+
+        assert!(parse(&["@2025-01-01", "#! al @ks a/b", "@10:00", "#! dz @ks/c"]).is_none());
+    }
 }
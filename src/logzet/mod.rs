@@ -6,7 +6,7 @@ mod session;
 pub mod session_tree;
 pub mod statement;
 pub mod tables;
-use entity::{statements_to_entities, EntityId};
+use entity::{statements_to_entities, CommandError, EntityId};
 use id::WithId;
 use session::build_session_map;
 use statement::Statement;
@@ -64,9 +64,11 @@ pub struct TextLine {
 }
 
 /// A command
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Command {
     args: Vec<String>,
+    /// The line it was on, counting from 1
+    linum: usize,
 }
 
 #[derive(Clone)]
@@ -281,10 +283,10 @@ struct Session {
 }
 
 #[allow(dead_code)]
-fn build_sessions(stmts: Vec<Statement>) -> Vec<Session> {
-    let entities = statements_to_entities(stmts);
+fn build_sessions(stmts: Vec<Statement>) -> Result<Vec<Session>, CommandError> {
+    let entities = statements_to_entities(stmts)?;
     let session_map = build_session_map(entities);
-    session_map.into_iter().map(|s| s.into()).collect()
+    Ok(session_map.into_iter().map(|s| s.into()).collect())
 }
 
 #[cfg(test)]
@@ -325,7 +327,7 @@ mod tests {
                 title: "".to_string(),
             }),
         ];
-        let output = build_sessions(stmts.clone());
+        let output = build_sessions(stmts.clone()).unwrap();
 
         // sanity check
         assert_eq!(stmts.len(), output.len());
@@ -397,7 +399,7 @@ mod tests {
                 text: "one more thought".to_string(),
             }),
         ];
-        let sessions = build_sessions(document.clone());
+        let sessions = build_sessions(document.clone()).unwrap();
 
         // Only one document expected
         assert_eq!(sessions.len(), 1);
@@ -465,7 +467,7 @@ mod tests {
             }),
         ];
 
-        let entities = statements_to_entities(document);
+        let entities = statements_to_entities(document).unwrap();
 
         assert_eq!(entities.entities.len(), 7);
     }
@@ -495,6 +497,7 @@ mod tests {
             }),
             cmd(Command {
                 args: vec!["dz".to_string(), "a/b".to_string()],
+                ..Default::default()
             }),
             tl(TextLine {
                 text: "I am writing some words".to_string(),
@@ -510,6 +513,7 @@ mod tests {
             }),
             cmd(Command {
                 args: vec!["dz".to_string(), "g/h".to_string()],
+                ..Default::default()
             }),
             tl(TextLine {
                 text: "this is a thought I had".to_string(),
@@ -517,9 +521,11 @@ mod tests {
             br.clone(),
             cmd(Command {
                 args: vec!["dz".to_string(), "c/d".to_string()],
+                ..Default::default()
             }),
             cmd(Command {
                 args: vec!["dz".to_string(), "e/f".to_string()],
+                ..Default::default()
             }),
             tl(TextLine {
                 text: "this is a another thought I had".to_string(),
@@ -530,7 +536,7 @@ mod tests {
             }),
         ];
 
-        let entities = statements_to_entities(document);
+        let entities = statements_to_entities(document).unwrap();
         assert_eq!(entities.connections.len(), 3);
 
         let mut total_connections = 0;
@@ -610,6 +610,7 @@ mod tests {
             }),
             cmd(Command {
                 args: vec!["dz".to_string(), "a/a".to_string()],
+                ..Default::default()
             }),
             tl(TextLine {
                 text: "I am writing some words".to_string(),
@@ -629,6 +630,7 @@ mod tests {
             br.clone(),
             cmd(Command {
                 args: vec!["dz".to_string(), "a/b".to_string()],
+                ..Default::default()
             }),
             tl(TextLine {
                 text: "this is a another thought I had".to_string(),
@@ -636,6 +638,7 @@ mod tests {
             br.clone(),
             cmd(Command {
                 args: vec!["dz".to_string(), "a/c".to_string()],
+                ..Default::default()
             }),
             tl(TextLine {
                 text: "one more thought".to_string(),
@@ -651,7 +654,7 @@ mod tests {
             }),
         ];
 
-        let entities = statements_to_entities(document);
+        let entities = statements_to_entities(document).unwrap();
 
         let session_map = entities_to_map(&entities.entities);
 
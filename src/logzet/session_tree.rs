@@ -156,7 +156,7 @@ mod tests {
             }),
         ];
 
-        let entities = statements_to_entities(document);
+        let entities = statements_to_entities(document).unwrap();
 
         let session_map = entities_to_map(&entities.entities);
 
@@ -205,7 +205,7 @@ mod tests {
             }),
         ];
 
-        let entities = statements_to_entities(document);
+        let entities = statements_to_entities(document).unwrap();
 
         let session_map = entities_to_map(&entities.entities);
 
@@ -215,4 +215,3 @@ mod tests {
         assert_eq!(tree.blocks.len(), 3);
     }
 }
-
@@ -114,7 +114,10 @@ impl TryFrom<String> for Statement {
                 .skip(1)
                 .map(|s| s.to_string())
                 .collect();
-            return Ok(Statement::Command(Command { args }));
+            return Ok(Statement::Command(Command {
+                args,
+                ..Default::default()
+            }));
         }
 
         Ok(Statement::TextLine(TextLine { text: value }))
@@ -149,6 +152,8 @@ fn title_and_tags(full_title: &str) -> (String, Vec<String>) {
 #[derive(Default)]
 pub struct StatementBuilder {
     statements: Vec<Statement>,
+    /// Lines seen so far, for reporting where commands were
+    linum: usize,
 }
 
 impl StatementBuilder {
@@ -157,10 +162,14 @@ impl StatementBuilder {
     }
 
     pub fn parse(&mut self, line: String) {
+        self.linum += 1;
         if line.is_empty() {
             return;
         }
-        if let Ok(stmt) = line.try_into() {
+        if let Ok(mut stmt) = Statement::try_from(line) {
+            if let Statement::Command(cmd) = &mut stmt {
+                cmd.linum = self.linum;
+            }
             self.statements.push(stmt);
         }
     }
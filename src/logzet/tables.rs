@@ -2,26 +2,27 @@ use crate::logzet::rows::{
     BlockRow as InnerBlockRow, EntityConnectionsRow as InnerEntityConnectionRow,
     EntryRow as InnerEntryRow, SessionRow as InnerSessionRow, SessionRows, TagsRow as InnerTagRow,
 };
-use crate::sqlite::{escape_quotes, Param, ParamType, Row, SQLize, Table};
+use crate::sqlite::{
+    Ids, Output, Param, ParamType, Row, SQLize, SearchTable, Table, Trigger, Value, View, WithIds,
+};
 use std::collections::HashMap;
 
-use std::io;
-
 use super::entity::EntityId;
 use super::rows::EntityRow;
 struct EntityTable;
 
 impl<EntityTable> Row<EntityTable> for EntityRow {
-    fn sqlize_values(&self) -> String {
-        let uuid: String = self.into();
-        format!("'{}'", uuid)
+    fn values(&self) -> Vec<Value> {
+        vec![Value::Text(self.into())]
     }
 }
 
 impl Default for Table<EntityTable> {
     fn default() -> Self {
         let mut con: Table<EntityTable> = Table::new("lz_entities");
-        con.add_column(&Param::new("id", ParamType::Text));
+        con.add_column(&Param::new("rowid", ParamType::IntegerPrimaryKey));
+        con.add_column(&Param::new("id", ParamType::TextNotNull));
+        con.add_unique(&["id"]);
         con
     }
 }
@@ -33,38 +34,44 @@ struct SessionRow<'a> {
     lookup: &'a HashMap<EntityId, String>,
 }
 
-fn uuid_lookup(lookup: &HashMap<EntityId, String>, entity_id: Option<EntityId>) -> String {
+const ENTITY_LOOKUP: &str = "(SELECT rowid FROM lz_entities WHERE id IS ?)";
+
+/// Row IDs for every entity, numbered in the order the
+/// sessions insert them
+pub fn entity_ids(rows: &[SessionRows]) -> Ids {
+    let mut ids = Ids::default();
+    for (row, rowid) in rows.iter().flat_map(|r| &r.entities).zip(1..) {
+        ids.insert(ENTITY_LOOKUP, &String::from(row), rowid);
+    }
+    ids
+}
+
+/// An entity as a row ID, looked up by its UUID. Entities
+/// that aren't given are -1, and unknown ones are -2.
+fn entity_value(lookup: &HashMap<EntityId, String>, entity_id: Option<EntityId>) -> Value {
     if let Some(entity_id) = entity_id {
         if let Some(uuid) = lookup.get(&entity_id) {
-            format!("(SELECT rowid FROM lz_entities WHERE id IS '{}')", uuid)
+            Value::Lookup(ENTITY_LOOKUP, uuid.clone())
         } else {
-            "-2".to_string()
+            Value::Integer(-2)
         }
     } else {
-        "-1".to_string()
+        Value::Integer(-1)
     }
 }
 
 impl<SessionTable> Row<SessionTable> for SessionRow<'_> {
-    fn sqlize_values(&self) -> String {
+    fn values(&self) -> Vec<Value> {
         let inner = &self.inner;
         let id: String = (&inner.entity_id).into();
-        let id_lookup = format!("(SELECT rowid FROM lz_entities WHERE id IS '{}')", id);
-        let day = &inner.day;
-        let title = inner.title.as_deref().unwrap_or("");
-        let context = inner.context.as_deref().unwrap_or("");
-        let nblocks = inner.nblocks;
-        let top_block = uuid_lookup(self.lookup, inner.top_block);
-
-        format!(
-            "{}, '{}', '{}', '{}', {}, {}",
-            id_lookup,
-            day,
-            escape_quotes(title),
-            escape_quotes(context),
-            nblocks,
-            top_block
-        )
+        vec![
+            Value::Lookup(ENTITY_LOOKUP, id),
+            Value::Text(inner.day.to_string()),
+            Value::Text(inner.title.clone().unwrap_or_default()),
+            Value::Text(inner.context.clone().unwrap_or_default()),
+            Value::Integer(inner.nblocks as i64),
+            entity_value(self.lookup, inner.top_block),
+        ]
     }
 }
 
@@ -77,6 +84,8 @@ impl Default for Table<SessionTable> {
         con.add_column(&Param::new("context", ParamType::Text));
         con.add_column(&Param::new("nblocks", ParamType::Integer));
         con.add_column(&Param::new("top_block", ParamType::Integer));
+        con.add_index(&["id"]);
+        con.add_index(&["day"]);
         con
     }
 }
@@ -89,28 +98,17 @@ struct EntryRow<'a> {
 }
 
 impl<EntryTable> Row<EntryTable> for EntryRow<'_> {
-    fn sqlize_values(&self) -> String {
+    fn values(&self) -> Vec<Value> {
         let inner = &self.inner;
-        let id = uuid_lookup(self.lookup, Some(self.inner.entity_id));
-        let day = &inner.day;
-        // TODO: make title optional
-        //let title = inner.title.as_deref().unwrap_or("");
-        let title = &inner.title;
-        let context = inner.context.as_deref().unwrap_or("");
-        let nblocks = inner.nblocks;
-        let top_block = uuid_lookup(self.lookup, inner.top_block);
-        let position = inner.position;
-
-        format!(
-            "{}, '{}', '{}', '{}', {}, {}, {}",
-            id,
-            escape_quotes(day),
-            escape_quotes(title),
-            escape_quotes(context),
-            nblocks,
-            top_block,
-            position
-        )
+        vec![
+            entity_value(self.lookup, Some(inner.entity_id)),
+            Value::Text(inner.day.clone()),
+            Value::Text(inner.title.clone()),
+            Value::Text(inner.context.clone().unwrap_or_default()),
+            Value::Integer(inner.nblocks as i64),
+            entity_value(self.lookup, inner.top_block),
+            Value::Integer(inner.position as i64),
+        ]
     }
 }
 
@@ -124,6 +122,8 @@ impl Default for Table<EntryTable> {
         con.add_column(&Param::new("nblocks", ParamType::Integer));
         con.add_column(&Param::new("top_block", ParamType::Integer));
         con.add_column(&Param::new("position", ParamType::Integer));
+        con.add_index(&["id"]);
+        con.add_index(&["day"]);
         con
     }
 }
@@ -136,20 +136,14 @@ struct BlockRow<'a> {
 }
 
 impl<BlockTable> Row<BlockTable> for BlockRow<'_> {
-    fn sqlize_values(&self) -> String {
+    fn values(&self) -> Vec<Value> {
         let inner = &self.inner;
-        let id = uuid_lookup(self.lookup, Some(inner.entity_id));
-        let parent = uuid_lookup(self.lookup, Some(inner.parent_id));
-        let position = inner.position;
-        let content = &inner.content;
-
-        format!(
-            "{}, {}, '{}', {}",
-            id,
-            parent,
-            escape_quotes(content),
-            position
-        )
+        vec![
+            entity_value(self.lookup, Some(inner.entity_id)),
+            entity_value(self.lookup, Some(inner.parent_id)),
+            Value::Text(inner.content.clone()),
+            Value::Integer(inner.position as i64),
+        ]
     }
 }
 
@@ -160,6 +154,8 @@ impl Default for Table<BlockTable> {
         con.add_column(&Param::new("parent", ParamType::Integer));
         con.add_column(&Param::new("content", ParamType::Text));
         con.add_column(&Param::new("position", ParamType::Integer));
+        con.add_index(&["id"]);
+        con.add_index(&["parent"]);
         con
     }
 }
@@ -175,18 +171,19 @@ impl Default for Table<EntityConnectionTable> {
     fn default() -> Self {
         let mut con: Table<EntityConnectionTable> = Table::new("lz_connections");
         con.add_column(&Param::new("id", ParamType::Integer));
-        con.add_column(&Param::new("node", ParamType::Text));
+        con.add_column(&Param::new("node", ParamType::TextNotNull));
+        con.add_index(&["id"]);
+        con.add_index(&["node"]);
         con
     }
 }
 
 impl<EntityConnectionTable> Row<EntityConnectionTable> for EntityConnectionRow<'_> {
-    fn sqlize_values(&self) -> String {
-        let inner = &self.inner;
-        let id = uuid_lookup(self.lookup, Some(inner.entity_id));
-        let node = &inner.node;
-
-        format!("{}, '{}'", id, escape_quotes(node),)
+    fn values(&self) -> Vec<Value> {
+        vec![
+            entity_value(self.lookup, Some(self.inner.entity_id)),
+            Value::Text(self.inner.node.clone()),
+        ]
     }
 }
 
@@ -201,21 +198,102 @@ impl Default for Table<TagTable> {
     fn default() -> Self {
         let mut con: Table<TagTable> = Table::new("lz_tags");
         con.add_column(&Param::new("id", ParamType::Integer));
-        con.add_column(&Param::new("tag", ParamType::Text));
+        con.add_column(&Param::new("tag", ParamType::TextNotNull));
+        con.add_index(&["id"]);
+        con.add_index(&["tag"]);
         con
     }
 }
 
 impl<TagTable> Row<TagTable> for TagRow<'_> {
-    fn sqlize_values(&self) -> String {
-        let inner = &self.inner;
-        let id = uuid_lookup(self.lookup, Some(inner.entity_id));
-        let tag = &inner.tag;
-
-        format!("{}, '{}'", id, escape_quotes(tag),)
+    fn values(&self) -> Vec<Value> {
+        vec![
+            entity_value(self.lookup, Some(self.inner.entity_id)),
+            Value::Text(self.inner.tag.clone()),
+        ]
     }
 }
 
+fn views() -> [View; 2] {
+    [
+        View::new(
+            "lz_tagged_entries",
+            concat!(
+                "SELECT tag, lz_entries.id, day, title, position FROM lz_tags\n",
+                "INNER JOIN lz_entries ON lz_entries.id = lz_tags.id"
+            ),
+        ),
+        View::new(
+            "lz_entry_blocks",
+            concat!(
+                "SELECT lz_entries.id AS entry, lz_entries.title, ",
+                "lz_blocks.id AS block, lz_blocks.content, lz_blocks.position\n",
+                "FROM lz_blocks\n",
+                "INNER JOIN lz_entries ON lz_entries.id = lz_blocks.parent"
+            ),
+        ),
+    ]
+}
+
+/// The blocks of an entry, in order, one per line
+fn entry_content(id: &str) -> String {
+    format!(
+        "(SELECT group_concat(content, char(10)) FROM \
+         (SELECT content FROM lz_blocks WHERE parent = {} ORDER BY position))",
+        id
+    )
+}
+
+/// Bring the content of an entry in lz_search up to date
+fn refresh_search(id: &str) -> String {
+    format!(
+        "UPDATE lz_search SET content = {} WHERE rowid = {}",
+        entry_content(id),
+        id
+    )
+}
+
+fn search_triggers() -> [Trigger; 6] {
+    let insert = format!(
+        "INSERT INTO lz_search(rowid, title, content) SELECT new.id, new.title, {} WHERE new.id > 0",
+        entry_content("new.id")
+    );
+    let delete = "DELETE FROM lz_search WHERE rowid = old.id".to_string();
+
+    [
+        Trigger::new(
+            "lz_search_entries_insert",
+            "AFTER INSERT ON lz_entries",
+            vec![insert.clone()],
+        ),
+        Trigger::new(
+            "lz_search_entries_delete",
+            "AFTER DELETE ON lz_entries",
+            vec![delete.clone()],
+        ),
+        Trigger::new(
+            "lz_search_entries_update",
+            "AFTER UPDATE ON lz_entries",
+            vec![delete, insert],
+        ),
+        Trigger::new(
+            "lz_search_blocks_insert",
+            "AFTER INSERT ON lz_blocks",
+            vec![refresh_search("new.parent")],
+        ),
+        Trigger::new(
+            "lz_search_blocks_delete",
+            "AFTER DELETE ON lz_blocks",
+            vec![refresh_search("old.parent")],
+        ),
+        Trigger::new(
+            "lz_search_blocks_update",
+            "AFTER UPDATE ON lz_blocks",
+            vec![refresh_search("old.parent"), refresh_search("new.parent")],
+        ),
+    ]
+}
+
 #[derive(Default)]
 pub struct Schemas {
     entities: Table<EntityTable>,
@@ -227,81 +305,179 @@ pub struct Schemas {
 }
 
 impl Schemas {
-    pub fn generate(&self, f: &mut impl io::Write) {
-        let _ = f.write_all(b"BEGIN;\n");
-        let _ = f.write_all(&self.entities.sqlize().into_bytes());
-        let _ = f.write_all(&self.sessions.sqlize().into_bytes());
-        let _ = f.write_all(&self.entries.sqlize().into_bytes());
-        let _ = f.write_all(&self.blocks.sqlize().into_bytes());
-        let _ = f.write_all(&self.connections.sqlize().into_bytes());
-        let _ = f.write_all(&self.tags.sqlize().into_bytes());
-        let _ = f.write_all(b"COMMIT;\n");
+    pub fn generate(&self, f: &mut impl Output) {
+        f.execute("BEGIN;\n");
+        f.create(&self.entities);
+        f.create(&self.sessions);
+        f.create(&self.entries);
+        f.create(&self.blocks);
+        f.create(&self.connections);
+        f.create(&self.tags);
+        for view in views() {
+            f.execute(&view.sqlize());
+        }
+        f.execute("COMMIT;\n");
+    }
+
+    /// An FTS5 table of entry titles and the content of their
+    /// blocks, with the entry ID as its rowid. Triggers keep it
+    /// in sync, and entries already in the tables are indexed
+    /// straight away. This goes after generate, and is
+    /// quickest once the rows have been written.
+    pub fn generate_search(&self, f: &mut impl Output) {
+        f.execute("BEGIN;\n");
+        f.execute(&SearchTable::new("lz_search", &["title", "content"]).sqlize());
+        for trigger in search_triggers() {
+            f.execute(&trigger.sqlize());
+        }
+        f.execute("DELETE FROM lz_search;\n");
+        f.execute(&format!(
+            "INSERT INTO lz_search(rowid, title, content)\nSELECT id, title, {}\nFROM lz_entries WHERE id > 0;\n",
+            entry_content("lz_entries.id")
+        ));
+        f.execute("COMMIT;\n");
     }
 }
 
 impl SessionRows {
-    pub fn generate(&self, schemas: &Schemas, f: &mut impl io::Write) {
-        let _ = f.write_all(b"BEGIN;\n");
+    pub fn generate(&self, schemas: &Schemas, f: &mut impl Output) {
+        f.execute("BEGIN;\n");
         // Entity List
         for row in &self.entities {
-            let s = schemas.entities.sqlize_insert(row).to_string();
-            let _ = f.write_all(&s.into_bytes());
+            f.insert(&schemas.entities, row);
+        }
+        self.generate_rows(schemas, f);
+        f.execute("COMMIT;\n");
+    }
+
+    /// Like generate, but entities get their row IDs from
+    /// ids, and every other row refers to them by ID rather
+    /// than with a subquery
+    pub fn generate_with_ids(&self, schemas: &Schemas, ids: &Ids, f: &mut impl Output) {
+        let mut entities: Table<EntityTable> = Table::default();
+        entities.insert_keys();
+
+        f.execute("BEGIN;\n");
+        for row in &self.entities {
+            let uuid = String::from(row);
+            let rowid = ids
+                .get(ENTITY_LOOKUP, &uuid)
+                .map_or(Value::Null, Value::Integer);
+            f.insert_values(&entities, vec![rowid, Value::Text(uuid)]);
         }
+        self.generate_rows(schemas, &mut WithIds::new(f, ids));
+        f.execute("COMMIT;\n");
+    }
 
+    /// Entities referred to by this session that it has no
+    /// UUID for. generate writes these as -2.
+    pub fn unresolved(&self) -> Vec<EntityId> {
+        let mut refs: Vec<Option<EntityId>> = vec![self.session.top_block];
+        for row in &self.logs {
+            refs.extend([Some(row.entity_id), row.top_block]);
+        }
+        for row in &self.blocks {
+            refs.extend([Some(row.entity_id), Some(row.parent_id)]);
+        }
+        refs.extend(self.connections.iter().map(|row| Some(row.entity_id)));
+        refs.extend(self.tags.iter().map(|row| Some(row.entity_id)));
+
+        let mut unresolved: Vec<EntityId> = refs
+            .into_iter()
+            .flatten()
+            .filter(|id| !self.lookup.contains_key(id))
+            .collect();
+        unresolved.sort();
+        unresolved.dedup();
+        unresolved
+    }
+
+    fn generate_rows(&self, schemas: &Schemas, f: &mut impl Output) {
         // Session
-        let s = schemas
-            .sessions
-            .sqlize_insert(&SessionRow {
+        f.insert(
+            &schemas.sessions,
+            &SessionRow {
                 inner: &self.session,
                 lookup: &self.lookup,
-            })
-            .to_string();
-        let _ = f.write_all(&s.into_bytes());
+            },
+        );
 
         for row in &self.logs {
-            let s = schemas
-                .entries
-                .sqlize_insert(&EntryRow {
+            f.insert(
+                &schemas.entries,
+                &EntryRow {
                     inner: row,
                     lookup: &self.lookup,
-                })
-                .to_string();
-            let _ = f.write_all(&s.into_bytes());
+                },
+            );
         }
 
         for row in &self.blocks {
-            let s = schemas
-                .blocks
-                .sqlize_insert(&BlockRow {
+            f.insert(
+                &schemas.blocks,
+                &BlockRow {
                     inner: row,
                     lookup: &self.lookup,
-                })
-                .to_string();
-            let _ = f.write_all(&s.into_bytes());
+                },
+            );
         }
 
         for row in &self.connections {
-            let s = schemas
-                .connections
-                .sqlize_insert(&EntityConnectionRow {
+            f.insert(
+                &schemas.connections,
+                &EntityConnectionRow {
                     inner: row,
                     lookup: &self.lookup,
-                })
-                .to_string();
-            let _ = f.write_all(&s.into_bytes());
+                },
+            );
         }
 
         for row in &self.tags {
-            let s = schemas
-                .tags
-                .sqlize_insert(&TagRow {
+            f.insert(
+                &schemas.tags,
+                &TagRow {
                     inner: row,
                     lookup: &self.lookup,
-                })
-                .to_string();
-            let _ = f.write_all(&s.into_bytes());
+                },
+            );
         }
+    }
+}
 
-        let _ = f.write_all(b"COMMIT;\n");
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::logzet::rows::{BlockRow, TagsRow};
+
+    #[test]
+    fn test_literal_ids() {
+        let rows = SessionRows {
+            entities: vec![EntityRow::default()],
+            blocks: vec![BlockRow {
+                entity_id: 0,
+                parent_id: 3,
+                ..Default::default()
+            }],
+            tags: vec![TagsRow {
+                entity_id: 5,
+                tag: "x".to_string(),
+            }],
+            lookup: HashMap::from([(0, String::new())]),
+            ..Default::default()
+        };
+
+        assert_eq!(rows.unresolved(), [3, 5]);
+
+        let rows = [rows];
+        let ids = entity_ids(&rows);
+        let mut sql: Vec<u8> = vec![];
+        rows[0].generate_with_ids(&Schemas::default(), &ids, &mut sql);
+        let sql = String::from_utf8(sql).unwrap();
+
+        assert!(sql.contains("INSERT INTO lz_entities(rowid, id)\nVALUES(1, '');"));
+        assert!(sql.contains(
+            "INSERT INTO lz_blocks(id, parent, content, position)\nVALUES(1, -2, '', 0);"
+        ));
+        assert!(!sql.contains("SELECT rowid FROM lz_entities"));
     }
 }
@@ -0,0 +1,875 @@
+//! A language server for dagzet. Open documents are parsed and
+//! validated on every change, with the same parser and checks
+//! as the compiler, and the results are used for diagnostics,
+//! go-to-definition, hover, and completion of node paths.
+//!
+//! Only full document sync is supported. Messages are JSON
+//! values; framing them over stdio is left to the binary.
+
+use crate::dagzet::{DagZet, DagZetError, ReturnCode};
+use crate::jsonrpc::Value;
+use crate::parser::{self, Command, Span, Statement};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A problem with part of a document
+#[derive(Debug, PartialEq)]
+pub struct Diagnostic {
+    pub span: Span,
+    pub message: String,
+}
+
+/// Where a node was made. Files other than the document
+/// itself are ones it includes.
+#[derive(Debug, PartialEq)]
+pub struct Definition {
+    pub filename: Option<String>,
+    pub linum: u32,
+}
+
+/// A node path that could go where the cursor is, replacing
+/// the word being typed
+#[derive(Debug, PartialEq)]
+pub struct Completion {
+    pub label: String,
+    pub text: String,
+    pub span: Span,
+}
+
+/// A document that has been parsed and validated
+pub struct Analysis {
+    dz: DagZet,
+    filename: Option<String>,
+    lines: Vec<String>,
+    pub diagnostics: Vec<Diagnostic>,
+    /// Node names mentioned in the document, and where
+    refs: Vec<(Span, String)>,
+    /// The namespace at the start of each line
+    namespaces: Vec<Option<String>>,
+    /// The file each node was made in, as an index
+    /// into DagZet::files
+    node_files: Vec<Option<usize>>,
+    /// Files being parsed, for catching include cycles
+    parsing: Vec<PathBuf>,
+}
+
+/// The words in a statement's arguments, with their spans
+fn words(stmt: &Statement) -> Vec<(Span, &str)> {
+    let args = stmt.args.as_str();
+    let span = stmt.args_span;
+    let mut words = vec![];
+    let mut current: Option<(usize, usize)> = None;
+
+    let ends = std::iter::once((args.len(), ' '));
+    for (col, (pos, c)) in args.char_indices().chain(ends).enumerate() {
+        if !c.is_whitespace() {
+            current = current.or(Some((pos, col)));
+            continue;
+        }
+        if let Some((start, start_col)) = current.take() {
+            let word_span = Span {
+                line: span.line,
+                start: span.start + start_col,
+                end: span.start + col,
+            };
+            words.push((word_span, &args[start..pos]));
+        }
+    }
+
+    words
+}
+
+fn line_span(linum: u32, line: &str) -> Span {
+    Span {
+        line: linum,
+        start: 0,
+        end: line.chars().count(),
+    }
+}
+
+fn message(err: &DagZetError) -> String {
+    let mut message = err.reason.to_string();
+    for note in &err.notes {
+        let filename = note.filename.as_deref().unwrap_or("<stdin>");
+        message.push_str(&format!("\n{}:{}: {}", filename, note.linum, note.note));
+    }
+    message
+}
+
+fn canonical(filename: &str) -> PathBuf {
+    fs::canonicalize(filename).unwrap_or_else(|_| PathBuf::from(filename))
+}
+
+impl Analysis {
+    /// Parse and validate a document. The filename is used to
+    /// find included files.
+    pub fn new(filename: Option<&str>, text: &str) -> Self {
+        let mut analysis = Analysis {
+            dz: DagZet::new(),
+            filename: filename.map(String::from),
+            lines: text.lines().map(String::from).collect(),
+            diagnostics: vec![],
+            refs: vec![],
+            namespaces: vec![],
+            node_files: vec![],
+            parsing: vec![],
+        };
+
+        if let Some(filename) = filename {
+            analysis.dz.begin_file(filename);
+            analysis.parsing.push(canonical(filename));
+        }
+
+        let (connections, _) = analysis.parse(text, true);
+        analysis.validate();
+
+        // suffixes are only resolved once the graph is validated
+        for (idx, spans) in connections {
+            let co = &analysis.dz.connections[idx];
+            for (span, name) in spans.into_iter().zip(co) {
+                analysis.refs.push((span, name.clone()));
+            }
+        }
+
+        analysis
+    }
+
+    fn diagnose(&mut self, span: Span, message: String) {
+        self.diagnostics.push(Diagnostic { span, message });
+    }
+
+    /// Parse the lines of the document, or a file it includes.
+    /// Only the document itself gets diagnostics and refs, so
+    /// for included files, the number of errors is returned.
+    /// Connections made by the document are returned with the
+    /// spans of their two nodes, as they can only be named once
+    /// suffixes are resolved.
+    fn parse(&mut self, text: &str, main: bool) -> (Vec<(usize, [Span; 2])>, usize) {
+        let mut connections = vec![];
+        let mut nerrors = 0;
+
+        for (linum, line) in (1..).zip(text.lines()) {
+            self.dz.linum = linum;
+            if main {
+                self.namespaces.push(self.dz.namespace.clone());
+            }
+
+            let stmt = match parser::parse_line(line, linum) {
+                Ok(Some(stmt)) => stmt,
+                Ok(None) => continue,
+                Err(e) => {
+                    nerrors += 1;
+                    if main {
+                        self.diagnose(e.span, e.reason.to_string());
+                    }
+                    continue;
+                }
+            };
+
+            if let Command::Include(path) = &stmt.command {
+                let errors = self.include(path, &stmt, main);
+                nerrors += errors;
+                continue;
+            }
+
+            let nnodes = self.dz.nodelist.len();
+
+            if let Err(rc) = self.dz.eval(&stmt.command) {
+                nerrors += 1;
+                if main {
+                    self.diagnose(stmt.args_span, rc.to_string());
+                }
+                continue;
+            }
+
+            let file = self.dz.source_ref().file;
+            self.node_files
+                .extend((nnodes..self.dz.nodelist.len()).map(|_| file));
+
+            if !main {
+                continue;
+            }
+
+            let words = words(&stmt);
+            let curnode = self
+                .dz
+                .curnode
+                .map(|id| self.dz.nodelist[id as usize - 1].clone());
+
+            match &stmt.command {
+                Command::NewNode(_) | Command::SelectNode(_) => {
+                    if let (Some(name), Some((span, _))) = (curnode, words.first()) {
+                        let span = match stmt.command {
+                            Command::NewNode(_) => stmt.args_span,
+                            _ => *span,
+                        };
+                        self.refs.push((span, name));
+                    }
+                }
+                Command::Connect(_, _) | Command::ConnectExternal(_, _) => {
+                    let idx = self.dz.connections.len() - 1;
+                    connections.push((idx, [words[0].0, words[1].0]));
+                }
+                Command::Alias(_, _) => {
+                    if let Some(alias) = self.dz.alias_list.last() {
+                        self.refs.push((words[1].0, alias.path.clone()));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if main {
+            connections.sort_by_key(|(idx, _)| *idx);
+        }
+
+        (connections, nerrors)
+    }
+
+    /// Parse an included file in place, the way the compiler
+    /// does. Problems inside it are summed up at the include.
+    fn include(&mut self, path: &str, stmt: &Statement, main: bool) -> usize {
+        let dir = self
+            .dz
+            .source_ref()
+            .file
+            .and_then(|f| Path::new(&self.dz.files[f]).parent().map(Path::to_path_buf));
+        let filename = match dir {
+            Some(dir) => dir.join(path).to_string_lossy().into_owned(),
+            None => path.to_string(),
+        };
+
+        let problem = match fs::read_to_string(&filename) {
+            Err(e) => Some(ReturnCode::CouldNotRead(format!("{}: {}", filename, e))),
+            Ok(_) if self.parsing.contains(&canonical(&filename)) => {
+                Some(ReturnCode::IncludeCycle(vec![filename.clone()]))
+            }
+            Ok(text) => {
+                let suspended = self.dz.suspend_file();
+                self.dz.begin_file(&filename);
+                self.parsing.push(canonical(&filename));
+                let (_, nerrors) = self.parse(&text, false);
+                self.parsing.pop();
+                self.dz.resume_file(suspended);
+
+                if main && nerrors > 0 {
+                    let message = format!("{} error(s) in {}", nerrors, filename);
+                    self.diagnose(stmt.args_span, message);
+                }
+                return nerrors;
+            }
+        };
+
+        if let (Some(rc), true) = (problem, main) {
+            self.diagnose(stmt.args_span, rc.to_string());
+        }
+        1
+    }
+
+    /// Run the checks that need the whole graph. Only problems
+    /// in the document itself are reported.
+    fn validate(&mut self) {
+        let errors = match self.dz.validate() {
+            Ok(_) => return,
+            Err(errors) => errors,
+        };
+
+        for err in errors {
+            if err.filename != self.filename || err.linum == 0 {
+                continue;
+            }
+            let line = self.lines.get(err.linum as usize - 1);
+            let span = line_span(err.linum, line.map(|s| s.as_str()).unwrap_or(""));
+            self.diagnose(span, message(&err));
+        }
+    }
+
+    /// The text of a line, counting from 1
+    pub fn line(&self, linum: u32) -> Option<&str> {
+        let idx = (linum as usize).checked_sub(1)?;
+        self.lines.get(idx).map(|s| s.as_str())
+    }
+
+    /// The full name of the node at a position
+    pub fn node_at(&self, linum: u32, col: usize) -> Option<&str> {
+        self.refs
+            .iter()
+            .find(|(span, _)| span.line == linum && span.start <= col && col <= span.end)
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// Where a node was made
+    pub fn definition(&self, name: &str) -> Option<Definition> {
+        let id = *self.dz.nodes.get(name)?;
+        let file = self.node_files.get(id as usize - 1).copied().flatten();
+        Some(Definition {
+            filename: file.map(|f| self.dz.files[f].clone()),
+            linum: *self.dz.noderefs.get(&id)?,
+        })
+    }
+
+    /// A description of a node in markdown, with its lines
+    /// and tags
+    pub fn hover(&self, name: &str) -> Option<String> {
+        let id = *self.dz.nodes.get(name)?;
+        let mut text = format!("**{}**", name);
+
+        if let Some(tags) = self.dz.tags.get(&id) {
+            let tags: Vec<String> = tags.iter().map(|t| format!("`{}`", t)).collect();
+            text.push_str(&format!("\n\ntags: {}", tags.join(" ")));
+        }
+
+        for line in self.dz.lines.get(&id).into_iter().flatten() {
+            text.push_str(&format!("\n\n{}", line));
+        }
+
+        Some(text)
+    }
+
+    /// Node paths that could complete the word before a
+    /// position, in co, cx, sn and al commands
+    pub fn complete(&self, linum: u32, col: usize) -> Vec<Completion> {
+        let line = match self.line(linum) {
+            Some(line) => line,
+            None => return vec![],
+        };
+
+        let stmt = match parser::parse_partial_line(line, linum) {
+            Ok(Some(stmt)) => stmt,
+            _ => return vec![],
+        };
+
+        // co and sn are relative to the namespace
+        let relative = match stmt.command {
+            Command::Connect(_, _) | Command::SelectNode(_) => true,
+            Command::ConnectExternal(_, _) | Command::Alias(_, _) => false,
+            _ => return vec![],
+        };
+
+        if col < stmt.args_span.start {
+            return vec![];
+        }
+
+        let before: Vec<char> = line.chars().take(col).collect();
+        let start = before
+            .iter()
+            .rposition(|c| c.is_whitespace())
+            .map_or(0, |pos| pos + 1);
+        let word: String = before[start..].iter().collect();
+        let span = Span {
+            line: linum,
+            start,
+            end: col,
+        };
+
+        let completion = |label: &str, text: String| Completion {
+            label: label.to_string(),
+            text,
+            span,
+        };
+
+        let mut found: Vec<Completion> = if let Some(suffix) = word.strip_prefix('?') {
+            let tree = self.dz.suffix_tree();
+            tree.complete(suffix)
+                .iter()
+                .map(|path| completion(path, format!("?{}", tree.shortest_suffix(path))))
+                .collect()
+        } else {
+            let prefix = match &self.namespaces[linum as usize - 1] {
+                Some(ns) if relative => format!("{}/", ns),
+                _ => String::new(),
+            };
+            self.dz
+                .nodelist
+                .iter()
+                .filter_map(|path| {
+                    let rest = path.strip_prefix(&prefix)?;
+                    rest.starts_with(&word)
+                        .then(|| completion(path, rest.to_string()))
+                })
+                .collect()
+        };
+
+        found.sort_by(|a, b| a.label.cmp(&b.label));
+        found
+    }
+}
+
+/// A file URI as a path
+pub fn uri_to_path(uri: &str) -> Option<String> {
+    let path = uri.strip_prefix("file://")?;
+    let bytes = path.as_bytes();
+    let mut out = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+/// A path as a file URI
+pub fn path_to_uri(path: &str) -> String {
+    let path = canonical(path);
+    let mut uri = String::from("file://");
+    for c in path.to_string_lossy().chars() {
+        match c {
+            ' ' | '%' | '#' | '?' => uri.push_str(&format!("%{:02X}", c as u32)),
+            c => uri.push(c),
+        }
+    }
+    uri
+}
+
+/// A column in characters as UTF-16 code units, which is
+/// what positions in the protocol count
+fn utf16_col(line: &str, col: usize) -> u32 {
+    line.chars().take(col).map(|c| c.len_utf16() as u32).sum()
+}
+
+fn char_col(line: &str, utf16: u32) -> usize {
+    let mut units = 0;
+    for (col, c) in line.chars().enumerate() {
+        if units >= utf16 {
+            return col;
+        }
+        units += c.len_utf16() as u32;
+    }
+    line.chars().count()
+}
+
+struct Document {
+    uri: String,
+    text: String,
+    analysis: Analysis,
+}
+
+/// The state of a language server session
+#[derive(Default)]
+pub struct Server {
+    documents: HashMap<String, Document>,
+    shutdown: bool,
+    /// Set once the client asks the server to exit, with the
+    /// exit code to use
+    pub exit: Option<i32>,
+}
+
+fn response(id: &Value, result: Value) -> Value {
+    Value::object(vec![
+        ("jsonrpc", Value::string("2.0")),
+        ("id", id.clone()),
+        ("result", result),
+    ])
+}
+
+fn error_response(id: &Value, code: i32, message: &str) -> Value {
+    let error = Value::object(vec![
+        ("code", Value::Number(code as f64)),
+        ("message", Value::string(message)),
+    ]);
+    Value::object(vec![
+        ("jsonrpc", Value::string("2.0")),
+        ("id", id.clone()),
+        ("error", error),
+    ])
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    Value::object(vec![
+        ("jsonrpc", Value::string("2.0")),
+        ("method", Value::string(method)),
+        ("params", params),
+    ])
+}
+
+fn capabilities() -> Value {
+    let completion = Value::object(vec![(
+        "triggerCharacters",
+        Value::Array(vec![Value::string("?"), Value::string("/")]),
+    )]);
+    Value::object(vec![
+        (
+            "capabilities",
+            Value::object(vec![
+                ("textDocumentSync", 1.into()),
+                ("definitionProvider", true.into()),
+                ("hoverProvider", true.into()),
+                ("completionProvider", completion),
+            ]),
+        ),
+        (
+            "serverInfo",
+            Value::object(vec![("name", Value::string("dagzet-lsp"))]),
+        ),
+    ])
+}
+
+impl Document {
+    fn new(uri: &str, text: &str) -> Self {
+        let filename = uri_to_path(uri);
+        Document {
+            uri: uri.to_string(),
+            text: text.to_string(),
+            analysis: Analysis::new(filename.as_deref(), text),
+        }
+    }
+
+    fn position(&self, linum: u32, col: usize) -> Value {
+        let line = self.analysis.line(linum).unwrap_or("");
+        Value::object(vec![
+            ("line", (linum.saturating_sub(1)).into()),
+            ("character", utf16_col(line, col).into()),
+        ])
+    }
+
+    fn range(&self, span: &Span) -> Value {
+        Value::object(vec![
+            ("start", self.position(span.line, span.start)),
+            ("end", self.position(span.line, span.end)),
+        ])
+    }
+
+    fn diagnostics(&self) -> Value {
+        let diagnostics = self
+            .analysis
+            .diagnostics
+            .iter()
+            .map(|d| {
+                Value::object(vec![
+                    ("range", self.range(&d.span)),
+                    ("severity", 1.into()),
+                    ("source", Value::string("dagzet")),
+                    ("message", Value::string(&d.message)),
+                ])
+            })
+            .collect();
+
+        notification(
+            "textDocument/publishDiagnostics",
+            Value::object(vec![
+                ("uri", Value::string(&self.uri)),
+                ("diagnostics", Value::Array(diagnostics)),
+            ]),
+        )
+    }
+
+    /// The line (counting from 1) and column (in characters)
+    /// of a position given in a request
+    fn cursor(&self, params: &Value) -> Option<(u32, usize)> {
+        let line = params.path(&["position", "line"])?.as_u64()? as u32 + 1;
+        let character = params.path(&["position", "character"])?.as_u64()? as u32;
+        let text = self.analysis.line(line)?;
+        Some((line, char_col(text, character)))
+    }
+
+    fn definition(&self, params: &Value) -> Option<Value> {
+        let (linum, col) = self.cursor(params)?;
+        let name = self.analysis.node_at(linum, col)?;
+        let def = self.analysis.definition(name)?;
+
+        let uri = match &def.filename {
+            Some(f) if Some(f) != self.analysis.filename.as_ref() => path_to_uri(f),
+            _ => self.uri.clone(),
+        };
+        let start = Value::object(vec![
+            ("line", def.linum.saturating_sub(1).into()),
+            ("character", 0.into()),
+        ]);
+
+        Some(Value::object(vec![
+            ("uri", Value::String(uri)),
+            (
+                "range",
+                Value::object(vec![("start", start.clone()), ("end", start)]),
+            ),
+        ]))
+    }
+
+    fn hover(&self, params: &Value) -> Option<Value> {
+        let (linum, col) = self.cursor(params)?;
+        let name = self.analysis.node_at(linum, col)?;
+        let text = self.analysis.hover(name)?;
+        let contents = Value::object(vec![
+            ("kind", Value::string("markdown")),
+            ("value", Value::String(text)),
+        ]);
+        Some(Value::object(vec![("contents", contents)]))
+    }
+
+    fn completion(&self, params: &Value) -> Option<Value> {
+        let (linum, col) = self.cursor(params)?;
+        let items = self
+            .analysis
+            .complete(linum, col)
+            .into_iter()
+            .map(|c| {
+                let edit = Value::object(vec![
+                    ("range", self.range(&c.span)),
+                    ("newText", Value::String(c.text)),
+                ]);
+                Value::object(vec![("label", Value::String(c.label)), ("textEdit", edit)])
+            })
+            .collect();
+        Some(Value::Array(items))
+    }
+}
+
+impl Server {
+    pub fn new() -> Self {
+        Server::default()
+    }
+
+    fn open(&mut self, uri: &str, text: &str) -> Value {
+        let doc = Document::new(uri, text);
+        let diagnostics = doc.diagnostics();
+        self.documents.insert(uri.to_string(), doc);
+        diagnostics
+    }
+
+    /// Handle one message from the client, returning the
+    /// messages to send back
+    pub fn handle(&mut self, msg: &Value) -> Vec<Value> {
+        let method = msg.get("method").and_then(|m| m.as_str()).unwrap_or("");
+        let params = msg.get("params").unwrap_or(&Value::Null);
+        let uri = params
+            .path(&["textDocument", "uri"])
+            .and_then(|u| u.as_str())
+            .unwrap_or("");
+        let doc = self.documents.get(uri);
+
+        let id = match msg.get("id") {
+            Some(id) => id,
+            None => {
+                return self.notify(method, params, uri);
+            }
+        };
+
+        let result = match method {
+            "initialize" => capabilities(),
+            "shutdown" => {
+                self.shutdown = true;
+                Value::Null
+            }
+            "textDocument/definition" => doc
+                .and_then(|d| d.definition(params))
+                .unwrap_or(Value::Null),
+            "textDocument/hover" => doc.and_then(|d| d.hover(params)).unwrap_or(Value::Null),
+            "textDocument/completion" => doc
+                .and_then(|d| d.completion(params))
+                .unwrap_or(Value::Array(vec![])),
+            _ => return vec![error_response(id, -32601, "method not found")],
+        };
+
+        vec![response(id, result)]
+    }
+
+    fn notify(&mut self, method: &str, params: &Value, uri: &str) -> Vec<Value> {
+        match method {
+            "exit" => {
+                self.exit = Some(if self.shutdown { 0 } else { 1 });
+                vec![]
+            }
+            "textDocument/didOpen" => {
+                let text = params
+                    .path(&["textDocument", "text"])
+                    .and_then(|t| t.as_str());
+                vec![self.open(uri, text.unwrap_or(""))]
+            }
+            "textDocument/didChange" => {
+                let changes = params.get("contentChanges").and_then(|c| c.as_array());
+                let text = changes
+                    .and_then(|c| c.last())
+                    .and_then(|c| c.get("text"))
+                    .and_then(|t| t.as_str());
+                match text {
+                    Some(text) => vec![self.open(uri, text)],
+                    None => vec![],
+                }
+            }
+            // included files may have changed, so everything
+            // gets checked again
+            "textDocument/didSave" => {
+                let docs: Vec<(String, String)> = self
+                    .documents
+                    .values()
+                    .map(|d| (d.uri.clone(), d.text.clone()))
+                    .collect();
+                docs.iter()
+                    .map(|(uri, text)| self.open(uri, text))
+                    .collect()
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                let params = Value::object(vec![
+                    ("uri", Value::string(uri)),
+                    ("diagnostics", Value::Array(vec![])),
+                ]);
+                vec![notification("textDocument/publishDiagnostics", params)]
+            }
+            _ => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::jsonrpc;
+
+    const CODE: &str = concat!(
+        "ns a\n",
+        "nn b/c\n",
+        "ln a line\n",
+        "tg x\n",
+        "nn d\n",
+        "co $ b/c\n",
+        "co d ?e\n",
+        "xx nope\n",
+        "sn b/c\n",
+        "co b/c d\n",
+    );
+
+    #[test]
+    fn test_diagnostics() {
+        let analysis = Analysis::new(None, CODE);
+        let lines: Vec<(u32, &str)> = analysis
+            .diagnostics
+            .iter()
+            .map(|d| (d.span.line, d.message.as_str()))
+            .collect();
+
+        assert_eq!(
+            lines,
+            [
+                (8, "Invalid command"),
+                (7, "suffix ?e does not match any node"),
+                (
+                    10,
+                    "cycle found: a/b/c -> a/d -> a/b/c\n<stdin>:10: a/b/c -> a/d\n<stdin>:6: a/d -> a/b/c"
+                ),
+            ]
+        );
+        assert_eq!(
+            analysis.diagnostics[0].span,
+            Span {
+                line: 8,
+                start: 0,
+                end: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_definition_and_hover() {
+        let analysis = Analysis::new(None, CODE);
+
+        assert_eq!(analysis.node_at(6, 5), Some("a/b/c"));
+        assert_eq!(analysis.node_at(6, 3), Some("a/d"));
+        assert_eq!(analysis.node_at(9, 4), Some("a/b/c"));
+        assert_eq!(analysis.node_at(3, 4), None);
+        assert_eq!(
+            analysis.definition("a/b/c"),
+            Some(Definition {
+                filename: None,
+                linum: 2
+            })
+        );
+        assert_eq!(
+            analysis.hover("a/b/c").unwrap(),
+            "**a/b/c**\n\ntags: `x`\n\na line"
+        );
+    }
+
+    #[test]
+    fn test_completion() {
+        let analysis = Analysis::new(None, "ns a\nnn b/cat\nnn car\nco ?ca\nco c\ncx a/b\n");
+
+        let labels = |linum, col| -> Vec<(String, String)> {
+            analysis
+                .complete(linum, col)
+                .into_iter()
+                .map(|c| (c.label, c.text))
+                .collect()
+        };
+
+        assert_eq!(
+            labels(4, 6),
+            [
+                ("a/b/cat".to_string(), "?cat".to_string()),
+                ("a/car".to_string(), "?car".to_string())
+            ]
+        );
+        assert_eq!(labels(5, 4), [("a/car".to_string(), "car".to_string())]);
+        assert_eq!(
+            labels(6, 6),
+            [("a/b/cat".to_string(), "a/b/cat".to_string())]
+        );
+        assert!(labels(2, 4).is_empty());
+        assert!(labels(5, 2).is_empty());
+    }
+
+    #[test]
+    fn test_server() {
+        let mut server = Server::new();
+        let msg = |text: &str| jsonrpc::parse(text).unwrap();
+
+        let out = server.handle(&msg(
+            r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#,
+        ));
+        assert_eq!(
+            out[0].path(&["result", "capabilities", "hoverProvider"]),
+            Some(&Value::Bool(true))
+        );
+
+        let open = r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":
+            {"textDocument":{"uri":"untitled:a","text":"ns a\nnn b\nco b c\n"}}}"#;
+        let out = server.handle(&msg(open));
+        let diagnostics = out[0]
+            .path(&["params", "diagnostics"])
+            .unwrap()
+            .as_array()
+            .unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0]
+                .path(&["range", "start", "line"])
+                .unwrap()
+                .as_u64(),
+            Some(2)
+        );
+
+        let hover = r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/hover","params":
+            {"textDocument":{"uri":"untitled:a"},"position":{"line":2,"character":3}}}"#;
+        let out = server.handle(&msg(hover));
+        assert_eq!(
+            out[0]
+                .path(&["result", "contents", "value"])
+                .unwrap()
+                .as_str(),
+            Some("**a/b**")
+        );
+
+        let out = server.handle(&msg(r#"{"jsonrpc":"2.0","id":3,"method":"nope"}"#));
+        assert!(out[0].get("error").is_some());
+
+        server.handle(&msg(r#"{"jsonrpc":"2.0","id":4,"method":"shutdown"}"#));
+        server.handle(&msg(r#"{"jsonrpc":"2.0","method":"exit"}"#));
+        assert_eq!(server.exit, Some(0));
+    }
+
+    #[test]
+    fn test_uri() {
+        assert_eq!(
+            uri_to_path("file:///a%20b/c.dz").as_deref(),
+            Some("/a b/c.dz")
+        );
+        assert_eq!(uri_to_path("untitled:1"), None);
+    }
+}
@@ -1,182 +1,489 @@
+use dagzet::cache::compile_incremental;
+use dagzet::compiler::{build_files, compile_with_options, Graph, SqlOptions};
+use dagzet::dot::DotOptions;
+use dagzet::fmt;
+use dagzet::query::{self, Query};
+use std::collections::BTreeMap;
 use std::env;
-use std::fs::File;
-use std::io;
-use std::io::prelude::*;
-use std::io::BufReader;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::process;
+
+fn usage() -> ! {
+    eprintln!("usage: dagzet [--format sql|json|dot|anki] [--cache FILE | --db FILE [--append]]");
+    eprintln!("              [--literal-ids] [--search]");
+    eprintln!("              [--namespace NS] [--around NODE [--hops N]] [files...]");
+    eprintln!("       dagzet fmt [--check] [files...]");
+    eprintln!("       dagzet query [--json] [--depth N] [--namespace NS] QUERY [files...]");
+    eprintln!("       dagzet plan --target NODE [--target NODE...] [--known TAG] [files...]");
+    eprintln!("       dagzet html DIR [files...]");
+    eprintln!("       dagzet decompile [--by-file] DB DIR");
+    eprintln!();
+    eprintln!("queries: ancestors NODE, descendants NODE, path FROM TO,");
+    eprintln!("         roots, leaves, neighbours NODE");
+    process::exit(1);
+}
 
-mod dagzet;
-use dagzet::*;
+/// Format a single source, returning None if it has errors
+fn format_source(filename: &str, source: &str) -> Option<String> {
+    match fmt::format(source) {
+        Ok(formatted) => Some(formatted),
+        Err(errors) => {
+            for e in errors {
+                let col = e.span.start + 1;
+                eprintln!("{}:{}:{}: {}", filename, e.span.line, col, e.reason);
+            }
+            None
+        }
+    }
+}
 
-mod sqlite;
-use sqlite::*;
+/// "dagzet fmt": rewrite files in their canonical form, or
+/// with --check, list the files that aren't. With no files,
+/// stdin is formatted to stdout.
+fn fmt_main(args: impl Iterator<Item = String>) -> ! {
+    let mut check = false;
+    let mut filenames: Vec<String> = vec![];
+
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            _ => filenames.push(arg),
+        }
+    }
 
-mod tables;
-use tables::*;
+    if filenames.is_empty() {
+        let mut source = String::new();
+        if let Err(e) = io::stdin().read_to_string(&mut source) {
+            eprintln!("could not read stdin: {}", e);
+            process::exit(1);
+        }
+        let formatted = match format_source("<stdin>", &source) {
+            Some(formatted) => formatted,
+            None => process::exit(1),
+        };
+        if check {
+            process::exit((formatted != source) as i32);
+        }
+        print!("{}", formatted);
+        process::exit(0);
+    }
 
-mod trie;
+    let mut failed = false;
 
-fn parse_file(filename: &str, dz: &mut DagZet) {
-    let f = File::open(filename).unwrap();
-    let reader = BufReader::new(f);
+    for filename in &filenames {
+        let source = match fs::read_to_string(filename) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("could not read {}: {}", filename, e);
+                failed = true;
+                continue;
+            }
+        };
+
+        let formatted = match format_source(filename, &source) {
+            Some(formatted) => formatted,
+            None => {
+                failed = true;
+                continue;
+            }
+        };
 
-    let lines_iter = reader.lines().map(|l| l.unwrap());
+        if formatted == source {
+            continue;
+        }
 
-    let mut linum = 1;
+        if check {
+            println!("{}", filename);
+            failed = true;
+        } else if let Err(e) = fs::write(filename, formatted) {
+            eprintln!("could not write {}: {}", filename, e);
+            failed = true;
+        }
+    }
 
-    for str in lines_iter {
-        dz.linum = linum;
-        let result = dz.parse_line_with_result(&str);
+    process::exit(failed as i32);
+}
 
-        match result {
-            Ok(_) => {}
-            Err(rc) => {
-                panic!("Error on line {}: {}\nContext:'{}'", linum, rc, &str)
+/// Parse and validate files, or report the errors and exit
+fn build_or_exit(filenames: &[String]) -> Graph {
+    match build_files(filenames) {
+        Ok(graph) => graph,
+        Err(errors) => {
+            for err in &errors {
+                eprintln!("{}", err);
             }
-        };
-        linum += 1;
+            eprintln!("{} error(s) found, no output generated.", errors.len());
+            process::exit(1);
+        }
     }
 }
 
-fn parse_stdin(dz: &mut DagZet) {
-    let reader = BufReader::new(io::stdin());
+/// "dagzet query": answer a question about the graph, printing
+/// node names one per line, or as JSON. Exits with 1 when
+/// there is no path between two nodes.
+fn query_main(mut args: impl Iterator<Item = String>) -> ! {
+    let mut json = false;
+    let mut depth: Option<usize> = None;
+    let mut namespace: Option<String> = None;
+    let mut positional: Vec<String> = vec![];
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--depth" => match args.next().map(|n| n.parse()) {
+                Some(Ok(n)) => depth = Some(n),
+                _ => usage(),
+            },
+            "--namespace" => match args.next() {
+                Some(ns) => namespace = Some(ns),
+                None => usage(),
+            },
+            _ => positional.push(arg),
+        }
+    }
 
-    let lines_iter = reader.lines().map(|l| l.unwrap());
+    let mut positional = positional.into_iter();
+    let kind = positional.next().unwrap_or_else(|| usage());
+    let mut node = || positional.next().unwrap_or_else(|| usage());
+
+    let query = match kind.as_str() {
+        "ancestors" => Query::Ancestors(node(), depth),
+        "descendants" => Query::Descendants(node(), depth),
+        "path" => Query::Path(node(), node()),
+        "roots" => Query::Roots(namespace.take()),
+        "leaves" => Query::Leaves(namespace.take()),
+        "neighbours" => Query::Neighbours(node(), depth.unwrap_or(1)),
+        _ => usage(),
+    };
+
+    if namespace.is_some() {
+        eprintln!("--namespace only works with roots and leaves");
+        usage();
+    }
 
-    let mut linum = 1;
+    let filenames: Vec<String> = positional.collect();
 
-    for str in lines_iter {
-        dz.linum = linum;
-        let result = dz.parse_line_with_result(&str);
+    let graph = build_or_exit(&filenames);
 
-        match result {
-            Ok(_) => {}
-            Err(rc) => {
-                panic!("Error on line {}: {}\nContext:'{}'", linum, rc, &str)
-            }
-        };
-        linum += 1;
+    let ids = match query::run(&graph.dz, &query) {
+        Ok(ids) => ids,
+        Err(rc) => {
+            eprintln!("{}", rc);
+            process::exit(1);
+        }
+    };
+
+    let mut f = io::stdout();
+    if json {
+        query::write_json(&graph.dz, &ids, &mut f);
+    } else {
+        query::write_names(&graph.dz, &ids, &mut f);
     }
-}
 
-struct FileMapper {
-    start: usize,
-    end: usize,
+    let no_path = matches!(query, Query::Path(_, _)) && ids.is_empty();
+    process::exit(no_path as i32);
 }
 
-fn main() {
-    let mut stdin = false;
+/// "dagzet plan": print everything to learn before some
+/// target nodes, prerequisites first
+fn plan_main(mut args: impl Iterator<Item = String>) -> ! {
+    let mut targets: Vec<String> = vec![];
+    let mut known: Vec<String> = vec![];
+    let mut filenames: Vec<String> = vec![];
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--target" => match args.next() {
+                Some(node) => targets.push(node),
+                None => usage(),
+            },
+            "--known" => match args.next() {
+                Some(tag) => known.push(tag),
+                None => usage(),
+            },
+            _ => filenames.push(arg),
+        }
+    }
 
-    if env::args().len() < 2 {
-        stdin = true;
+    if targets.is_empty() {
+        usage();
     }
 
-    let mut dz = DagZet::new();
-    let mut file_mappings: Vec<FileMapper> = vec![];
+    let graph = build_or_exit(&filenames);
+
+    match query::study_plan(&graph.dz, &targets, &known) {
+        Ok(ids) => query::write_plan(&graph.dz, &ids, &mut io::stdout()),
+        Err(rc) => {
+            eprintln!("{}", rc);
+            process::exit(1);
+        }
+    }
 
-    let mut start = 0;
-    let mut end;
-    let filenames = env::args().skip(1);
+    process::exit(0);
+}
 
-    if stdin {
-        parse_stdin(&mut dz);
-        end = dz.nodelist.len();
-        file_mappings.push(FileMapper { start, end });
+#[cfg(feature = "database")]
+fn compile_to_database(
+    filenames: &[String],
+    path: &str,
+    append: bool,
+    options: &SqlOptions,
+) -> Result<(), Vec<dagzet::dagzet::DagZetError>> {
+    use dagzet::database::{Database, WriteMode};
+
+    let mode = if append {
+        WriteMode::Append
     } else {
-        for filename in env::args().skip(1) {
-            parse_file(&filename, &mut dz);
-            end = dz.nodelist.len();
-            file_mappings.push(FileMapper { start, end });
-            start = end;
-        }
-    }
-
-    dz.resolve_connections();
-    let unknowns = dz.check_unknown_nodes();
-    if !unknowns.is_empty() {
-        panic!(
-            "There were some unknown nodes:\n{}",
-            unknowns.into_iter().collect::<Vec<_>>().join("\n")
-        );
-    }
-
-    let result = dz.check_for_loops(&dz.generate_edges());
-
-    if result.is_err() {
-        let found_loops = result.unwrap_err();
-        let loop_str = found_loops
-            .iter()
-            .map(|e| {
-                format!(
-                    "{} -> {}",
-                    dz.nodelist[(e[0] - 1) as usize],
-                    dz.nodelist[(e[1] - 1) as usize]
-                )
-            })
-            .collect::<Vec<_>>()
-            .join(",\n");
-        panic!("Loops found:\n{}", loop_str)
-    }
-
-    // Generate nodes table
-    let mut f = io::stdout();
+        WriteMode::Replace
+    };
+
+    let mut db = match Database::open(path, mode) {
+        Ok(db) => db,
+        Err(e) => {
+            eprintln!("could not open {}: {}", path, e);
+            process::exit(1);
+        }
+    };
+
+    compile_with_options(filenames, options, &mut db)?;
 
-    let _ = f.write_all(b"BEGIN;\n");
-    let nodes: Table<NodesTable> = Table::default();
-    nodes.generate(&dz, &mut f);
-    let _ = f.write_all(b"COMMIT;\n");
+    if let Err(e) = db.finish() {
+        eprintln!("could not write to {}: {}", path, e);
+        process::exit(1);
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "database"))]
+fn compile_to_database(
+    _filenames: &[String],
+    _path: &str,
+    _append: bool,
+    _options: &SqlOptions,
+) -> Result<(), Vec<dagzet::dagzet::DagZetError>> {
+    eprintln!("--db needs dagzet to be built with the \"database\" feature");
+    process::exit(1);
+}
+
+/// Write files into a directory, making any directories
+/// they need along the way
+fn write_files<P: AsRef<Path>>(outdir: &Path, files: &BTreeMap<P, String>) {
+    for (path, contents) in files {
+        let path = outdir.join(path);
+        let written = match path.parent() {
+            Some(dir) => fs::create_dir_all(dir).and_then(|_| fs::write(&path, contents)),
+            None => fs::write(&path, contents),
+        };
+        if let Err(e) = written {
+            eprintln!("could not write {}: {}", path.display(), e);
+            process::exit(1);
+        }
+    }
+}
+
+/// "dagzet html": write the graph out as a static site
+fn html_main(mut args: impl Iterator<Item = String>) -> ! {
+    let outdir = match args.next() {
+        Some(dir) if !dir.starts_with("--") => dir,
+        _ => usage(),
+    };
+    let filenames: Vec<String> = args.collect();
+
+    let graph = build_or_exit(&filenames);
+    write_files(Path::new(&outdir), &graph.generate_html());
+    process::exit(0);
+}
+
+/// "dagzet decompile": write a database back out as dagzet
+/// code, one file per namespace, or with --by-file, one per
+/// file the nodes were made in
+#[cfg(feature = "database")]
+fn decompile_main(args: impl Iterator<Item = String>) -> ! {
+    use dagzet::decompile::{decompile, has_table, Layout};
+    use rusqlite::{Connection, OpenFlags};
+
+    let mut layout = Layout::Namespace;
+    let mut positional: Vec<String> = vec![];
+
+    for arg in args {
+        match arg.as_str() {
+            "--by-file" => layout = Layout::File,
+            _ => positional.push(arg),
+        }
+    }
+
+    let (db, outdir) = match positional.as_slice() {
+        [db, outdir] => (db, Path::new(outdir)),
+        _ => usage(),
+    };
+
+    // read-only, so that a missing database isn't created
+    let conn = match Connection::open_with_flags(db, OpenFlags::SQLITE_OPEN_READ_ONLY) {
+        Ok(conn) => conn,
+        Err(e) => {
+            eprintln!("could not open {}: {}", db, e);
+            process::exit(1);
+        }
+    };
+
+    let files = match has_table(&conn, "dz_nodes") {
+        Ok(true) => decompile(&conn, layout),
+        Ok(false) => {
+            eprintln!("{} has no dz_nodes table", db);
+            process::exit(1);
+        }
+        Err(e) => Err(e),
+    };
+
+    let files = match files {
+        Ok(files) => files,
+        Err(e) => {
+            eprintln!("could not read {}: {}", db, e);
+            process::exit(1);
+        }
+    };
+
+    write_files(outdir, &files);
+    process::exit(0);
+}
 
-    let _ = f.write_all(b"BEGIN;\n");
+#[cfg(not(feature = "database"))]
+fn decompile_main(_args: impl Iterator<Item = String>) -> ! {
+    eprintln!("decompile needs dagzet to be built with the \"database\" feature");
+    process::exit(1);
+}
 
-    let connections: Table<ConnectionsTable> = Table::default();
-    connections.generate(&dz, &mut f);
+fn main() {
+    let mut filenames: Vec<String> = vec![];
+    let mut cache: Option<String> = None;
+    let mut db: Option<String> = None;
+    let mut append = false;
+    let mut sql_options = SqlOptions::default();
+    let mut format = "sql".to_string();
+    let mut dot_options = DotOptions::default();
+    let mut around: Option<String> = None;
+    let mut hops: usize = 1;
+    let mut args = env::args().skip(1).peekable();
+
+    if args.peek().map(|s| s.as_str()) == Some("fmt") {
+        args.next();
+        fmt_main(args);
+    }
 
-    let lines: Table<LinesTable> = Table::default();
-    lines.generate(&dz, &mut f);
+    if args.peek().map(|s| s.as_str()) == Some("query") {
+        args.next();
+        query_main(args);
+    }
 
-    let graph_remarks: Table<GraphRemarksTable> = Table::default();
-    graph_remarks.generate(&dz, &mut f);
+    if args.peek().map(|s| s.as_str()) == Some("plan") {
+        args.next();
+        plan_main(args);
+    }
 
-    let connection_remarks: Table<ConnectionRemarksTable> = Table::default();
-    connection_remarks.generate(&dz, &mut f);
+    if args.peek().map(|s| s.as_str()) == Some("html") {
+        args.next();
+        html_main(args);
+    }
 
-    let node_remarks: Table<NodeRemarksTable> = Table::default();
-    node_remarks.generate(&dz, &mut f);
+    if args.peek().map(|s| s.as_str()) == Some("decompile") {
+        args.next();
+        decompile_main(args);
+    }
 
-    let file_ranges: Table<FileRangesTable> = Table::default();
-    file_ranges.generate(&dz, &mut f);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cache" => match args.next() {
+                Some(path) => cache = Some(path),
+                None => usage(),
+            },
+            "--db" => match args.next() {
+                Some(path) => db = Some(path),
+                None => usage(),
+            },
+            "--append" => append = true,
+            "--literal-ids" => sql_options.literal_ids = true,
+            "--search" => sql_options.search = true,
+            "--format" => match args.next() {
+                Some(fmt) if ["sql", "json", "dot", "anki"].contains(&fmt.as_str()) => format = fmt,
+                _ => usage(),
+            },
+            "--namespace" => match args.next() {
+                Some(ns) => dot_options.namespace = Some(ns),
+                None => usage(),
+            },
+            "--around" => match args.next() {
+                Some(node) => around = Some(node),
+                None => usage(),
+            },
+            "--hops" => match args.next().map(|n| n.parse()) {
+                Some(Ok(n)) => hops = n,
+                _ => usage(),
+            },
+            _ => filenames.push(arg),
+        }
+    }
 
-    let hyperlinks: Table<HyperlinksTable> = Table::default();
-    hyperlinks.generate(&dz, &mut f);
+    let mut f = io::stdout();
 
-    let todos: Table<TODOTable> = Table::default();
-    todos.generate(&dz, &mut f);
+    if append && db.is_none() {
+        usage();
+    }
 
-    let tags: Table<TagsTable> = Table::default();
-    tags.generate(&dz, &mut f);
+    dot_options.around = around.map(|node| (node, hops));
 
-    let flashcards: Table<FlashCardsTable> = Table::default();
-    flashcards.generate(&dz, &mut f);
+    if format != "dot" && (dot_options.namespace.is_some() || dot_options.around.is_some()) {
+        eprintln!("--namespace and --around only work with DOT output");
+        usage();
+    }
 
-    let images: Table<ImagesTable> = Table::default();
-    images.generate(&dz, &mut f);
+    let sql_only = cache.is_some() || db.is_some() || sql_options.literal_ids || sql_options.search;
+    if format != "sql" && sql_only {
+        eprintln!("--cache, --db, --literal-ids and --search only work with SQL output");
+        usage();
+    }
 
-    let audio: Table<AudioTable> = Table::default();
-    audio.generate(&dz, &mut f);
+    if sql_options.literal_ids && (cache.is_some() || append) {
+        eprintln!("--literal-ids needs a fresh set of tables, so it can't be");
+        eprintln!("used with --cache or --append");
+        usage();
+    }
 
-    let noderefs: Table<NodeRefsTable> = Table::default();
-    for (idx, filename) in filenames.enumerate() {
-        let mapping = &file_mappings[idx];
-        noderefs.generate_with_filename(
-            &dz,
-            &mut f,
-            Some(&filename.to_string()),
-            mapping.start + 1,
-            mapping.end + 1,
-        );
+    if sql_options.search && cache.is_some() {
+        eprintln!("--search can't be used with --cache");
+        usage();
     }
 
-    let attributes: Table<AttributesTable> = Table::default();
-    attributes.generate(&dz, &mut f);
+    let result = match (&cache, &db) {
+        (Some(_), Some(_)) => usage(),
+        (Some(_), None) if filenames.is_empty() => {
+            eprintln!("--cache needs input files, stdin can't be cached");
+            usage();
+        }
+        (Some(path), None) => compile_incremental(&filenames, path, &mut f),
+        (None, Some(path)) => compile_to_database(&filenames, path, append, &sql_options),
+        (None, None) if format == "json" => {
+            build_files(&filenames).map(|graph| graph.generate_json(&mut f))
+        }
+        (None, None) if format == "anki" => {
+            build_files(&filenames).map(|graph| graph.generate_anki(&mut f))
+        }
+        (None, None) if format == "dot" => build_files(&filenames).map(|graph| {
+            if let Err(rc) = graph.generate_dot(&dot_options, &mut f) {
+                eprintln!("{}", rc);
+                process::exit(1);
+            }
+        }),
+        (None, None) => compile_with_options(&filenames, &sql_options, &mut f),
+    };
 
-    let _ = f.write_all(b"COMMIT;\n");
+    if let Err(errors) = result {
+        for err in &errors {
+            eprintln!("{}", err);
+        }
+        eprintln!("{} error(s) found, no output generated.", errors.len());
+        process::exit(1);
+    }
 }
@@ -0,0 +1,369 @@
+//! Turns lines of dagzet code into commands, without touching
+//! any graph state. Every line is a two-letter command name,
+//! a space, and the arguments. Arguments are split up here, so
+//! anything that works on dagzet code (the compiler, formatters,
+//! editor tooling) sees the same thing.
+//!
+//! Columns in spans count characters, starting from 0.
+
+use crate::dagzet::ReturnCode;
+
+/// A range of columns on a line. The end is exclusive.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Span {
+    pub line: u32,
+    pub start: usize,
+    pub end: usize,
+}
+
+/// A single dagzet command, with its arguments split up
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    Namespace(String),
+    GraphRemark(String),
+    NewNode(String),
+    Line(String),
+    Connect(String, String),
+    ConnectionRemark(String),
+    Comment(String),
+    Include(String),
+    Remark(String),
+    FileRange {
+        filename: String,
+        start: Option<i32>,
+        end: Option<i32>,
+    },
+    Hyperlink(String),
+    Todo(String),
+    Tags(Vec<String>),
+    SelectNode(String),
+    ConnectExternal(String, String),
+    FlashCardFront(String),
+    FlashCardBack(String),
+    Image(String),
+    Equation(String),
+    Page(String),
+    Alias(String, String),
+    Audio(String),
+    Attribute(String, Option<String>),
+}
+
+/// A parsed line of dagzet code
+#[derive(Clone, Debug, PartialEq)]
+pub struct Statement {
+    pub command: Command,
+    /// Where the two-letter command name is
+    pub name_span: Span,
+    /// The arguments, exactly as they were written
+    pub args: String,
+    pub args_span: Span,
+}
+
+/// A line that couldn't be parsed, and the part of it that
+/// was the problem
+#[derive(Debug)]
+pub struct ParseError {
+    pub reason: ReturnCode,
+    pub span: Span,
+}
+
+impl Command {
+    /// The two-letter name of the command
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Namespace(_) => "ns",
+            Command::GraphRemark(_) => "gr",
+            Command::NewNode(_) => "nn",
+            Command::Line(_) => "ln",
+            Command::Connect(_, _) => "co",
+            Command::ConnectionRemark(_) => "cr",
+            Command::Comment(_) => "zz",
+            Command::Include(_) => "in",
+            Command::Remark(_) => "rm",
+            Command::FileRange { .. } => "fr",
+            Command::Hyperlink(_) => "hl",
+            Command::Todo(_) => "td",
+            Command::Tags(_) => "tg",
+            Command::SelectNode(_) => "sn",
+            Command::ConnectExternal(_, _) => "cx",
+            Command::FlashCardFront(_) => "ff",
+            Command::FlashCardBack(_) => "fb",
+            Command::Image(_) => "im",
+            Command::Equation(_) => "eq",
+            Command::Page(_) => "pg",
+            Command::Alias(_, _) => "al",
+            Command::Audio(_) => "au",
+            Command::Attribute(_, _) => "at",
+        }
+    }
+}
+
+fn number(arg: &str) -> Result<i32, ReturnCode> {
+    arg.parse::<i32>()
+        .map_err(|_| ReturnCode::InvalidNumber(arg.to_string()))
+}
+
+/// The first two whitespace-separated arguments. Partial
+/// lines leave out the ones that haven't been written yet.
+fn pair(args: &str, partial: bool) -> Result<(String, String), ReturnCode> {
+    let args: Vec<_> = args.split_whitespace().collect();
+    if args.len() < 2 && !partial {
+        return Err(ReturnCode::NotEnoughArgs);
+    }
+    let arg = |idx: usize| args.get(idx).map_or(String::new(), |s| s.to_string());
+    Ok((arg(0), arg(1)))
+}
+
+/// The first whitespace-separated argument
+fn first(args: &str, partial: bool) -> Result<String, ReturnCode> {
+    match args.split_whitespace().next() {
+        Some(arg) => Ok(arg.to_string()),
+        None if partial => Ok(String::new()),
+        None => Err(ReturnCode::NotEnoughArgs),
+    }
+}
+
+/// Arguments that are used as a whole, and can't be blank
+fn nonblank(args: &str) -> Result<String, ReturnCode> {
+    let args = args.trim();
+    if args.is_empty() {
+        return Err(ReturnCode::NotEnoughArgs);
+    }
+    Ok(args.to_string())
+}
+
+fn file_range(args: &str) -> Result<Command, ReturnCode> {
+    let args: Vec<_> = args.split_whitespace().collect();
+
+    if args.is_empty() {
+        return Err(ReturnCode::NotEnoughArgs);
+    }
+
+    let start = args.get(1).map(|s| number(s)).transpose()?;
+    let end = args.get(2).map(|s| number(s)).transpose()?;
+
+    if let (Some(start), Some(end)) = (start, end) {
+        if start >= 0 && end >= 0 && start > end {
+            return Err(ReturnCode::InvalidRange(start, end));
+        }
+    }
+
+    Ok(Command::FileRange {
+        filename: args[0].to_string(),
+        start,
+        end,
+    })
+}
+
+fn command(name: &str, args: &str, partial: bool) -> Result<Command, ReturnCode> {
+    let text = args.to_string();
+
+    let cmd = match name {
+        "ns" => Command::Namespace(nonblank(args)?),
+        "gr" => Command::GraphRemark(text),
+        "nn" => Command::NewNode(nonblank(args)?),
+        "ln" => Command::Line(text),
+        "co" => {
+            let (left, right) = pair(args, partial)?;
+            Command::Connect(left, right)
+        }
+        "cr" => Command::ConnectionRemark(text),
+        "zz" => Command::Comment(text),
+        "in" => Command::Include(nonblank(args)?),
+        "rm" => Command::Remark(text),
+        "fr" => file_range(args)?,
+        "hl" => Command::Hyperlink(first(args, partial)?),
+        "td" if args.trim().is_empty() => return Err(ReturnCode::NotEnoughArgs),
+        "td" => Command::Todo(text),
+        "tg" => Command::Tags(args.split_whitespace().map(String::from).collect()),
+        "sn" => Command::SelectNode(first(args, partial)?),
+        "cx" => {
+            let (left, right) = pair(args, partial)?;
+            Command::ConnectExternal(left, right)
+        }
+        "ff" => Command::FlashCardFront(text),
+        "fb" => Command::FlashCardBack(text),
+        "im" => Command::Image(text),
+        "eq" => Command::Equation(nonblank(args)?),
+        "pg" => Command::Page(nonblank(args)?),
+        "al" => {
+            let (name, path) = pair(args, partial)?;
+            let name = name.strip_prefix('@').unwrap_or(&name).to_string();
+            Command::Alias(name, path)
+        }
+        "au" => Command::Audio(text),
+        "at" => {
+            let mut args = args.split_whitespace();
+            let key = match args.next() {
+                Some(key) => key.to_string(),
+                None => return Err(ReturnCode::NotEnoughArgs),
+            };
+            Command::Attribute(key, args.next().map(String::from))
+        }
+        _ => return Err(ReturnCode::InvalidCommand),
+    };
+
+    Ok(cmd)
+}
+
+/// Parse a single line of dagzet code. Empty lines have
+/// nothing in them, and give None.
+pub fn parse_line(line: &str, linum: u32) -> Result<Option<Statement>, ParseError> {
+    parse(line, linum, false)
+}
+
+/// Parse a line that is still being written, for editors.
+/// Arguments that haven't been typed yet (in co, cx, hl, sn
+/// and al) are left empty instead of being an error.
+pub fn parse_partial_line(line: &str, linum: u32) -> Result<Option<Statement>, ParseError> {
+    parse(line, linum, true)
+}
+
+fn parse(line: &str, linum: u32, partial: bool) -> Result<Option<Statement>, ParseError> {
+    if line.is_empty() {
+        return Ok(None);
+    }
+
+    let span = |start, end| Span {
+        line: linum,
+        start,
+        end,
+    };
+
+    let mut chars = line.char_indices();
+    let name_end = match chars.nth(2) {
+        Some((pos, ' ')) => pos,
+        None if line.chars().count() == 2 => line.len(),
+        _ => {
+            let len = line.chars().count();
+            return Err(ParseError {
+                reason: ReturnCode::InvalidCommand,
+                span: span(0, len.min(2)),
+            });
+        }
+    };
+
+    let name = &line[..name_end];
+    let args = line.get(name_end + 1..).unwrap_or("");
+    let name_span = span(0, 2);
+    let args_span = span(3, 3 + args.chars().count());
+
+    match command(name, args, partial) {
+        Ok(command) => Ok(Some(Statement {
+            command,
+            name_span,
+            args: args.to_string(),
+            args_span,
+        })),
+        Err(reason) => {
+            let span = match reason {
+                ReturnCode::InvalidCommand => name_span,
+                _ => args_span,
+            };
+            Err(ParseError { reason, span })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Command {
+        parse_line(line, 1).unwrap().unwrap().command
+    }
+
+    fn error(line: &str) -> ReturnCode {
+        parse_line(line, 1).unwrap_err().reason
+    }
+
+    #[test]
+    fn test_commands() {
+        assert_eq!(parse("ns  a/b "), Command::Namespace("a/b".to_string()));
+        assert_eq!(parse("ln  kept "), Command::Line(" kept ".to_string()));
+        assert_eq!(
+            parse("co a b c"),
+            Command::Connect("a".to_string(), "b".to_string())
+        );
+        assert_eq!(
+            parse("fr f.txt 3"),
+            Command::FileRange {
+                filename: "f.txt".to_string(),
+                start: Some(3),
+                end: None,
+            }
+        );
+        assert_eq!(
+            parse("al @ks a/b"),
+            Command::Alias("ks".to_string(), "a/b".to_string())
+        );
+        assert_eq!(parse("at key"), Command::Attribute("key".to_string(), None));
+        assert_eq!(parse("tg"), Command::Tags(vec![]));
+        assert!(matches!(error("co a"), ReturnCode::NotEnoughArgs));
+        assert!(matches!(error("fr f 2 1"), ReturnCode::InvalidRange(2, 1)));
+        assert!(matches!(error("fr f x"), ReturnCode::InvalidNumber(s) if s == "x"));
+    }
+
+    #[test]
+    fn test_partial_lines() {
+        let partial = |line| parse_partial_line(line, 1).unwrap().unwrap().command;
+        assert_eq!(
+            partial("co a"),
+            Command::Connect("a".to_string(), String::new())
+        );
+        assert_eq!(partial("sn "), Command::SelectNode(String::new()));
+        assert!(parse_partial_line("nn ", 1).is_err());
+    }
+
+    #[test]
+    fn test_invalid_lines() {
+        for line in ["xx hello", "n", "nnx", "é", "aé b", "ns"] {
+            assert!(parse_line(line, 1).is_err(), "{} should not parse", line);
+        }
+        assert_eq!(parse_line("", 1).unwrap(), None);
+
+        let err = parse_line("aé b", 4).unwrap_err();
+        assert!(matches!(err.reason, ReturnCode::InvalidCommand));
+        assert_eq!(
+            err.span,
+            Span {
+                line: 4,
+                start: 0,
+                end: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_spans() {
+        let stmt = parse_line("ln héllo", 7).unwrap().unwrap();
+        assert_eq!(
+            stmt.name_span,
+            Span {
+                line: 7,
+                start: 0,
+                end: 2
+            }
+        );
+        assert_eq!(
+            stmt.args_span,
+            Span {
+                line: 7,
+                start: 3,
+                end: 8
+            }
+        );
+        assert_eq!(stmt.args, "héllo");
+        assert_eq!(stmt.command.name(), "ln");
+
+        let err = parse_line("co a", 2).unwrap_err();
+        assert_eq!(
+            err.span,
+            Span {
+                line: 2,
+                start: 3,
+                end: 4
+            }
+        );
+    }
+}
@@ -0,0 +1,380 @@
+//! Graph queries, answered directly on the parsed graph
+//! instead of going through SQL. "co a b" is an edge from
+//! a to b, making b a parent of a. Ancestors are found by
+//! following edges forward, and descendants by following
+//! them backward.
+
+use crate::dagzet::{in_namespace, namespace, DagZet, ReturnCode};
+use crate::json;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::io;
+
+/// A question to ask about the graph. Nodes are given by
+/// their full name, a suffix starting with '?', or an alias
+/// starting with '@' (see DagZet::lookup).
+#[derive(Debug, PartialEq)]
+pub enum Query {
+    /// Everything a node points to, directly or not, up to
+    /// an optional depth
+    Ancestors(String, Option<usize>),
+    /// Everything that points to a node, directly or not, up
+    /// to an optional depth
+    Descendants(String, Option<usize>),
+    /// The shortest path of edges from one node to another
+    Path(String, String),
+    /// Nodes without parents. With a namespace, nodes in it
+    /// without parents in it.
+    Roots(Option<String>),
+    /// Nodes without children. With a namespace, nodes in it
+    /// without children in it.
+    Leaves(Option<String>),
+    /// Nodes within some number of edges of a node, following
+    /// edges in either direction
+    Neighbours(String, usize),
+}
+
+fn adjacency(edges: impl Iterator<Item = (u32, u32)>) -> HashMap<u32, Vec<u32>> {
+    let mut adj: HashMap<u32, Vec<u32>> = HashMap::new();
+    for (from, to) in edges {
+        adj.entry(from).or_default().push(to);
+    }
+    adj
+}
+
+fn forward(edges: &[[u32; 2]]) -> impl Iterator<Item = (u32, u32)> + '_ {
+    edges.iter().map(|[left, right]| (*left, *right))
+}
+
+fn backward(edges: &[[u32; 2]]) -> impl Iterator<Item = (u32, u32)> + '_ {
+    edges.iter().map(|[left, right]| (*right, *left))
+}
+
+/// Nodes reachable from start in breadth-first order, not
+/// including start itself
+fn reachable(adj: &HashMap<u32, Vec<u32>>, start: u32, depth: Option<usize>) -> Vec<u32> {
+    let mut found = HashSet::from([start]);
+    let mut order = vec![];
+    let mut queue = VecDeque::from([(start, 0)]);
+
+    while let Some((id, d)) = queue.pop_front() {
+        if Some(d) == depth {
+            continue;
+        }
+        for next in adj.get(&id).into_iter().flatten() {
+            if found.insert(*next) {
+                order.push(*next);
+                queue.push_back((*next, d + 1));
+            }
+        }
+    }
+
+    order
+}
+
+/// The shortest path from one node to another, including
+/// both ends. Empty if there isn't one.
+fn shortest_path(adj: &HashMap<u32, Vec<u32>>, from: u32, to: u32) -> Vec<u32> {
+    let mut prev: HashMap<u32, u32> = HashMap::new();
+    let mut queue = VecDeque::from([from]);
+
+    while let Some(id) = queue.pop_front() {
+        if id == to {
+            let mut path = vec![to];
+            let mut id = to;
+            while let Some(p) = prev.get(&id) {
+                path.push(*p);
+                id = *p;
+            }
+            path.reverse();
+            return path;
+        }
+        for next in adj.get(&id).into_iter().flatten() {
+            if *next != from && !prev.contains_key(next) {
+                prev.insert(*next, id);
+                queue.push_back(*next);
+            }
+        }
+    }
+
+    vec![]
+}
+
+/// Nodes with no edges in the adjacency list leading to
+/// another node in the namespace (or anywhere, without one)
+fn ends(dz: &DagZet, adj: &HashMap<u32, Vec<u32>>, ns: Option<&str>) -> Vec<u32> {
+    let included = |id: &u32| match ns {
+        Some(ns) => in_namespace(&dz.nodelist[*id as usize - 1], ns),
+        None => true,
+    };
+
+    (1..=dz.nodelist.len() as u32)
+        .filter(included)
+        .filter(|id| !adj.get(id).into_iter().flatten().any(included))
+        .collect()
+}
+
+/// Answer a query, giving node IDs. Nodes found by searching
+/// out from a node are in order of distance; everything else
+/// is in the order the nodes were made.
+pub fn run(dz: &DagZet, query: &Query) -> Result<Vec<u32>, ReturnCode> {
+    let edges = dz.generate_edges();
+
+    let ids = match query {
+        Query::Ancestors(name, depth) => {
+            reachable(&adjacency(forward(&edges)), dz.lookup(name)?, *depth)
+        }
+        Query::Descendants(name, depth) => {
+            reachable(&adjacency(backward(&edges)), dz.lookup(name)?, *depth)
+        }
+        Query::Path(from, to) => {
+            let (from, to) = (dz.lookup(from)?, dz.lookup(to)?);
+            shortest_path(&adjacency(forward(&edges)), from, to)
+        }
+        Query::Roots(ns) => ends(dz, &adjacency(forward(&edges)), ns.as_deref()),
+        Query::Leaves(ns) => ends(dz, &adjacency(backward(&edges)), ns.as_deref()),
+        Query::Neighbours(name, hops) => {
+            let adj = adjacency(forward(&edges).chain(backward(&edges)));
+            reachable(&adj, dz.lookup(name)?, Some(*hops))
+        }
+    };
+
+    Ok(ids)
+}
+
+/// Everything that has to be understood before a set of
+/// targets, and the targets themselves, in an order where
+/// every node comes after its prerequisites. Ties go to the
+/// first namespace, then the node made first. Nodes with any
+/// of the known tags are left out, along with anything only
+/// needed through them.
+pub fn study_plan(
+    dz: &DagZet,
+    targets: &[String],
+    known: &[String],
+) -> Result<Vec<u32>, ReturnCode> {
+    let edges = dz.generate_edges();
+    let prereqs = adjacency(forward(&edges));
+    let dependents = adjacency(backward(&edges));
+
+    let is_known = |id: &u32| match dz.tags.get(id) {
+        Some(tags) => known.iter().any(|tag| tags.contains(tag)),
+        None => false,
+    };
+
+    let mut stack = vec![];
+    for target in targets {
+        stack.push(dz.lookup(target)?);
+    }
+
+    let mut needed: HashSet<u32> = HashSet::new();
+    while let Some(id) = stack.pop() {
+        if is_known(&id) || !needed.insert(id) {
+            continue;
+        }
+        stack.extend(prereqs.get(&id).into_iter().flatten());
+    }
+
+    let mut waiting: HashMap<u32, usize> = HashMap::new();
+    let mut ready: BTreeSet<(&str, u32)> = BTreeSet::new();
+    let key = |id: u32| (namespace(&dz.nodelist[id as usize - 1]), id);
+
+    for id in &needed {
+        let n = prereqs
+            .get(id)
+            .into_iter()
+            .flatten()
+            .filter(|p| needed.contains(p))
+            .count();
+        if n == 0 {
+            ready.insert(key(*id));
+        } else {
+            waiting.insert(*id, n);
+        }
+    }
+
+    let mut plan = vec![];
+    while let Some((_, id)) = ready.pop_first() {
+        plan.push(id);
+        for next in dependents.get(&id).into_iter().flatten() {
+            if let Some(n) = waiting.get_mut(next) {
+                *n -= 1;
+                if *n == 0 {
+                    waiting.remove(next);
+                    ready.insert(key(*next));
+                }
+            }
+        }
+    }
+
+    Ok(plan)
+}
+
+/// Write a study plan as numbered steps, each with the
+/// node's first line and its TODO, if it has them
+pub fn write_plan(dz: &DagZet, ids: &[u32], f: &mut impl io::Write) {
+    for (step, id) in (1..).zip(ids) {
+        let _ = writeln!(f, "{}. {}", step, dz.nodelist[*id as usize - 1]);
+        if let Some(line) = dz.lines.get(id).and_then(|lines| lines.first()) {
+            let _ = writeln!(f, "   {}", line);
+        }
+        if let Some(todo) = dz.todos.get(id) {
+            let _ = writeln!(f, "   TODO: {}", todo);
+        }
+    }
+}
+
+/// Write node names, one per line
+pub fn write_names(dz: &DagZet, ids: &[u32], f: &mut impl io::Write) {
+    for id in ids {
+        let _ = writeln!(f, "{}", dz.nodelist[*id as usize - 1]);
+    }
+}
+
+/// Write node names as a JSON array
+pub fn write_json(dz: &DagZet, ids: &[u32], f: &mut impl io::Write) {
+    let names: Vec<String> = ids
+        .iter()
+        .map(|id| json::string(&dz.nodelist[*id as usize - 1]))
+        .collect();
+    let _ = writeln!(f, "[{}]", names.join(","));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph() -> DagZet {
+        let mut dz = DagZet::new();
+        let code = [
+            "ns a", "nn b", "nn c", "nn d", "co b c", "co c d", "ns a/e", "nn f", "nn g", "co g f",
+            "nn h", "ns a", "co e/f c", "al e a/e",
+        ];
+        for line in code {
+            dz.parse_line_with_result(line).unwrap();
+        }
+        dz.validate().unwrap();
+        dz
+    }
+
+    fn names(dz: &DagZet, query: Query) -> Vec<String> {
+        run(dz, &query)
+            .unwrap()
+            .iter()
+            .map(|id| dz.nodelist[*id as usize - 1].clone())
+            .collect()
+    }
+
+    fn node(name: &str) -> String {
+        name.to_string()
+    }
+
+    #[test]
+    fn test_ancestors_and_descendants() {
+        let dz = graph();
+        assert_eq!(
+            names(&dz, Query::Ancestors(node("a/e/g"), None)),
+            ["a/e/f", "a/c", "a/d"]
+        );
+        assert_eq!(
+            names(&dz, Query::Ancestors(node("a/e/g"), Some(2))),
+            ["a/e/f", "a/c"]
+        );
+        assert_eq!(
+            names(&dz, Query::Descendants(node("?c"), None)),
+            ["a/b", "a/e/f", "a/e/g"]
+        );
+        assert!(names(&dz, Query::Descendants(node("a/e/h"), None)).is_empty());
+    }
+
+    #[test]
+    fn test_path() {
+        let dz = graph();
+        assert_eq!(
+            names(&dz, Query::Path(node("a/e/g"), node("a/d"))),
+            ["a/e/g", "a/e/f", "a/c", "a/d"]
+        );
+        assert!(names(&dz, Query::Path(node("a/d"), node("a/b"))).is_empty());
+        assert_eq!(names(&dz, Query::Path(node("a/b"), node("a/b"))), ["a/b"]);
+    }
+
+    #[test]
+    fn test_roots_and_leaves() {
+        let dz = graph();
+        assert_eq!(names(&dz, Query::Roots(None)), ["a/d", "a/e/h"]);
+        assert_eq!(names(&dz, Query::Leaves(None)), ["a/b", "a/e/g", "a/e/h"]);
+        assert_eq!(
+            names(&dz, Query::Roots(Some(node("a/e")))),
+            ["a/e/f", "a/e/h"]
+        );
+    }
+
+    #[test]
+    fn test_neighbours() {
+        let dz = graph();
+        assert_eq!(
+            names(&dz, Query::Neighbours(node("a/c"), 1)),
+            ["a/d", "a/b", "a/e/f"]
+        );
+        assert!(matches!(
+            run(&dz, &Query::Neighbours(node("a/nope"), 1)),
+            Err(ReturnCode::UnknownNode(name)) if name == "a/nope"
+        ));
+        assert!(matches!(
+            run(&dz, &Query::Ancestors(node("?x"), None)),
+            Err(ReturnCode::SuffixNotFound(_))
+        ));
+        assert_eq!(names(&dz, Query::Neighbours(node("@e/g"), 1)), ["a/e/f"]);
+    }
+
+    #[test]
+    fn test_study_plan() {
+        let mut dz = DagZet::new();
+        let code = [
+            "ns a/z",
+            "nn basics",
+            "ln start here",
+            "ns a",
+            "nn x",
+            "td write this",
+            "nn y",
+            "tg known",
+            "nn extra",
+            "nn goal",
+            "co goal x",
+            "co goal y",
+            "co x z/basics",
+            "co y extra",
+        ];
+        for line in code {
+            dz.parse_line_with_result(line).unwrap();
+        }
+
+        let plan = |known: &[String]| {
+            let ids = study_plan(&dz, &[node("a/goal")], known).unwrap();
+            let names: Vec<&str> = ids
+                .iter()
+                .map(|id| dz.nodelist[*id as usize - 1].as_str())
+                .collect();
+            names.join(" ")
+        };
+
+        assert_eq!(plan(&[]), "a/extra a/y a/z/basics a/x a/goal");
+        assert_eq!(plan(&[node("known")]), "a/z/basics a/x a/goal");
+
+        let mut out: Vec<u8> = vec![];
+        let ids = study_plan(&dz, &[node("a/x")], &[]).unwrap();
+        write_plan(&dz, &ids, &mut out);
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "1. a/z/basics\n   start here\n2. a/x\n   TODO: write this\n"
+        );
+    }
+
+    #[test]
+    fn test_json() {
+        let dz = graph();
+        let mut out: Vec<u8> = vec![];
+        write_json(&dz, &run(&dz, &Query::Roots(None)).unwrap(), &mut out);
+        assert_eq!(String::from_utf8(out).unwrap(), "[\"a/d\",\"a/e/h\"]\n");
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::HashMap;
+use std::io;
 use std::marker::PhantomData;
 
 #[derive(Clone)]
@@ -7,6 +9,7 @@ pub enum ParamType {
     Integer,
     Text,
     IntegerNotNull,
+    TextNotNull,
 }
 
 pub trait SQLize {
@@ -14,21 +17,174 @@ pub trait SQLize {
 }
 
 pub trait Row<T> {
-    fn sqlize_values(&self) -> String;
+    /// The values, in column order. These are rendered as
+    /// SQL literals, or bound to a prepared statement.
+    fn values(&self) -> Vec<Value>;
+}
+
+/// A single value in a row. Lookups are subqueries that turn
+/// a key (such as a node name) into a row ID. The subquery
+/// has a single '?' placeholder where the key goes.
+///
+/// This is the only way values make it into generated SQL,
+/// so text always gets escaped.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Integer(i64),
+    Text(String),
+    Lookup(&'static str, String),
+}
+
+impl SQLize for Value {
+    /// Render the value as a SQL literal
+    fn sqlize(&self) -> String {
+        match self {
+            Value::Null => "NULL".to_string(),
+            Value::Integer(i) => i.to_string(),
+            Value::Text(s) => format!("'{}'", escape_quotes(s)),
+            Value::Lookup(query, key) => {
+                query.replacen('?', &format!("'{}'", escape_quotes(key)), 1)
+            }
+        }
+    }
+}
+
+impl Value {
+    /// What this value looks like in a prepared statement
+    pub fn placeholder(&self) -> &'static str {
+        match self {
+            Value::Lookup(query, _) => query,
+            _ => "?",
+        }
+    }
+}
+
+/// Somewhere generated SQL can go. Anything implementing
+/// io::Write gets SQL code as text. With the "database"
+/// feature, a Database runs the statements directly.
+pub trait Output {
+    /// Run one or more SQL statements as-is
+    fn execute(&mut self, sql: &str);
+
+    fn create<T>(&mut self, table: &Table<T>) {
+        self.execute(&table.sqlize());
+        self.execute(&table.sqlize_indexes());
+    }
+
+    fn insert<T>(&mut self, table: &Table<T>, row: &impl Row<T>) {
+        self.insert_values(table, row.values());
+    }
+
+    fn insert_values<T>(&mut self, table: &Table<T>, values: Vec<Value>);
+}
+
+impl<W: io::Write> Output for W {
+    fn execute(&mut self, sql: &str) {
+        let _ = self.write_all(sql.as_bytes());
+    }
+
+    fn insert_values<T>(&mut self, table: &Table<T>, values: Vec<Value>) {
+        self.execute(&table.sqlize_insert_values(&values));
+    }
+}
+
+/// Row IDs that are already known, so that lookups can be
+/// written as integer literals instead of subqueries
+#[derive(Default)]
+pub struct Ids {
+    ids: HashMap<(&'static str, String), i64>,
+}
+
+impl Ids {
+    pub fn insert(&mut self, query: &'static str, key: &str, id: i64) {
+        self.ids.insert((query, key.to_string()), id);
+    }
+
+    pub fn get(&self, query: &'static str, key: &str) -> Option<i64> {
+        self.ids.get(&(query, key.to_string())).copied()
+    }
+
+    /// Replace a lookup with its ID. Lookups that can't be
+    /// resolved become NULL, like the subquery would.
+    pub fn resolve(&self, value: Value) -> Value {
+        match value {
+            Value::Lookup(query, key) => match self.get(query, &key) {
+                Some(id) => Value::Integer(id),
+                None => Value::Null,
+            },
+            value => value,
+        }
+    }
+}
+
+/// Wraps another output, resolving every lookup to a
+/// literal ID before it gets written
+pub struct WithIds<'a, O> {
+    out: &'a mut O,
+    ids: &'a Ids,
+}
+
+impl<'a, O: Output> WithIds<'a, O> {
+    pub fn new(out: &'a mut O, ids: &'a Ids) -> Self {
+        WithIds { out, ids }
+    }
+}
+
+impl<O: Output> Output for WithIds<'_, O> {
+    fn execute(&mut self, sql: &str) {
+        self.out.execute(sql);
+    }
+
+    fn create<T>(&mut self, table: &Table<T>) {
+        self.out.create(table);
+    }
+
+    fn insert_values<T>(&mut self, table: &Table<T>, values: Vec<Value>) {
+        let values = values.into_iter().map(|v| self.ids.resolve(v)).collect();
+        self.out.insert_values(table, values);
+    }
 }
 
 #[derive(Clone)]
 pub struct Param {
     name: String,
     ptype: ParamType,
+    /// The table and column this one refers to, if any
+    references: Option<(String, String)>,
 }
 
 pub struct Table<T> {
     name: String,
     columns: Vec<Param>,
+    /// Groups of columns that must be unique together
+    unique: Vec<Vec<String>>,
+    /// Groups of columns to index
+    indexes: Vec<Vec<String>>,
+    /// Whether inserts include the primary key
+    insert_keys: bool,
     phantom: PhantomData<T>,
 }
 
+/// A named query, created after the tables it reads from
+pub struct View {
+    name: String,
+    query: String,
+}
+
+/// An FTS5 virtual table, for full-text search
+pub struct SearchTable {
+    name: String,
+    columns: Vec<String>,
+}
+
+/// Statements run by SQLite whenever a table changes
+pub struct Trigger {
+    name: String,
+    event: String,
+    body: Vec<String>,
+}
+
 impl SQLize for ParamType {
     fn sqlize(&self) -> String {
         match self {
@@ -37,13 +193,18 @@ impl SQLize for ParamType {
             ParamType::Integer => "INTEGER".to_string(),
             ParamType::Text => "TEXT".to_string(),
             ParamType::IntegerNotNull => "INTEGER NOT NULL".to_string(),
+            ParamType::TextNotNull => "TEXT NOT NULL".to_string(),
         }
     }
 }
 
 impl SQLize for Param {
     fn sqlize(&self) -> String {
-        format!("{} {}", self.name, self.ptype.sqlize())
+        let mut sql = format!("{} {}", self.name, self.ptype.sqlize());
+        if let Some((table, column)) = &self.references {
+            sql.push_str(&format!(" REFERENCES {}({})", table, column));
+        }
+        sql
     }
 }
 
@@ -56,17 +217,91 @@ impl<T> SQLize for Table<T> {
             params.push(format!("    {}", col.sqlize()));
         }
 
+        for columns in &self.unique {
+            params.push(format!("    UNIQUE({})", columns.join(", ")));
+        }
+
         sql.push_str(&params.join(",\n"));
         sql.push_str("\n);\n");
         sql
     }
 }
 
+impl SQLize for View {
+    fn sqlize(&self) -> String {
+        format!(
+            "CREATE VIEW IF NOT EXISTS {} AS\n{};\n",
+            self.name, self.query
+        )
+    }
+}
+
+impl SQLize for SearchTable {
+    fn sqlize(&self) -> String {
+        format!(
+            "CREATE VIRTUAL TABLE IF NOT EXISTS {} USING fts5({});\n",
+            self.name,
+            self.columns.join(", ")
+        )
+    }
+}
+
+impl SQLize for Trigger {
+    fn sqlize(&self) -> String {
+        let mut sql = format!(
+            "CREATE TRIGGER IF NOT EXISTS {} {}\nBEGIN\n",
+            self.name, self.event
+        );
+        for stmt in &self.body {
+            sql.push_str(&format!("    {};\n", stmt));
+        }
+        sql.push_str("END;\n");
+        sql
+    }
+}
+
 impl Param {
     pub fn new(name: &str, ptype: ParamType) -> Self {
         Param {
             name: name.to_string(),
             ptype,
+            references: None,
+        }
+    }
+
+    /// Make this column a foreign key
+    pub fn references(mut self, table: &str, column: &str) -> Self {
+        self.references = Some((table.to_string(), column.to_string()));
+        self
+    }
+}
+
+impl View {
+    pub fn new(name: &str, query: &str) -> Self {
+        View {
+            name: name.to_string(),
+            query: query.to_string(),
+        }
+    }
+}
+
+impl SearchTable {
+    pub fn new(name: &str, columns: &[&str]) -> Self {
+        SearchTable {
+            name: name.to_string(),
+            columns: columns.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+}
+
+impl Trigger {
+    /// The event is everything between the trigger name and
+    /// BEGIN, such as "AFTER INSERT ON dz_lines".
+    pub fn new(name: &str, event: &str, body: Vec<String>) -> Self {
+        Trigger {
+            name: name.to_string(),
+            event: event.to_string(),
+            body,
         }
     }
 }
@@ -76,15 +311,59 @@ impl<T> Table<T> {
         Table::<T> {
             name: name.to_string(),
             columns: vec![],
+            unique: vec![],
+            indexes: vec![],
+            insert_keys: false,
             phantom: PhantomData,
         }
     }
 
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
     pub fn add_column(&mut self, param: &Param) {
         self.columns.push(param.clone());
     }
 
-    pub fn sqlize_insert(&self, row: &impl Row<T>) -> String {
+    /// Include the primary key in inserts, for rows that
+    /// bring their own IDs instead of having them assigned
+    pub fn insert_keys(&mut self) {
+        self.insert_keys = true;
+    }
+
+    pub fn inserts_keys(&self) -> bool {
+        self.insert_keys
+    }
+
+    /// Require a group of columns to be unique together
+    pub fn add_unique(&mut self, columns: &[&str]) {
+        self.unique
+            .push(columns.iter().map(|c| c.to_string()).collect());
+    }
+
+    /// Index a group of columns
+    pub fn add_index(&mut self, columns: &[&str]) {
+        self.indexes
+            .push(columns.iter().map(|c| c.to_string()).collect());
+    }
+
+    /// CREATE INDEX statements for every index on the table
+    pub fn sqlize_indexes(&self) -> String {
+        let mut sql = String::new();
+        for columns in &self.indexes {
+            sql.push_str(&format!(
+                "CREATE INDEX IF NOT EXISTS {}_{} ON {}({});\n",
+                self.name,
+                columns.join("_"),
+                self.name,
+                columns.join(", ")
+            ));
+        }
+        sql
+    }
+
+    fn sqlize_insert_with(&self, values: &str) -> String {
         let mut sql = "".to_string();
 
         sql.push_str(&format!("INSERT INTO {}(", self.name));
@@ -92,17 +371,56 @@ impl<T> Table<T> {
         let mut params: Vec<String> = vec![];
 
         for col in &self.columns {
-            if !matches!(col.ptype, ParamType::IntegerPrimaryKey) {
+            if self.insert_keys || !matches!(col.ptype, ParamType::IntegerPrimaryKey) {
                 params.push(col.name.to_string());
             }
         }
 
         sql.push_str(&params.join(", "));
         sql.push_str(")\nVALUES(");
-        sql.push_str(&row.sqlize_values());
+        sql.push_str(values);
         sql.push_str(");\n");
         sql
     }
+
+    pub fn sqlize_insert(&self, row: &impl Row<T>) -> String {
+        self.sqlize_insert_values(&row.values())
+    }
+
+    pub fn sqlize_insert_values(&self, values: &[Value]) -> String {
+        let values: Vec<String> = values.iter().map(|v| v.sqlize()).collect();
+        self.sqlize_insert_with(&values.join(", "))
+    }
+
+    /// An INSERT statement with placeholders, meant to be
+    /// prepared and then given the values to bind.
+    pub fn insert_statement(&self, values: &[Value]) -> String {
+        let placeholders: Vec<&str> = values.iter().map(|v| v.placeholder()).collect();
+        self.sqlize_insert_with(&placeholders.join(", "))
+    }
+
+    /// Like sqlize_insert, but if a row with the same key
+    /// already exists, the other columns get updated instead.
+    pub fn sqlize_upsert(&self, row: &impl Row<T>, key: &str) -> String {
+        let mut sql = self.sqlize_insert(row);
+
+        // strip the trailing ";\n" and append the conflict clause
+        sql.truncate(sql.len() - 2);
+
+        let updates: Vec<String> = self
+            .columns
+            .iter()
+            .filter(|col| col.name != key && !matches!(col.ptype, ParamType::IntegerPrimaryKey))
+            .map(|col| format!("{} = excluded.{}", col.name, col.name))
+            .collect();
+
+        sql.push_str(&format!(
+            "\nON CONFLICT({}) DO UPDATE SET {};\n",
+            key,
+            updates.join(", ")
+        ));
+        sql
+    }
 }
 
 pub fn escape_quotes(str: &str) -> String {
@@ -129,8 +447,11 @@ mod tests {
     }
 
     impl<TestTable> Row<TestTable> for TestRow {
-        fn sqlize_values(&self) -> String {
-            format!("'{}', {}", self.name, self.position)
+        fn values(&self) -> Vec<Value> {
+            vec![
+                Value::Text(self.name.clone()),
+                Value::Integer(self.position as i64),
+            ]
         }
     }
 
@@ -144,6 +465,7 @@ mod tests {
         let p = Param {
             ptype: ParamType::TextUnique,
             name: "name".to_string(),
+            references: None,
         };
 
         assert_eq!(p.sqlize(), "name TEXT UNIQUE", "unexpected SQLite code");
@@ -174,6 +496,64 @@ mod tests {
         assert_eq!(tab.sqlize(), expected);
     }
 
+    #[test]
+    fn sqlize_constraints() {
+        let mut tab: Table<TestTable> = Table::new("dz_connections");
+        tab.add_column(&Param::new("left", ParamType::IntegerNotNull).references("dz_nodes", "id"));
+        tab.add_column(
+            &Param::new("right", ParamType::IntegerNotNull).references("dz_nodes", "id"),
+        );
+        tab.add_unique(&["left", "right"]);
+        tab.add_index(&["right"]);
+
+        let expected = concat!(
+            "CREATE TABLE IF NOT EXISTS dz_connections(\n",
+            "    left INTEGER NOT NULL REFERENCES dz_nodes(id),\n",
+            "    right INTEGER NOT NULL REFERENCES dz_nodes(id),\n",
+            "    UNIQUE(left, right)\n",
+            ");\n"
+        );
+
+        assert_eq!(tab.sqlize(), expected);
+        assert_eq!(
+            tab.sqlize_indexes(),
+            "CREATE INDEX IF NOT EXISTS dz_connections_right ON dz_connections(right);\n"
+        );
+    }
+
+    #[test]
+    fn sqlize_view() {
+        let view = View::new("dz_names", "SELECT name FROM dz_nodes");
+        assert_eq!(
+            view.sqlize(),
+            "CREATE VIEW IF NOT EXISTS dz_names AS\nSELECT name FROM dz_nodes;\n"
+        );
+    }
+
+    #[test]
+    fn sqlize_search_table() {
+        let search = SearchTable::new("dz_search", &["name", "lines"]);
+        assert_eq!(
+            search.sqlize(),
+            "CREATE VIRTUAL TABLE IF NOT EXISTS dz_search USING fts5(name, lines);\n"
+        );
+
+        let trigger = Trigger::new(
+            "dz_search_delete",
+            "AFTER DELETE ON dz_nodes",
+            vec!["DELETE FROM dz_search WHERE rowid = old.id".to_string()],
+        );
+        assert_eq!(
+            trigger.sqlize(),
+            concat!(
+                "CREATE TRIGGER IF NOT EXISTS dz_search_delete AFTER DELETE ON dz_nodes\n",
+                "BEGIN\n",
+                "    DELETE FROM dz_search WHERE rowid = old.id;\n",
+                "END;\n"
+            )
+        );
+    }
+
     #[test]
     #[allow(unused)]
     fn sqlize_insert() {
@@ -196,6 +576,106 @@ mod tests {
         );
     }
 
+    #[test]
+    fn sqlize_upsert() {
+        let tab = generate_test_table();
+
+        let row = TestRow {
+            name: "test".to_string(),
+            position: 1,
+        };
+
+        let expected = concat!(
+            "INSERT INTO dz_nodes(name, position)\n",
+            "VALUES('test', 1)\n",
+            "ON CONFLICT(name) DO UPDATE SET position = excluded.position;\n"
+        );
+
+        assert_eq!(tab.sqlize_upsert(&row, "name"), expected);
+    }
+
+    #[test]
+    fn insert_statement() {
+        let mut tab: Table<TestTable> = Table::new("dz_lines");
+        tab.add_column(&Param::new("node", ParamType::Integer));
+        tab.add_column(&Param::new("lines", ParamType::Text));
+
+        let values = [
+            Value::Lookup("(SELECT id FROM dz_nodes WHERE name IS ?)", "a".to_string()),
+            Value::Text("it's".to_string()),
+        ];
+
+        let expected = concat!(
+            "INSERT INTO dz_lines(node, lines)\n",
+            "VALUES((SELECT id FROM dz_nodes WHERE name IS ?), ?);\n"
+        );
+
+        assert_eq!(tab.insert_statement(&values), expected);
+    }
+
+    #[test]
+    fn sqlize_value() {
+        assert_eq!(Value::Null.sqlize(), "NULL");
+        assert_eq!(Value::Integer(-2).sqlize(), "-2");
+        assert_eq!(Value::Text("it's".to_string()).sqlize(), "'it''s'");
+
+        let lookup = Value::Lookup(
+            "(SELECT id FROM dz_nodes WHERE name IS ?)",
+            "a'b".to_string(),
+        );
+        assert_eq!(
+            lookup.sqlize(),
+            "(SELECT id FROM dz_nodes WHERE name IS 'a''b')"
+        );
+    }
+
+    #[test]
+    fn resolve_ids() {
+        const LOOKUP: &str = "(SELECT id FROM dz_nodes WHERE name IS ?)";
+        let mut ids = Ids::default();
+        ids.insert(LOOKUP, "a", 3);
+
+        let mut lines: Table<TestTable> = Table::new("dz_lines");
+        lines.add_column(&Param::new("node", ParamType::Integer));
+        lines.add_column(&Param::new("lines", ParamType::Text));
+
+        let mut out: Vec<u8> = vec![];
+        let mut f = WithIds::new(&mut out, &ids);
+        for name in ["a", "b"] {
+            let values = vec![
+                Value::Lookup(LOOKUP, name.to_string()),
+                Value::Text("[]".to_string()),
+            ];
+            f.insert_values(&lines, values);
+        }
+
+        let expected = concat!(
+            "INSERT INTO dz_lines(node, lines)\n",
+            "VALUES(3, '[]');\n",
+            "INSERT INTO dz_lines(node, lines)\n",
+            "VALUES(NULL, '[]');\n",
+        );
+        assert_eq!(String::from_utf8(out).unwrap(), expected);
+    }
+
+    #[test]
+    fn insert_keys() {
+        let mut tab = generate_test_table();
+        tab.insert_keys();
+
+        let values = [
+            Value::Text("a".to_string()),
+            Value::Integer(4),
+            Value::Integer(4),
+        ];
+
+        let expected = concat!(
+            "INSERT INTO dz_nodes(name, id, position)\n",
+            "VALUES('a', 4, 4);\n"
+        );
+        assert_eq!(tab.sqlize_insert_values(&values), expected);
+    }
+
     #[test]
     fn test_escape_quote() {
         let str = "There's a need to escape this string.".to_string();
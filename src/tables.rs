@@ -1,27 +1,35 @@
-use crate::escape_quotes;
-use crate::sqlite::SQLize;
-use crate::DagZet;
-use crate::FileRange;
-use crate::FlashCard;
-use crate::Param;
-use crate::{ParamType, Row, Table};
+use crate::dagzet::DagZet;
+use crate::dagzet::FileRange;
+use crate::dagzet::FlashCard;
+use crate::dagzet::SourceRef;
+use crate::sqlite::{
+    Ids, Output, Param, ParamType, Row, SQLize, SearchTable, Table, Trigger, Value, View,
+};
+use std::collections::BTreeMap;
 use std::io;
 use std::ops::Not;
 
 pub trait Generate {
-    fn generate(&self, dz: &DagZet, f: &mut impl io::Write);
+    fn generate(&self, dz: &DagZet, f: &mut impl Output);
 }
 
 pub struct NodesTable;
 
 pub struct NodesRow {
     name: String,
+    /// Only given when the table inserts its keys
+    id: Option<u32>,
     position: u32,
 }
 
 impl<NodesTable> Row<NodesTable> for NodesRow {
-    fn sqlize_values(&self) -> String {
-        format!("'{}', {}", self.name, self.position)
+    fn values(&self) -> Vec<Value> {
+        let mut values = vec![Value::Text(self.name.clone())];
+        if let Some(id) = self.id {
+            values.push(Value::Integer(id as i64));
+        }
+        values.push(Value::Integer(self.position as i64));
+        values
     }
 }
 
@@ -36,15 +44,34 @@ impl Default for Table<NodesTable> {
 }
 
 impl Generate for Table<NodesTable> {
-    fn generate(&self, dz: &DagZet, f: &mut impl io::Write) {
-        let _ = f.write_all(&self.sqlize().into_bytes());
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
 
-        for (name, id) in dz.nodes.iter() {
+        for (name, id) in dz.nodelist.iter().zip(1..) {
             let row = NodesRow {
                 name: name.to_string(),
-                position: *id,
+                id: self.inserts_keys().then_some(id),
+                position: id,
+            };
+            f.insert(self, &row);
+        }
+    }
+}
+
+impl Table<NodesTable> {
+    /// Insert nodes start..end (1-indexed, end exclusive),
+    /// leaving any that already exist in place so that their
+    /// IDs don't change. Only the position is updated.
+    pub fn generate_upserts(&self, dz: &DagZet, f: &mut impl io::Write, start: usize, end: usize) {
+        f.create(self);
+
+        for id in start..end {
+            let row = NodesRow {
+                name: dz.nodelist[id - 1].to_string(),
+                id: None,
+                position: id as u32,
             };
-            let str = self.sqlize_insert(&row).to_string();
+            let str = self.sqlize_upsert(&row, "name").to_string();
             let _ = f.write_all(&str.into_bytes());
         }
     }
@@ -58,51 +85,68 @@ pub struct ConnectionsRow<'a> {
 }
 
 impl<ConnectionsTable> Row<ConnectionsTable> for ConnectionsRow<'_> {
-    fn sqlize_values(&self) -> String {
-        format!("{}, {}", name_lookup(self.left), name_lookup(self.right))
+    fn values(&self) -> Vec<Value> {
+        vec![node(self.left), node(self.right)]
     }
 }
 
 impl Default for Table<ConnectionsTable> {
     fn default() -> Self {
         let mut con: Table<ConnectionsTable> = Table::new("dz_connections");
-        con.add_column(&Param::new("left", ParamType::IntegerNotNull));
-        con.add_column(&Param::new("right", ParamType::IntegerNotNull));
+        add_node_column(&mut con, "left", ParamType::IntegerNotNull);
+        add_node_column(&mut con, "right", ParamType::IntegerNotNull);
+        con.add_unique(&["left", "right"]);
         con
     }
 }
 
 impl Generate for Table<ConnectionsTable> {
-    fn generate(&self, dz: &DagZet, f: &mut impl io::Write) {
-        let _ = f.write_all(&self.sqlize().into_bytes());
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
 
         for con in &dz.connections {
             let row = ConnectionsRow {
                 left: &con[0],
                 right: &con[1],
             };
-            let str = self.sqlize_insert(&row).to_string();
-            let _ = f.write_all(&str.into_bytes());
+            f.insert(self, &row);
         }
     }
 }
 
-fn name_lookup(name: &String) -> String {
-    format!("(SELECT id from dz_nodes WHERE name IS '{name}' LIMIT 1)")
+/// Add a column holding a node ID, which refers to
+/// dz_nodes and gets an index
+fn add_node_column<T>(table: &mut Table<T>, name: &str, ptype: ParamType) {
+    table.add_column(&Param::new(name, ptype).references("dz_nodes", "id"));
+    table.add_index(&[name]);
+}
+
+const NODE_LOOKUP: &str = "(SELECT id from dz_nodes WHERE name IS ? LIMIT 1)";
+
+/// A node, looked up by name
+pub(crate) fn node(name: &str) -> Value {
+    Value::Lookup(NODE_LOOKUP, name.to_string())
+}
+
+/// The ID of every node, for writing literal IDs
+/// instead of lookups
+pub fn node_ids(dz: &DagZet) -> Ids {
+    let mut ids = Ids::default();
+    for (name, id) in dz.nodelist.iter().zip(1..) {
+        ids.insert(NODE_LOOKUP, name, id);
+    }
+    ids
 }
 
-fn lines_to_json(lines: &[String]) -> String {
+/// Encode lines as a JSON array of strings
+fn lines_json(lines: &[String]) -> String {
     let mut jsonstr = "[".to_string();
 
     let vals = lines
         .iter()
         .map(|x| {
             let mut s = "\"".to_string();
-            s.push_str(
-                &x.replace('\'', "''")
-                    .replace('\\', "\\\\")
-                    .replace('\"', "\\\""),
-            );
+            s.push_str(&x.replace('\\', "\\\\").replace('\"', "\\\""));
             s.push('"');
             s
         })
@@ -122,35 +166,30 @@ pub struct LinesRow<'a> {
 }
 
 impl<LinesTable> Row<LinesTable> for LinesRow<'_> {
-    fn sqlize_values(&self) -> String {
-        format!(
-            "{}, '{}'",
-            name_lookup(&self.node),
-            lines_to_json(self.lines)
-        )
+    fn values(&self) -> Vec<Value> {
+        vec![node(&self.node), Value::Text(lines_json(self.lines))]
     }
 }
 
 impl Default for Table<LinesTable> {
     fn default() -> Self {
         let mut con: Table<LinesTable> = Table::new("dz_lines");
-        con.add_column(&Param::new("node", ParamType::Integer));
+        add_node_column(&mut con, "node", ParamType::Integer);
         con.add_column(&Param::new("lines", ParamType::Text));
         con
     }
 }
 
 impl Generate for Table<LinesTable> {
-    fn generate(&self, dz: &DagZet, f: &mut impl io::Write) {
-        let _ = f.write_all(&self.sqlize().into_bytes());
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
 
         for (key, val) in &dz.lines {
             let row = LinesRow {
                 node: dz.nodelist[*key as usize - 1].to_string(),
                 lines: val,
             };
-            let str = self.sqlize_insert(&row).to_string();
-            let _ = f.write_all(&str.into_bytes());
+            f.insert(self, &row);
         }
     }
 }
@@ -163,8 +202,11 @@ pub struct GraphRemarksRow<'a> {
 }
 
 impl<GraphRemarksTable> Row<GraphRemarksTable> for GraphRemarksRow<'_> {
-    fn sqlize_values(&self) -> String {
-        format!("'{}', '{}'", self.namespace, lines_to_json(self.remarks))
+    fn values(&self) -> Vec<Value> {
+        vec![
+            Value::Text(self.namespace.clone()),
+            Value::Text(lines_json(self.remarks)),
+        ]
     }
 }
 
@@ -173,21 +215,21 @@ impl Default for Table<GraphRemarksTable> {
         let mut con: Table<GraphRemarksTable> = Table::new("dz_graph_remarks");
         con.add_column(&Param::new("namespace", ParamType::Text));
         con.add_column(&Param::new("remarks", ParamType::Text));
+        con.add_index(&["namespace"]);
         con
     }
 }
 
 impl Generate for Table<GraphRemarksTable> {
-    fn generate(&self, dz: &DagZet, f: &mut impl io::Write) {
-        let _ = f.write_all(&self.sqlize().into_bytes());
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
 
         for (key, val) in &dz.graph_remarks {
             let row = GraphRemarksRow {
                 namespace: key.to_string(),
                 remarks: val,
             };
-            let str = self.sqlize_insert(&row).to_string();
-            let _ = f.write_all(&str.into_bytes());
+            f.insert(self, &row);
         }
     }
 }
@@ -201,29 +243,28 @@ pub struct ConnectionRemarksRow<'a> {
 }
 
 impl<ConnectionRemarksTable> Row<ConnectionRemarksTable> for ConnectionRemarksRow<'_> {
-    fn sqlize_values(&self) -> String {
-        format!(
-            "{}, {}, '{}'",
-            name_lookup(self.left),
-            name_lookup(self.right),
-            lines_to_json(self.remarks)
-        )
+    fn values(&self) -> Vec<Value> {
+        vec![
+            node(self.left),
+            node(self.right),
+            Value::Text(lines_json(self.remarks)),
+        ]
     }
 }
 
 impl Default for Table<ConnectionRemarksTable> {
     fn default() -> Self {
         let mut con: Table<ConnectionRemarksTable> = Table::new("dz_connection_remarks");
-        con.add_column(&Param::new("left", ParamType::Integer));
-        con.add_column(&Param::new("right", ParamType::Integer));
+        add_node_column(&mut con, "left", ParamType::Integer);
+        add_node_column(&mut con, "right", ParamType::Integer);
         con.add_column(&Param::new("remarks", ParamType::Text));
         con
     }
 }
 
 impl Generate for Table<ConnectionRemarksTable> {
-    fn generate(&self, dz: &DagZet, f: &mut impl io::Write) {
-        let _ = f.write_all(&self.sqlize().into_bytes());
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
 
         for (key, val) in &dz.connection_remarks {
             let co = &dz.connections[*key];
@@ -232,8 +273,7 @@ impl Generate for Table<ConnectionRemarksTable> {
                 right: &co[1],
                 remarks: val,
             };
-            let str = self.sqlize_insert(&row).to_string();
-            let _ = f.write_all(&str.into_bytes());
+            f.insert(self, &row);
         }
     }
 }
@@ -246,35 +286,30 @@ pub struct NodeRemarksRow<'a> {
 }
 
 impl<NodeRemarksTable> Row<NodeRemarksTable> for NodeRemarksRow<'_> {
-    fn sqlize_values(&self) -> String {
-        format!(
-            "{}, '{}'",
-            name_lookup(self.node),
-            lines_to_json(self.remarks)
-        )
+    fn values(&self) -> Vec<Value> {
+        vec![node(self.node), Value::Text(lines_json(self.remarks))]
     }
 }
 
 impl Default for Table<NodeRemarksTable> {
     fn default() -> Self {
         let mut con: Table<NodeRemarksTable> = Table::new("dz_remarks");
-        con.add_column(&Param::new("node", ParamType::Integer));
+        add_node_column(&mut con, "node", ParamType::Integer);
         con.add_column(&Param::new("remarks", ParamType::Text));
         con
     }
 }
 
 impl Generate for Table<NodeRemarksTable> {
-    fn generate(&self, dz: &DagZet, f: &mut impl io::Write) {
-        let _ = f.write_all(&self.sqlize().into_bytes());
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
 
         for (key, val) in &dz.node_remarks {
             let row = NodeRemarksRow {
                 node: &dz.nodelist[*key as usize - 1],
                 remarks: val,
             };
-            let str = self.sqlize_insert(&row).to_string();
-            let _ = f.write_all(&str.into_bytes());
+            f.insert(self, &row);
         }
     }
 }
@@ -287,21 +322,20 @@ pub struct FileRangesRow<'a> {
 }
 
 impl<FileRangesTable> Row<FileRangesTable> for FileRangesRow<'_> {
-    fn sqlize_values(&self) -> String {
-        format!(
-            "{}, '{}', {}, {}",
-            name_lookup(self.node),
-            self.file_range.filename,
-            self.file_range.start,
-            self.file_range.end
-        )
+    fn values(&self) -> Vec<Value> {
+        vec![
+            node(self.node),
+            Value::Text(self.file_range.filename.clone()),
+            Value::Integer(self.file_range.start as i64),
+            Value::Integer(self.file_range.end as i64),
+        ]
     }
 }
 
 impl Default for Table<FileRangesTable> {
     fn default() -> Self {
         let mut con: Table<FileRangesTable> = Table::new("dz_file_ranges");
-        con.add_column(&Param::new("node", ParamType::Integer));
+        add_node_column(&mut con, "node", ParamType::Integer);
         con.add_column(&Param::new("filename", ParamType::Text));
         con.add_column(&Param::new("start", ParamType::Integer));
         con.add_column(&Param::new("end", ParamType::Integer));
@@ -310,16 +344,15 @@ impl Default for Table<FileRangesTable> {
 }
 
 impl Generate for Table<FileRangesTable> {
-    fn generate(&self, dz: &DagZet, f: &mut impl io::Write) {
-        let _ = f.write_all(&self.sqlize().into_bytes());
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
 
         for (key, val) in &dz.file_ranges {
             let row = FileRangesRow {
                 node: &dz.nodelist[*key as usize - 1],
                 file_range: val,
             };
-            let str = self.sqlize_insert(&row).to_string();
-            let _ = f.write_all(&str.into_bytes());
+            f.insert(self, &row);
         }
     }
 }
@@ -332,35 +365,30 @@ pub struct HyperlinksRow<'a> {
 }
 
 impl<HyperlinksTable> Row<HyperlinksTable> for HyperlinksRow<'_> {
-    fn sqlize_values(&self) -> String {
-        format!(
-            "{}, '{}'",
-            name_lookup(self.node),
-            escape_quotes(self.hyperlink)
-        )
+    fn values(&self) -> Vec<Value> {
+        vec![node(self.node), Value::Text(self.hyperlink.clone())]
     }
 }
 
 impl Default for Table<HyperlinksTable> {
     fn default() -> Self {
         let mut con: Table<HyperlinksTable> = Table::new("dz_hyperlinks");
-        con.add_column(&Param::new("node", ParamType::Integer));
+        add_node_column(&mut con, "node", ParamType::Integer);
         con.add_column(&Param::new("hyperlink", ParamType::Text));
         con
     }
 }
 
 impl Generate for Table<HyperlinksTable> {
-    fn generate(&self, dz: &DagZet, f: &mut impl io::Write) {
-        let _ = f.write_all(&self.sqlize().into_bytes());
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
 
         for (key, val) in &dz.hyperlinks {
             let row = HyperlinksRow {
                 node: &dz.nodelist[*key as usize - 1],
                 hyperlink: val,
             };
-            let str = self.sqlize_insert(&row).to_string();
-            let _ = f.write_all(&str.into_bytes());
+            f.insert(self, &row);
         }
     }
 }
@@ -373,35 +401,30 @@ pub struct TODORow<'a> {
 }
 
 impl<TODOTable> Row<TODOTable> for TODORow<'_> {
-    fn sqlize_values(&self) -> String {
-        format!(
-            "{}, '{}'",
-            name_lookup(self.node),
-            escape_quotes(self.todo_item)
-        )
+    fn values(&self) -> Vec<Value> {
+        vec![node(self.node), Value::Text(self.todo_item.clone())]
     }
 }
 
 impl Default for Table<TODOTable> {
     fn default() -> Self {
         let mut con: Table<TODOTable> = Table::new("dz_todo");
-        con.add_column(&Param::new("node", ParamType::Integer));
+        add_node_column(&mut con, "node", ParamType::Integer);
         con.add_column(&Param::new("task", ParamType::Text));
         con
     }
 }
 
 impl Generate for Table<TODOTable> {
-    fn generate(&self, dz: &DagZet, f: &mut impl io::Write) {
-        let _ = f.write_all(&self.sqlize().into_bytes());
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
 
         for (key, val) in &dz.todos {
             let row = TODORow {
                 node: &dz.nodelist[*key as usize - 1],
                 todo_item: val,
             };
-            let str = self.sqlize_insert(&row).to_string();
-            let _ = f.write_all(&str.into_bytes());
+            f.insert(self, &row);
         }
     }
 }
@@ -414,23 +437,25 @@ pub struct TagsRow<'a> {
 }
 
 impl<TagsTable> Row<TagsTable> for TagsRow<'_> {
-    fn sqlize_values(&self) -> String {
-        format!("{}, '{}'", name_lookup(self.node), self.tag)
+    fn values(&self) -> Vec<Value> {
+        vec![node(self.node), Value::Text(self.tag.clone())]
     }
 }
 
 impl Default for Table<TagsTable> {
     fn default() -> Self {
         let mut con: Table<TagsTable> = Table::new("dz_tags");
-        con.add_column(&Param::new("node", ParamType::Integer));
-        con.add_column(&Param::new("tag", ParamType::Text));
+        add_node_column(&mut con, "node", ParamType::Integer);
+        con.add_column(&Param::new("tag", ParamType::TextNotNull));
+        con.add_unique(&["node", "tag"]);
+        con.add_index(&["tag"]);
         con
     }
 }
 
 impl Generate for Table<TagsTable> {
-    fn generate(&self, dz: &DagZet, f: &mut impl io::Write) {
-        let _ = f.write_all(&self.sqlize().into_bytes());
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
 
         for (nodeid, tags) in &dz.tags {
             // Insert tags as (node,tag) pairs
@@ -439,8 +464,7 @@ impl Generate for Table<TagsTable> {
                     node: &dz.nodelist[*nodeid as usize - 1],
                     tag,
                 };
-                let str = self.sqlize_insert(&row).to_string();
-                let _ = f.write_all(&str.into_bytes());
+                f.insert(self, &row);
             }
         }
     }
@@ -454,20 +478,19 @@ pub struct FlashCardsRow<'a> {
 }
 
 impl<FlashCardsTable> Row<FlashCardsTable> for FlashCardsRow<'_> {
-    fn sqlize_values(&self) -> String {
-        format!(
-            "{}, '{}', '{}'",
-            name_lookup(&self.node),
-            lines_to_json(&self.card.front),
-            lines_to_json(&self.card.back),
-        )
+    fn values(&self) -> Vec<Value> {
+        vec![
+            node(&self.node),
+            Value::Text(lines_json(&self.card.front)),
+            Value::Text(lines_json(&self.card.back)),
+        ]
     }
 }
 
 impl Default for Table<FlashCardsTable> {
     fn default() -> Self {
         let mut con: Table<FlashCardsTable> = Table::new("dz_flashcards");
-        con.add_column(&Param::new("node", ParamType::Integer));
+        add_node_column(&mut con, "node", ParamType::Integer);
         con.add_column(&Param::new("front", ParamType::Text));
         con.add_column(&Param::new("back", ParamType::Text));
         con
@@ -475,16 +498,15 @@ impl Default for Table<FlashCardsTable> {
 }
 
 impl Generate for Table<FlashCardsTable> {
-    fn generate(&self, dz: &DagZet, f: &mut impl io::Write) {
-        let _ = f.write_all(&self.sqlize().into_bytes());
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
 
         for (key, val) in &dz.flashcards {
             let row = FlashCardsRow {
                 node: dz.nodelist[*key as usize - 1].to_string(),
                 card: val,
             };
-            let str = self.sqlize_insert(&row).to_string();
-            let _ = f.write_all(&str.into_bytes());
+            f.insert(self, &row);
         }
     }
 }
@@ -497,30 +519,29 @@ pub struct ImagesRow<'a> {
 }
 
 impl<ImagesTable> Row<ImagesTable> for ImagesRow<'_> {
-    fn sqlize_values(&self) -> String {
-        format!("{}, '{}'", name_lookup(self.node), self.filename)
+    fn values(&self) -> Vec<Value> {
+        vec![node(self.node), Value::Text(self.filename.clone())]
     }
 }
 
 impl Default for Table<ImagesTable> {
     fn default() -> Self {
         let mut con: Table<ImagesTable> = Table::new("dz_images");
-        con.add_column(&Param::new("node", ParamType::Integer));
+        add_node_column(&mut con, "node", ParamType::Integer);
         con.add_column(&Param::new("image", ParamType::Text));
         con
     }
 }
 
 impl Generate for Table<ImagesTable> {
-    fn generate(&self, dz: &DagZet, f: &mut impl io::Write) {
-        let _ = f.write_all(&self.sqlize().into_bytes());
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
         for (key, val) in &dz.images {
             let row = ImagesRow {
                 node: &dz.nodelist[*key as usize - 1].to_string(),
                 filename: val,
             };
-            let str = self.sqlize_insert(&row).to_string();
-            let _ = f.write_all(&str.into_bytes());
+            f.insert(self, &row);
         }
     }
 }
@@ -533,31 +554,102 @@ pub struct AudioRow<'a> {
 }
 
 impl<AudioTable> Row<AudioTable> for AudioRow<'_> {
-    fn sqlize_values(&self) -> String {
-        format!("{}, '{}'", name_lookup(self.node), self.filename)
+    fn values(&self) -> Vec<Value> {
+        vec![node(self.node), Value::Text(self.filename.clone())]
     }
 }
 
 impl Default for Table<AudioTable> {
     fn default() -> Self {
         let mut con: Table<AudioTable> = Table::new("dz_audio");
-        con.add_column(&Param::new("node", ParamType::Integer));
+        add_node_column(&mut con, "node", ParamType::Integer);
         con.add_column(&Param::new("audio", ParamType::Text));
         con
     }
 }
 
 impl Generate for Table<AudioTable> {
-    fn generate(&self, dz: &DagZet, f: &mut impl io::Write) {
-        let _ = f.write_all(&self.sqlize().into_bytes());
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
         // TODO: replace images with audio table
         for (key, val) in &dz.images {
             let row = AudioRow {
                 node: &dz.nodelist[*key as usize - 1].to_string(),
                 filename: val,
             };
-            let str = self.sqlize_insert(&row).to_string();
-            let _ = f.write_all(&str.into_bytes());
+            f.insert(self, &row);
+        }
+    }
+}
+
+pub struct EquationsTable;
+
+pub struct EquationsRow<'a> {
+    node: &'a String,
+    equation: &'a String,
+}
+
+impl<EquationsTable> Row<EquationsTable> for EquationsRow<'_> {
+    fn values(&self) -> Vec<Value> {
+        vec![node(self.node), Value::Text(self.equation.clone())]
+    }
+}
+
+impl Default for Table<EquationsTable> {
+    fn default() -> Self {
+        let mut con: Table<EquationsTable> = Table::new("dz_equations");
+        add_node_column(&mut con, "node", ParamType::Integer);
+        con.add_column(&Param::new("equation", ParamType::Text));
+        con
+    }
+}
+
+impl Generate for Table<EquationsTable> {
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
+        for (key, equations) in &dz.equations {
+            for equation in equations {
+                let row = EquationsRow {
+                    node: &dz.nodelist[*key as usize - 1],
+                    equation,
+                };
+                f.insert(self, &row);
+            }
+        }
+    }
+}
+
+pub struct PagesTable;
+
+pub struct PagesRow<'a> {
+    node: &'a String,
+    page: &'a String,
+}
+
+impl<PagesTable> Row<PagesTable> for PagesRow<'_> {
+    fn values(&self) -> Vec<Value> {
+        vec![node(self.node), Value::Text(self.page.clone())]
+    }
+}
+
+impl Default for Table<PagesTable> {
+    fn default() -> Self {
+        let mut con: Table<PagesTable> = Table::new("dz_pages");
+        add_node_column(&mut con, "node", ParamType::Integer);
+        con.add_column(&Param::new("page", ParamType::Text));
+        con
+    }
+}
+
+impl Generate for Table<PagesTable> {
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
+        for (key, val) in &dz.pages {
+            let row = PagesRow {
+                node: &dz.nodelist[*key as usize - 1],
+                page: val,
+            };
+            f.insert(self, &row);
         }
     }
 }
@@ -571,20 +663,19 @@ pub struct NodeRefsRow<'a> {
 }
 
 impl<NodeRefsTable> Row<NodeRefsTable> for NodeRefsRow<'_> {
-    fn sqlize_values(&self) -> String {
-        format!(
-            "{}, '{}', {}",
-            name_lookup(self.node),
-            self.filename,
-            self.linum
-        )
+    fn values(&self) -> Vec<Value> {
+        vec![
+            node(self.node),
+            Value::Text(self.filename.clone()),
+            Value::Integer(self.linum as i64),
+        ]
     }
 }
 
 impl Default for Table<NodeRefsTable> {
     fn default() -> Self {
         let mut con: Table<NodeRefsTable> = Table::new("dz_noderefs");
-        con.add_column(&Param::new("node", ParamType::Integer));
+        add_node_column(&mut con, "node", ParamType::Integer);
         con.add_column(&Param::new("filename", ParamType::Text));
         con.add_column(&Param::new("linum", ParamType::Integer));
         con
@@ -592,7 +683,7 @@ impl Default for Table<NodeRefsTable> {
 }
 
 impl Generate for Table<NodeRefsTable> {
-    fn generate(&self, dz: &DagZet, f: &mut impl io::Write) {
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
         self.generate_with_filename(dz, f, None, 0, 0);
     }
 }
@@ -601,12 +692,12 @@ impl Table<NodeRefsTable> {
     pub fn generate_with_filename(
         &self,
         dz: &DagZet,
-        f: &mut impl io::Write,
+        f: &mut impl Output,
         filename: Option<&String>,
         start: usize,
         end: usize,
     ) {
-        let _ = f.write_all(&self.sqlize().into_bytes());
+        f.create(self);
         let emptystring = "".to_string();
         let filename = match filename {
             Some(x) => x,
@@ -622,8 +713,7 @@ impl Table<NodeRefsTable> {
                 filename,
                 linum: *val,
             };
-            let str = self.sqlize_insert(&row).to_string();
-            let _ = f.write_all(&str.into_bytes());
+            f.insert(self, &row);
         }
     }
 }
@@ -637,32 +727,29 @@ pub struct AttributesRow<'a> {
 }
 
 impl<AttributesTable> Row<AttributesTable> for AttributesRow<'_> {
-    fn sqlize_values(&self) -> String {
-        format!(
-            "{}, '{}', '{}'",
-            name_lookup(self.node),
-            self.key,
-            match self.value {
-                Some(x) => x,
-                None => "",
-            }
-        )
+    fn values(&self) -> Vec<Value> {
+        vec![
+            node(self.node),
+            Value::Text(self.key.clone()),
+            Value::Text(self.value.clone().unwrap_or_default()),
+        ]
     }
 }
 
 impl Default for Table<AttributesTable> {
     fn default() -> Self {
         let mut con: Table<AttributesTable> = Table::new("dz_attributes");
-        con.add_column(&Param::new("node", ParamType::Integer));
-        con.add_column(&Param::new("key", ParamType::Text));
+        add_node_column(&mut con, "node", ParamType::Integer);
+        con.add_column(&Param::new("key", ParamType::TextNotNull));
         con.add_column(&Param::new("value", ParamType::Text));
+        con.add_unique(&["node", "key"]);
         con
     }
 }
 
 impl Generate for Table<AttributesTable> {
-    fn generate(&self, dz: &DagZet, f: &mut impl io::Write) {
-        let _ = f.write_all(&self.sqlize().into_bytes());
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
         for (node_id, attributes) in &dz.attr {
             for (key, value) in attributes {
                 let row = AttributesRow {
@@ -670,9 +757,465 @@ impl Generate for Table<AttributesTable> {
                     key,
                     value,
                 };
-                let str = self.sqlize_insert(&row).to_string();
-                let _ = f.write_all(&str.into_bytes());
+                f.insert(self, &row);
+            }
+        }
+    }
+}
+
+pub struct AliasesTable;
+
+pub struct AliasesRow<'a> {
+    alias: &'a String,
+    path: &'a String,
+    filename: &'a str,
+}
+
+impl<AliasesTable> Row<AliasesTable> for AliasesRow<'_> {
+    fn values(&self) -> Vec<Value> {
+        vec![
+            Value::Text(self.alias.clone()),
+            Value::Text(self.path.clone()),
+            Value::Text(self.filename.to_string()),
+        ]
+    }
+}
+
+impl Default for Table<AliasesTable> {
+    fn default() -> Self {
+        let mut con: Table<AliasesTable> = Table::new("dz_aliases");
+        con.add_column(&Param::new("alias", ParamType::Text));
+        con.add_column(&Param::new("path", ParamType::Text));
+        con.add_column(&Param::new("filename", ParamType::Text));
+        con.add_index(&["filename"]);
+        con
+    }
+}
+
+impl Generate for Table<AliasesTable> {
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
+        for alias in &dz.alias_list {
+            let filename = match alias.sref.file {
+                Some(file) => &dz.files[file],
+                None => "",
+            };
+            let row = AliasesRow {
+                alias: &alias.name,
+                path: &alias.path,
+                filename,
+            };
+            f.insert(self, &row);
+        }
+    }
+}
+
+/// The file a source reference points to, or an empty
+/// string for stdin
+fn ref_filename<'a>(dz: &'a DagZet, sref: &SourceRef) -> &'a str {
+    match sref.file {
+        Some(file) => &dz.files[file],
+        None => "",
+    }
+}
+
+pub struct ConnectionRefsTable;
+
+pub struct ConnectionRefsRow<'a> {
+    left: &'a String,
+    right: &'a String,
+    filename: &'a str,
+    linum: u32,
+}
+
+impl<ConnectionRefsTable> Row<ConnectionRefsTable> for ConnectionRefsRow<'_> {
+    fn values(&self) -> Vec<Value> {
+        vec![
+            node(self.left),
+            node(self.right),
+            Value::Text(self.filename.to_string()),
+            Value::Integer(self.linum as i64),
+        ]
+    }
+}
+
+impl Default for Table<ConnectionRefsTable> {
+    fn default() -> Self {
+        let mut con: Table<ConnectionRefsTable> = Table::new("dz_connection_refs");
+        add_node_column(&mut con, "left", ParamType::Integer);
+        add_node_column(&mut con, "right", ParamType::Integer);
+        con.add_column(&Param::new("filename", ParamType::Text));
+        con.add_column(&Param::new("linum", ParamType::Integer));
+        con
+    }
+}
+
+impl Generate for Table<ConnectionRefsTable> {
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
+        for (co, sref) in dz.connections.iter().zip(&dz.connection_refs) {
+            let row = ConnectionRefsRow {
+                left: &co[0],
+                right: &co[1],
+                filename: ref_filename(dz, sref),
+                linum: sref.linum,
+            };
+            f.insert(self, &row);
+        }
+    }
+}
+
+pub struct ConnectionRemarkRefsTable;
+
+pub struct ConnectionRemarkRefsRow<'a> {
+    left: &'a String,
+    right: &'a String,
+    position: usize,
+    filename: &'a str,
+    linum: u32,
+}
+
+impl<ConnectionRemarkRefsTable> Row<ConnectionRemarkRefsTable> for ConnectionRemarkRefsRow<'_> {
+    fn values(&self) -> Vec<Value> {
+        vec![
+            node(self.left),
+            node(self.right),
+            Value::Integer(self.position as i64),
+            Value::Text(self.filename.to_string()),
+            Value::Integer(self.linum as i64),
+        ]
+    }
+}
+
+impl Default for Table<ConnectionRemarkRefsTable> {
+    fn default() -> Self {
+        let mut con: Table<ConnectionRemarkRefsTable> = Table::new("dz_connection_remark_refs");
+        add_node_column(&mut con, "left", ParamType::Integer);
+        add_node_column(&mut con, "right", ParamType::Integer);
+        con.add_column(&Param::new("position", ParamType::Integer));
+        con.add_column(&Param::new("filename", ParamType::Text));
+        con.add_column(&Param::new("linum", ParamType::Integer));
+        con
+    }
+}
+
+impl Generate for Table<ConnectionRemarkRefsTable> {
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        f.create(self);
+        for (key, srefs) in &dz.connection_remark_refs {
+            let co = &dz.connections[*key];
+            for (position, sref) in srefs.iter().enumerate() {
+                let row = ConnectionRemarkRefsRow {
+                    left: &co[0],
+                    right: &co[1],
+                    position,
+                    filename: ref_filename(dz, sref),
+                    linum: sref.linum,
+                };
+                f.insert(self, &row);
             }
         }
     }
 }
+
+pub struct LineRefsTable;
+
+/// Where a line (or remark) was written. The position is the
+/// index into the JSON array of lines stored for the node.
+pub struct LineRefsRow<'a> {
+    node: &'a String,
+    position: usize,
+    filename: &'a str,
+    linum: u32,
+}
+
+impl<T> Row<T> for LineRefsRow<'_> {
+    fn values(&self) -> Vec<Value> {
+        vec![
+            node(self.node),
+            Value::Integer(self.position as i64),
+            Value::Text(self.filename.to_string()),
+            Value::Integer(self.linum as i64),
+        ]
+    }
+}
+
+fn line_refs_table<T>(name: &str) -> Table<T> {
+    let mut con: Table<T> = Table::new(name);
+    add_node_column(&mut con, "node", ParamType::Integer);
+    con.add_column(&Param::new("position", ParamType::Integer));
+    con.add_column(&Param::new("filename", ParamType::Text));
+    con.add_column(&Param::new("linum", ParamType::Integer));
+    con
+}
+
+fn generate_line_refs<T>(
+    table: &Table<T>,
+    dz: &DagZet,
+    refs: &BTreeMap<u32, Vec<SourceRef>>,
+    f: &mut impl Output,
+) {
+    f.create(table);
+    for (key, srefs) in refs {
+        for (position, sref) in srefs.iter().enumerate() {
+            let row = LineRefsRow {
+                node: &dz.nodelist[*key as usize - 1],
+                position,
+                filename: ref_filename(dz, sref),
+                linum: sref.linum,
+            };
+            f.insert(table, &row);
+        }
+    }
+}
+
+impl Default for Table<LineRefsTable> {
+    fn default() -> Self {
+        line_refs_table("dz_line_refs")
+    }
+}
+
+impl Generate for Table<LineRefsTable> {
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        generate_line_refs(self, dz, &dz.line_refs, f);
+    }
+}
+
+pub struct NodeRemarkRefsTable;
+
+impl Default for Table<NodeRemarkRefsTable> {
+    fn default() -> Self {
+        line_refs_table("dz_remark_refs")
+    }
+}
+
+impl Generate for Table<NodeRemarkRefsTable> {
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        generate_line_refs(self, dz, &dz.node_remark_refs, f);
+    }
+}
+
+pub struct TagRefsTable;
+
+/// Where a tag or an attribute was set, identified by
+/// the node and the tag name (or attribute key)
+pub struct KeyRefsRow<'a> {
+    node: &'a String,
+    key: &'a String,
+    filename: &'a str,
+    linum: u32,
+}
+
+impl<T> Row<T> for KeyRefsRow<'_> {
+    fn values(&self) -> Vec<Value> {
+        vec![
+            node(self.node),
+            Value::Text(self.key.clone()),
+            Value::Text(self.filename.to_string()),
+            Value::Integer(self.linum as i64),
+        ]
+    }
+}
+
+fn generate_key_refs<T>(
+    table: &Table<T>,
+    dz: &DagZet,
+    refs: &BTreeMap<u32, BTreeMap<String, SourceRef>>,
+    f: &mut impl Output,
+) {
+    f.create(table);
+    for (key, srefs) in refs {
+        for (name, sref) in srefs {
+            let row = KeyRefsRow {
+                node: &dz.nodelist[*key as usize - 1],
+                key: name,
+                filename: ref_filename(dz, sref),
+                linum: sref.linum,
+            };
+            f.insert(table, &row);
+        }
+    }
+}
+
+impl Default for Table<TagRefsTable> {
+    fn default() -> Self {
+        let mut con: Table<TagRefsTable> = Table::new("dz_tag_refs");
+        add_node_column(&mut con, "node", ParamType::Integer);
+        con.add_column(&Param::new("tag", ParamType::Text));
+        con.add_column(&Param::new("filename", ParamType::Text));
+        con.add_column(&Param::new("linum", ParamType::Integer));
+        con
+    }
+}
+
+impl Generate for Table<TagRefsTable> {
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        generate_key_refs(self, dz, &dz.tag_refs, f);
+    }
+}
+
+pub struct AttributeRefsTable;
+
+impl Default for Table<AttributeRefsTable> {
+    fn default() -> Self {
+        let mut con: Table<AttributeRefsTable> = Table::new("dz_attribute_refs");
+        add_node_column(&mut con, "node", ParamType::Integer);
+        con.add_column(&Param::new("key", ParamType::Text));
+        con.add_column(&Param::new("filename", ParamType::Text));
+        con.add_column(&Param::new("linum", ParamType::Integer));
+        con
+    }
+}
+
+impl Generate for Table<AttributeRefsTable> {
+    fn generate(&self, dz: &DagZet, f: &mut impl Output) {
+        generate_key_refs(self, dz, &dz.attr_refs, f);
+    }
+}
+
+/// Views that join the node tables with node names, so that
+/// common queries don't need to look up IDs by hand
+pub fn generate_views(f: &mut impl Output) {
+    let views = [
+        View::new(
+            "dz_edges",
+            concat!(
+                "SELECT left, l.name AS left_name, right, r.name AS right_name\n",
+                "FROM dz_connections\n",
+                "INNER JOIN dz_nodes AS l ON l.id = left\n",
+                "INNER JOIN dz_nodes AS r ON r.id = right"
+            ),
+        ),
+        View::new(
+            "dz_node_lines",
+            concat!(
+                "SELECT node, name, lines FROM dz_lines\n",
+                "INNER JOIN dz_nodes ON dz_nodes.id = node"
+            ),
+        ),
+        View::new(
+            "dz_node_tags",
+            concat!(
+                "SELECT node, name, tag FROM dz_tags\n",
+                "INNER JOIN dz_nodes ON dz_nodes.id = node"
+            ),
+        ),
+        View::new(
+            "dz_node_sources",
+            concat!(
+                "SELECT node, name, filename, linum FROM dz_noderefs\n",
+                "INNER JOIN dz_nodes ON dz_nodes.id = node"
+            ),
+        ),
+    ];
+
+    for view in &views {
+        f.execute(&view.sqlize());
+    }
+}
+
+/// The JSON arrays in a column of one of the node tables,
+/// flattened into text, one entry per line
+fn search_text(table: &str, column: &str, key: &str, id: &str) -> String {
+    format!(
+        "(SELECT group_concat(j.value, char(10)) FROM {table}, json_each({table}.{column}) AS j WHERE {table}.{key} = {id})"
+    )
+}
+
+/// Everything besides the name that dz_search indexes for a
+/// node. Connection remarks go with the left node.
+fn search_columns(id: &str) -> [(&'static str, String); 3] {
+    [
+        ("lines", search_text("dz_lines", "lines", "node", id)),
+        ("remarks", search_text("dz_remarks", "remarks", "node", id)),
+        (
+            "connection_remarks",
+            search_text("dz_connection_remarks", "remarks", "left", id),
+        ),
+    ]
+}
+
+/// Bring the text of a node in dz_search up to date
+fn refresh_search(id: &str) -> String {
+    let sets: Vec<String> = search_columns(id)
+        .iter()
+        .map(|(col, text)| format!("{} = {}", col, text))
+        .collect();
+    format!(
+        "UPDATE dz_search SET {} WHERE rowid = {}",
+        sets.join(", "),
+        id
+    )
+}
+
+/// An FTS5 table of node names, lines, remarks and connection
+/// remarks, with the node ID as its rowid. Triggers keep it in
+/// sync with the node tables, so this should come after they
+/// have been created. Anything already in the tables is
+/// indexed straight away.
+pub fn generate_search(f: &mut impl Output) {
+    f.execute(
+        &SearchTable::new(
+            "dz_search",
+            &["name", "lines", "remarks", "connection_remarks"],
+        )
+        .sqlize(),
+    );
+
+    let mut triggers = vec![
+        Trigger::new(
+            "dz_search_nodes_insert",
+            "AFTER INSERT ON dz_nodes",
+            vec![
+                "INSERT INTO dz_search(rowid, name) VALUES (new.id, new.name)".to_string(),
+                refresh_search("new.id"),
+            ],
+        ),
+        Trigger::new(
+            "dz_search_nodes_delete",
+            "AFTER DELETE ON dz_nodes",
+            vec!["DELETE FROM dz_search WHERE rowid = old.id".to_string()],
+        ),
+        Trigger::new(
+            "dz_search_nodes_update",
+            "AFTER UPDATE OF name ON dz_nodes",
+            vec!["UPDATE dz_search SET name = new.name WHERE rowid = new.id".to_string()],
+        ),
+    ];
+
+    for (table, key) in [
+        ("dz_lines", "node"),
+        ("dz_remarks", "node"),
+        ("dz_connection_remarks", "left"),
+    ] {
+        let new = format!("new.{}", key);
+        let old = format!("old.{}", key);
+        triggers.push(Trigger::new(
+            &format!("dz_search_{}_insert", table),
+            &format!("AFTER INSERT ON {}", table),
+            vec![refresh_search(&new)],
+        ));
+        triggers.push(Trigger::new(
+            &format!("dz_search_{}_delete", table),
+            &format!("AFTER DELETE ON {}", table),
+            vec![refresh_search(&old)],
+        ));
+        triggers.push(Trigger::new(
+            &format!("dz_search_{}_update", table),
+            &format!("AFTER UPDATE ON {}", table),
+            vec![refresh_search(&old), refresh_search(&new)],
+        ));
+    }
+
+    for trigger in &triggers {
+        f.execute(&trigger.sqlize());
+    }
+
+    let (columns, text): (Vec<_>, Vec<_>) = search_columns("dz_nodes.id").into_iter().unzip();
+    f.execute("DELETE FROM dz_search;\n");
+    f.execute(&format!(
+        "INSERT INTO dz_search(rowid, name, {})\nSELECT id, name, {}\nFROM dz_nodes;\n",
+        columns.join(", "),
+        text.join(",\n")
+    ));
+}
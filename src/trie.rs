@@ -1,5 +1,12 @@
 use std::collections::BTreeMap;
 
+#[derive(Debug, PartialEq)]
+pub enum SuffixError {
+    NotFound,
+    /// Every path the suffix could be, sorted
+    Ambiguous(Vec<String>),
+}
+
 #[derive(Default)]
 pub struct TrieNode {
     pub is_end: bool,
@@ -27,7 +34,7 @@ impl Trie {
         node.is_end = true;
     }
 
-    pub fn search(&self, suffix: &str) -> Result<String, &'static str> {
+    pub fn search(&self, suffix: &str) -> Result<String, SuffixError> {
         let mut path: Vec<String> = vec![];
 
         let mut node = &self.root;
@@ -40,12 +47,18 @@ impl Trie {
                     node = n;
                     path.push(word.to_string());
                 }
-                None => return Err("Could not suffix"),
+                None => return Err(SuffixError::NotFound),
             }
         }
 
-        if node.traversed > 1 {
-            return Err("Ambiguous suffix");
+        // paths that end here count too, as the suffix
+        // could be a whole path
+        if node.traversed + node.is_end as u32 > 1 {
+            let mut words: Vec<&str> = suffix.split('/').rev().collect();
+            let mut found = vec![];
+            collect(node, &mut words, &mut found);
+            found.sort();
+            return Err(SuffixError::Ambiguous(found));
         }
 
         while !node.is_end {
@@ -56,6 +69,65 @@ impl Trie {
 
         Ok(path.into_iter().rev().collect::<Vec<_>>().join("/"))
     }
+
+    /// Every path that ends with a suffix that is still being
+    /// typed, where the last word only needs to be the start
+    /// of a word. The paths are in no particular order.
+    pub fn complete(&self, partial: &str) -> Vec<String> {
+        let mut words: Vec<&str> = partial.split('/').collect();
+        let last = words.pop().unwrap_or("");
+        let mut found = vec![];
+
+        'outer: for (key, child) in &self.root.children {
+            if !key.starts_with(last) {
+                continue;
+            }
+
+            let mut node = child;
+            let mut path = vec![key.as_str()];
+
+            for word in words.iter().rev() {
+                match node.children.get(*word) {
+                    Some(n) => {
+                        node = n;
+                        path.push(word);
+                    }
+                    None => continue 'outer,
+                }
+            }
+
+            collect(node, &mut path, &mut found);
+        }
+
+        found
+    }
+
+    /// The shortest suffix that finds a path with search()
+    pub fn shortest_suffix(&self, path: &str) -> String {
+        let words: Vec<&str> = path.split('/').collect();
+        for n in 1..words.len() {
+            let suffix = words[words.len() - n..].join("/");
+            if self.search(&suffix).as_deref() == Ok(path) {
+                return suffix;
+            }
+        }
+        path.to_string()
+    }
+}
+
+/// Every path at or below a node. The path so far is
+/// in reverse order, like the trie.
+fn collect<'a>(node: &'a TrieNode, path: &mut Vec<&'a str>, found: &mut Vec<String>) {
+    if node.is_end {
+        let words: Vec<&str> = path.iter().rev().copied().collect();
+        found.push(words.join("/"));
+    }
+
+    for (key, child) in &node.children {
+        path.push(key);
+        collect(child, path, found);
+        path.pop();
+    }
 }
 
 #[cfg(test)]
@@ -80,6 +152,24 @@ mod tests {
         assert!(res.is_err());
     }
 
+    #[test]
+    fn complete() {
+        let mut root = Trie::default();
+        root.add_path("a/b/cat");
+        root.add_path("f/b/car");
+        root.add_path("f/d/car");
+        root.add_path("x");
+
+        let mut found = root.complete("b/ca");
+        found.sort();
+        assert_eq!(found, ["a/b/cat", "f/b/car"]);
+        assert_eq!(root.complete("").len(), 4);
+        assert!(root.complete("z/ca").is_empty());
+
+        assert_eq!(root.shortest_suffix("a/b/cat"), "cat");
+        assert_eq!(root.shortest_suffix("f/b/car"), "b/car");
+    }
+
     #[test]
     fn amibguous() {
         // match
@@ -92,7 +182,17 @@ mod tests {
 
         // Ambiguous pattern
         let res = root.search("b/c");
-        assert!(res.is_err());
+        assert_eq!(
+            res,
+            Err(SuffixError::Ambiguous(vec![
+                "a/b/c".to_string(),
+                "f/a/b/c".to_string()
+            ]))
+        );
+
+        // A whole path is ambiguous if it is also a suffix
+        let res = root.search("d/e");
+        assert!(matches!(res, Err(SuffixError::Ambiguous(paths)) if paths.len() == 2));
 
         // Unambiguous pattern
         let res = root.search("a/d/e").unwrap();
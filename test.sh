@@ -19,3 +19,5 @@ cargo run test.dz | sqlite3 a.db
 table_count dz_images 1
 table_count dz_audio 1
 table_count dz_attributes 2
+table_count dz_equations 1
+table_count dz_pages 1